@@ -0,0 +1,440 @@
+use std::{env, f64::consts::PI};
+
+use gstreamer as gst;
+use gstreamer_app as gst_app;
+use gstreamer_audio as gst_audio;
+
+use anyhow::Error;
+use gst::prelude::*;
+
+const FORMATS: [gst_audio::AudioFormat; 5] = [
+    gst_audio::AudioFormat::S16le,
+    gst_audio::AudioFormat::S24le,
+    gst_audio::AudioFormat::S32le,
+    gst_audio::AudioFormat::F32le,
+    gst_audio::AudioFormat::F64le,
+];
+const RATES: [u32; 7] = [8_000, 16_000, 22_050, 44_100, 48_000, 96_000, 192_000];
+const CHANNELS: [u32; 3] = [1, 2, 6];
+/// Test tones peak at -6 dBFS
+const AMPLITUDE: f64 = 0.5;
+const SECONDS: f64 = 0.5;
+/// Skipped at the start of the output, the resampler filter is still filling up there
+const SETTLE_SECONDS: f64 = 0.05;
+const DEFAULT_TONE: f64 = 1_000.0;
+/// Harmonics counted for the THD, 2nd to 5th
+const HARMONICS: std::ops::RangeInclusive<u32> = 2..=5;
+/// Tones of the frequency response, only those below 0.45 of the lower rate are played
+const RESPONSE_TONES: [f64; 10] = [
+    20.0, 50.0, 100.0, 500.0, 1_000.0, 5_000.0, 10_000.0, 15_000.0, 20_000.0, 40_000.0,
+];
+
+/// One run of a tone through `audioconvert ! audioresample`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Conversion {
+    pub in_format: gst_audio::AudioFormat,
+    pub in_rate: u32,
+    pub in_channels: u32,
+    pub out_format: gst_audio::AudioFormat,
+    pub out_rate: u32,
+    pub out_channels: u32,
+    /// `quality` of audioresample, 0 to 10
+    pub quality: i32,
+}
+
+impl Default for Conversion {
+    fn default() -> Self {
+        Conversion {
+            in_format: gst_audio::AudioFormat::F32le,
+            in_rate: 48_000,
+            in_channels: 1,
+            out_format: gst_audio::AudioFormat::F32le,
+            out_rate: 48_000,
+            out_channels: 1,
+            quality: 4,
+        }
+    }
+}
+
+fn sample_bytes(format: gst_audio::AudioFormat) -> usize {
+    match format {
+        gst_audio::AudioFormat::S16le => 2,
+        gst_audio::AudioFormat::S24le => 3,
+        gst_audio::AudioFormat::S32le | gst_audio::AudioFormat::F32le => 4,
+        _ => 8,
+    }
+}
+
+/// Samples in -1.0..1.0 to little endian `format`, integers are rounded
+fn encode(format: gst_audio::AudioFormat, samples: &[f64]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(samples.len() * sample_bytes(format));
+    for &sample in samples {
+        match format {
+            gst_audio::AudioFormat::S16le => {
+                bytes.extend(((sample * i16::MAX as f64).round() as i16).to_le_bytes());
+            }
+            gst_audio::AudioFormat::S24le => {
+                let value = (sample * 8_388_607.0).round() as i32;
+                bytes.extend(&value.to_le_bytes()[..3]);
+            }
+            gst_audio::AudioFormat::S32le => {
+                bytes.extend(((sample * i32::MAX as f64).round() as i32).to_le_bytes());
+            }
+            gst_audio::AudioFormat::F32le => bytes.extend((sample as f32).to_le_bytes()),
+            _ => bytes.extend(sample.to_le_bytes()),
+        }
+    }
+    bytes
+}
+
+/// One channel of interleaved little endian `format` samples, scaled to -1.0..1.0
+fn decode(format: gst_audio::AudioFormat, bytes: &[u8], channels: u32, channel: u32) -> Vec<f64> {
+    let size = sample_bytes(format);
+    bytes
+        .chunks_exact(size * channels as usize)
+        .map(|frame| {
+            let b = &frame[size * channel as usize..][..size];
+            match format {
+                gst_audio::AudioFormat::S16le => {
+                    i16::from_le_bytes([b[0], b[1]]) as f64 / i16::MAX as f64
+                }
+                // Sign extended by putting the 24 bits at the top of an i32
+                gst_audio::AudioFormat::S24le => {
+                    (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f64 / 8_388_607.0
+                }
+                gst_audio::AudioFormat::S32le => {
+                    i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64 / i32::MAX as f64
+                }
+                gst_audio::AudioFormat::F32le => {
+                    f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64
+                }
+                _ => f64::from_le_bytes(b.try_into().unwrap()),
+            }
+        })
+        .collect()
+}
+
+/// Plays a sine of `frequency` on every input channel through the converters and returns
+/// the first output channel
+pub fn run(conversion: &Conversion, frequency: f64) -> Result<Vec<f64>, Error> {
+    let in_info = gst_audio::AudioInfo::builder(
+        conversion.in_format,
+        conversion.in_rate,
+        conversion.in_channels,
+    )
+    .build()?;
+    let out_info = gst_audio::AudioInfo::builder(
+        conversion.out_format,
+        conversion.out_rate,
+        conversion.out_channels,
+    )
+    .build()?;
+
+    let pipeline = gst::parse_launch(&format!(
+        "appsrc name=src format=time ! audioconvert ! audioresample quality={} \
+         ! appsink name=sink sync=false",
+        conversion.quality
+    ))?
+    .downcast::<gst::Pipeline>()
+    .unwrap();
+    let appsrc = pipeline
+        .by_name("src")
+        .unwrap()
+        .downcast::<gst_app::AppSrc>()
+        .unwrap();
+    let appsink = pipeline
+        .by_name("sink")
+        .unwrap()
+        .downcast::<gst_app::AppSink>()
+        .unwrap();
+    appsrc.set_caps(Some(&in_info.to_caps()?));
+    appsink.set_caps(Some(&out_info.to_caps()?));
+
+    let frames = (SECONDS * conversion.in_rate as f64) as usize;
+    let samples: Vec<f64> = (0..frames)
+        .flat_map(|n| {
+            let value =
+                AMPLITUDE * (2.0 * PI * frequency * n as f64 / conversion.in_rate as f64).sin();
+            std::iter::repeat(value).take(conversion.in_channels as usize)
+        })
+        .collect();
+    let mut buffer = gst::Buffer::from_mut_slice(encode(conversion.in_format, &samples));
+    {
+        let buffer = buffer.get_mut().unwrap();
+        buffer.set_pts(gst::ClockTime::ZERO);
+        buffer.set_duration(gst::ClockTime::from_seconds_f64(SECONDS));
+    }
+    appsrc.push_buffer(buffer)?;
+    appsrc.end_of_stream()?;
+
+    pipeline.set_state(gst::State::Playing)?;
+    let bus = pipeline.bus().unwrap();
+    let mut output = Vec::new();
+    let mut error = None;
+    loop {
+        if let Some(sample) = appsink.try_pull_sample(gst::ClockTime::from_mseconds(100)) {
+            let map = sample.buffer().unwrap().map_readable()?;
+            output.extend_from_slice(map.as_slice());
+            continue;
+        }
+        if appsink.is_eos() {
+            break;
+        }
+        // A failed negotiation stops the stream without an EOS
+        if let Some(msg) = bus.pop_filtered(&[gst::MessageType::Error]) {
+            if let gst::MessageView::Error(err) = msg.view() {
+                error = Some(err.error());
+            }
+            break;
+        }
+    }
+    pipeline.set_state(gst::State::Null)?;
+    if let Some(err) = error {
+        return Err(err.into());
+    }
+
+    Ok(decode(
+        conversion.out_format,
+        &output,
+        conversion.out_channels,
+        0,
+    ))
+}
+
+/// Least squares fit of `a·cos + b·sin + c` at `frequency`, returns (a, b, c)
+fn fit_sine(samples: &[f64], rate: u32, frequency: f64) -> (f64, f64, f64) {
+    // Normal equations of the three basis functions
+    let mut m = [[0.0; 3]; 3];
+    let mut v = [0.0; 3];
+    for (n, &x) in samples.iter().enumerate() {
+        let phase = 2.0 * PI * frequency * n as f64 / rate as f64;
+        let basis = [phase.cos(), phase.sin(), 1.0];
+        for ((row, value), bi) in m.iter_mut().zip(v.iter_mut()).zip(basis) {
+            for (cell, bj) in row.iter_mut().zip(basis) {
+                *cell += bi * bj;
+            }
+            *value += bi * x;
+        }
+    }
+
+    // Cramer's rule
+    let det = |m: &[[f64; 3]; 3]| {
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    };
+    let d = det(&m);
+    if d.abs() < f64::EPSILON {
+        return (0.0, 0.0, 0.0);
+    }
+    let solve = |column: usize| {
+        let mut replaced = m;
+        for (row, value) in v.iter().enumerate() {
+            replaced[row][column] = *value;
+        }
+        det(&replaced) / d
+    };
+    (solve(0), solve(1), solve(2))
+}
+
+/// Removes the fitted sine at `frequency` from `samples` and returns its amplitude
+fn remove_sine(samples: &mut [f64], rate: u32, frequency: f64) -> f64 {
+    let (a, b, c) = fit_sine(samples, rate, frequency);
+    for (n, x) in samples.iter_mut().enumerate() {
+        let phase = 2.0 * PI * frequency * n as f64 / rate as f64;
+        *x -= a * phase.cos() + b * phase.sin() + c;
+    }
+    a.hypot(b)
+}
+
+/// Quality of a converted tone
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToneAnalysis {
+    /// Amplitude of the tone relative to what went in, in dB
+    pub gain_db: f64,
+    /// Harmonics relative to the tone, in dB
+    pub thd_db: f64,
+    /// Tone relative to everything else that is not a harmonic, in dB
+    pub snr_db: f64,
+}
+
+fn db(ratio: f64) -> f64 {
+    20.0 * ratio.max(1e-15).log10()
+}
+
+pub fn analyse(output: &[f64], rate: u32, frequency: f64) -> ToneAnalysis {
+    let settle = ((SETTLE_SECONDS * rate as f64) as usize).min(output.len());
+    // And as much at the end, where the resampler drains
+    let end = output.len().saturating_sub(settle).max(settle);
+    let mut residual = output[settle..end].to_vec();
+
+    let amplitude = remove_sine(&mut residual, rate, frequency);
+    let harmonics: f64 = HARMONICS
+        .map(|k| k as f64 * frequency)
+        .filter(|&f| f < rate as f64 / 2.0)
+        .map(|f| remove_sine(&mut residual, rate, f).powi(2))
+        .sum();
+    let noise_rms =
+        (residual.iter().map(|x| x * x).sum::<f64>() / residual.len().max(1) as f64).sqrt();
+
+    ToneAnalysis {
+        gain_db: db(amplitude / AMPLITUDE),
+        thd_db: db(harmonics.sqrt() / amplitude),
+        snr_db: db(amplitude / std::f64::consts::SQRT_2 / noise_rms),
+    }
+}
+
+/// Gain of every response tone the conversion can carry, relative to the 1 kHz tone
+pub fn frequency_response(conversion: &Conversion) -> Result<Vec<(f64, f64)>, Error> {
+    let limit = 0.45 * conversion.in_rate.min(conversion.out_rate) as f64;
+    let reference = analyse(
+        &run(conversion, DEFAULT_TONE)?,
+        conversion.out_rate,
+        DEFAULT_TONE,
+    );
+
+    let mut response = Vec::new();
+    for frequency in RESPONSE_TONES.into_iter().filter(|&f| f <= limit) {
+        let analysis = analyse(&run(conversion, frequency)?, conversion.out_rate, frequency);
+        response.push((frequency, analysis.gain_db - reference.gain_db));
+    }
+    Ok(response)
+}
+
+fn format_name(format: gst_audio::AudioFormat) -> &'static str {
+    match format {
+        gst_audio::AudioFormat::S16le => "S16",
+        gst_audio::AudioFormat::S24le => "S24",
+        gst_audio::AudioFormat::S32le => "S32",
+        gst_audio::AudioFormat::F32le => "F32",
+        _ => "F64",
+    }
+}
+
+fn print_analysis(label: &str, result: Result<ToneAnalysis, Error>) {
+    match result {
+        Ok(analysis) => println!(
+            "{label:<24} gain {:>7.2} dB  THD {:>8.1} dB  SNR {:>7.1} dB",
+            analysis.gain_db, analysis.thd_db, analysis.snr_db
+        ),
+        Err(err) => println!("{label:<24} unsupported: {err}"),
+    }
+}
+
+fn format_matrix(tone: f64, quality: i32) {
+    println!("\nSample formats at 48000 Hz, {tone} Hz");
+    for in_format in FORMATS {
+        for out_format in FORMATS {
+            let conversion = Conversion {
+                in_format,
+                out_format,
+                quality,
+                ..Conversion::default()
+            };
+            let label = format!("{} -> {}", format_name(in_format), format_name(out_format));
+            print_analysis(
+                &label,
+                run(&conversion, tone).map(|output| analyse(&output, conversion.out_rate, tone)),
+            );
+        }
+    }
+}
+
+fn rate_matrix(tone: f64, qualities: &[i32]) {
+    for &quality in qualities {
+        println!("\nSample rates, F32, audioresample quality={quality}, {tone} Hz");
+        for in_rate in RATES {
+            for out_rate in RATES {
+                let conversion = Conversion {
+                    in_rate,
+                    out_rate,
+                    quality,
+                    ..Conversion::default()
+                };
+                // The tone has to fit both rates
+                let tone = tone.min(0.4 * in_rate.min(out_rate) as f64);
+                let label = format!("{in_rate} -> {out_rate}");
+                print_analysis(
+                    &label,
+                    run(&conversion, tone).map(|output| analyse(&output, out_rate, tone)),
+                );
+
+                // Flatness of the passband, the worst deviation from the 1 kHz gain
+                if in_rate != out_rate {
+                    match frequency_response(&conversion) {
+                        Ok(response) => {
+                            let worst = response
+                                .iter()
+                                .max_by(|a, b| a.1.abs().total_cmp(&b.1.abs()))
+                                .copied();
+                            if let Some((frequency, gain)) = worst {
+                                println!(
+                                    "{:<24} response worst {gain:+.2} dB at {frequency} Hz",
+                                    ""
+                                );
+                            }
+                        }
+                        Err(err) => println!("{:<24} response failed: {err}", ""),
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn channel_matrix(tone: f64, quality: i32) {
+    println!("\nChannel layouts, F32 at 48000 Hz, {tone} Hz, first output channel");
+    for in_channels in CHANNELS {
+        for out_channels in CHANNELS {
+            let conversion = Conversion {
+                in_channels,
+                out_channels,
+                quality,
+                ..Conversion::default()
+            };
+            let label = format!("{in_channels}ch -> {out_channels}ch");
+            print_analysis(
+                &label,
+                run(&conversion, tone).map(|output| analyse(&output, conversion.out_rate, tone)),
+            );
+        }
+    }
+}
+
+/// Measures `audioconvert ! audioresample` with sine tones: `formats`, `rates`, `channels`
+/// or `all` (the default) matrices. `--tone=HZ` changes the test tone and
+/// `--quality=Q[,Q...]` the audioresample qualities compared in the rate matrix.
+pub fn tutorial_main() -> Result<(), Error> {
+    // Initialize GStreamer
+    gst::init()?;
+
+    let mut matrices = Vec::new();
+    let mut tone = DEFAULT_TONE;
+    let mut qualities = vec![0, 4, 10];
+    for arg in env::args().skip(1) {
+        let (name, value) = arg.split_once('=').unwrap_or((arg.as_str(), ""));
+        match name {
+            "--tone" => tone = value.parse()?,
+            "--quality" => {
+                qualities = value.split(',').map(str::parse).collect::<Result<_, _>>()?;
+            }
+            "formats" | "rates" | "channels" | "all" => matrices.push(arg.clone()),
+            _ => return Err(glib::bool_error!("Unknown option {arg}").into()),
+        }
+    }
+    let all = matrices.is_empty() || matrices.iter().any(|m| m == "all");
+    // Format and channel matrices keep the rate, the default quality is enough
+    let default_quality = Conversion::default().quality;
+
+    if all || matrices.iter().any(|m| m == "formats") {
+        format_matrix(tone, default_quality);
+    }
+    if all || matrices.iter().any(|m| m == "rates") {
+        rate_matrix(tone, &qualities);
+    }
+    if all || matrices.iter().any(|m| m == "channels") {
+        channel_matrix(tone, default_quality);
+    }
+
+    Ok(())
+}
@@ -2,6 +2,9 @@ use std::{io, io::Write};
 
 use gstreamer::prelude::*;
 
+use crate::buffering::{self, BufferingController};
+use crate::timecode::{self, PositionReport};
+
 struct CustomData {
     /// Our one and only element
     playbin: gstreamer::Element,
@@ -15,6 +18,12 @@ struct CustomData {
     seek_done: bool,
     /// How long does this media last, in nanoseconds
     duration: Option<gstreamer::ClockTime>,
+    /// Negotiated video framerate, for timecodes and frame numbers
+    fps: Option<gstreamer::Fraction>,
+    /// Negotiated audio sample rate, for sample counts
+    rate: Option<u32>,
+    /// Pauses playback while network sources refill
+    buffering: BufferingController,
 }
 
 pub fn tutorial_main() {
@@ -30,10 +39,12 @@ pub fn tutorial_main() {
         .property("uri", uri)
         .build()
         .expect("Failed to create playbin element");
+    buffering::progressive_download_from_env(&playbin);
 
-    // Start playing
-    playbin
-        .set_state(gstreamer::State::Playing)
+    // Start playing, or buffering first
+    let mut buffering = BufferingController::new(&playbin);
+    buffering
+        .set_target_state(gstreamer::State::Playing)
         .expect("Unable to set the playbin to the `Playing` state");
 
     // Listen to the bus
@@ -45,6 +56,9 @@ pub fn tutorial_main() {
         seek_enabled: false,
         seek_done: false,
         duration: gstreamer::ClockTime::NONE,
+        fps: None,
+        rate: None,
+        buffering,
     };
 
     while !custom_data.terminate {
@@ -55,7 +69,7 @@ pub fn tutorial_main() {
                 handle_message(&mut custom_data, &msg);
             }
             None => {
-                if custom_data.playing {
+                if custom_data.playing && !custom_data.buffering.is_buffering() {
                     let position = custom_data
                         .playbin
                         .query_position::<gstreamer::ClockTime>()
@@ -66,12 +80,19 @@ pub fn tutorial_main() {
                         custom_data.duration = custom_data.playbin.query_duration();
                     }
 
-                    // Print current position and total duration
-                    print!(
-                        "\rPosition {} / {}",
-                        position,
-                        custom_data.duration.display()
-                    );
+                    // Print current position in every format we know, and total duration
+                    match PositionReport::query(
+                        &custom_data.playbin,
+                        custom_data.fps,
+                        custom_data.rate,
+                    ) {
+                        Some(report) => print!("\r{report}"),
+                        None => print!(
+                            "\rPosition {} / {}",
+                            position,
+                            custom_data.duration.display()
+                        ),
+                    }
                     io::stdout().flush().unwrap();
 
                     if custom_data.seek_enabled
@@ -103,6 +124,10 @@ pub fn tutorial_main() {
 fn handle_message(custom_data: &mut CustomData, msg: &gstreamer::Message) {
     use gstreamer::MessageView;
 
+    if custom_data.buffering.handle_message(msg) {
+        return;
+    }
+
     match msg.view() {
         MessageView::Error(err) => {
             println!(
@@ -134,6 +159,9 @@ fn handle_message(custom_data: &mut CustomData, msg: &gstreamer::Message) {
 
                 custom_data.playing = new_state == gstreamer::State::Playing;
                 if custom_data.playing {
+                    // Caps are negotiated by now
+                    (custom_data.fps, custom_data.rate) = timecode::playbin_rates(&custom_data.playbin);
+
                     let mut seeking = gstreamer::query::Seeking::new(gstreamer::Format::Time);
                     if custom_data.playbin.query(&mut seeking) {
                         let (seekable, start, end) = seeking.result();
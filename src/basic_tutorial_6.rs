@@ -1,6 +1,6 @@
 use gstreamer::prelude::*;
 
-fn print_caps(caps: &gstreamer::CapsRef, prefix: &str) {
+pub(crate) fn print_caps(caps: &gstreamer::CapsRef, prefix: &str) {
     if caps.is_any() {
         println!("{prefix}ANY");
         return;
@@ -1,23 +1,35 @@
-use std::sync::{Arc, Mutex};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
 
 extern crate gstreamer as gst;
 extern crate gstreamer_app as gst_app;
 extern crate gstreamer_audio as gst_audio;
 
 use byte_slice_cast::*;
+use crossterm::event::KeyCode;
 use glib::source::SourceId;
 use gst::prelude::*;
 use gst_app::{AppSink, AppSrc};
 use gst_audio::AudioInfo;
 
+use crate::channel_layout::{self, ChannelLayout};
+use crate::hot_swap;
+use crate::input::{InputController, Keymap};
+
 const CHUNK_SIZE: usize = 1024; // Amount of bytes we are sending in each buffer
 const SAMPLE_RATE: u32 = 44_100; // Samples per second we are sending
 
+// Visualisers v cycles through while playing
+const SCOPE_MODES: [&str; 3] = ["waveform", "lissajous", "spectrogram"];
+
 #[derive(Debug)]
 struct CustomData {
     source_id: Option<SourceId>,
 
     num_samples: u64, // Number of samples generated so far (for timestamp generation)
+    channels: usize,  // Every sample is written to all of them
     // For waveform generation
     a: f64,
     b: f64,
@@ -29,10 +41,11 @@ struct CustomData {
 }
 
 impl CustomData {
-    fn new(appsrc: &AppSrc, appsink: &AppSink) -> CustomData {
+    fn new(appsrc: &AppSrc, appsink: &AppSink, channels: usize) -> CustomData {
         CustomData {
             source_id: None,
             num_samples: 0,
+            channels,
             a: 0.0,
             b: 1.0,
             c: 0.0,
@@ -49,9 +62,20 @@ pub fn tutorial_main() {
         eprintln!("Failed to initialize Gst: {err}");
         return;
     }
+    // rsscope replaces wavescope, so gst-plugins-bad is not needed
+    if let Err(err) = crate::plugin_prac::register() {
+        eprintln!("Failed to register the gstreamprac plugin: {err}");
+        return;
+    }
 
-    let info = AudioInfo::builder(gst_audio::AudioFormat::S16le, SAMPLE_RATE, 1)
-        .build()
+    // AUDIO_LAYOUT picks what is generated, AUDIO_OUTPUT_LAYOUT what is played. Surround is
+    // downmixed to stereo unless asked otherwise.
+    let layout = ChannelLayout::from_env("AUDIO_LAYOUT", ChannelLayout::Mono);
+    let output_layout = ChannelLayout::from_env("AUDIO_OUTPUT_LAYOUT", layout.preview());
+    println!("Generating {layout:?}, playing {output_layout:?}");
+
+    let info = layout
+        .audio_info(gst_audio::AudioFormat::S16le, SAMPLE_RATE)
         .unwrap();
     let audio_caps = info.to_caps().unwrap();
 
@@ -72,6 +96,14 @@ pub fn tutorial_main() {
         .name("audio_convert1")
         .build()
         .unwrap();
+    if output_layout != layout {
+        channel_layout::set_mix_matrix(&audio_convert1, layout, output_layout);
+    }
+    let audio_mix_caps = gst::ElementFactory::make("capsfilter")
+        .name("audio_mix_caps")
+        .property("caps", output_layout.caps())
+        .build()
+        .unwrap();
     let audio_resample = gst::ElementFactory::make("audioresample")
         .name("audio_resample")
         .build()
@@ -88,10 +120,9 @@ pub fn tutorial_main() {
         .name("audio_convert2")
         .build()
         .unwrap();
-    let visual = gst::ElementFactory::make("wavescope")
+    let visual = gst::ElementFactory::make("rsscope")
         .name("visual")
-        .property_from_str("shader", "none")
-        .property_from_str("style", "lines")
+        .property_from_str("mode", "waveform")
         .build()
         .unwrap();
     let video_convert = gst::ElementFactory::make("videoconvert")
@@ -119,6 +150,7 @@ pub fn tutorial_main() {
             &tee,
             &audio_queue,
             &audio_convert1,
+            &audio_mix_caps,
             &audio_resample,
             &audio_sink,
             &video_queue,
@@ -132,14 +164,22 @@ pub fn tutorial_main() {
         .unwrap();
 
     gst::Element::link_many([appsrc.upcast_ref(), &tee]).unwrap();
-    gst::Element::link_many([&audio_queue, &audio_convert1, &audio_resample, &audio_sink]).unwrap();
+    gst::Element::link_many([
+        &audio_queue,
+        &audio_convert1,
+        &audio_mix_caps,
+        &audio_resample,
+        &audio_sink,
+    ])
+    .unwrap();
     gst::Element::link_many([
         &video_queue,
         &audio_convert2,
         &visual,
         &video_convert,
         &video_sink,
-    ]).unwrap();
+    ])
+    .unwrap();
     gst::Element::link_many([&app_queue, appsink.upcast_ref()]).unwrap();
 
     let tee_audio_pad = tee.request_pad_simple("src_%u").unwrap();
@@ -161,7 +201,11 @@ pub fn tutorial_main() {
     let queue_app_pad = app_queue.static_pad("sink").unwrap();
     tee_app_pad.link(&queue_app_pad).unwrap();
 
-    let data: Arc<Mutex<CustomData>> = Arc::new(Mutex::new(CustomData::new(&appsrc, &appsink)));
+    let data: Arc<Mutex<CustomData>> = Arc::new(Mutex::new(CustomData::new(
+        &appsrc,
+        &appsink,
+        info.channels() as usize,
+    )));
 
     let data_weak = Arc::downgrade(&data);
     let data_weak2 = Arc::downgrade(&data);
@@ -184,8 +228,10 @@ pub fn tutorial_main() {
 
                         let (appsrc, buffer) = {
                             let mut data = data.lock().unwrap();
-                            let mut buffer = gst::Buffer::with_size(CHUNK_SIZE).unwrap();
-                            let num_samples = CHUNK_SIZE / 2; /* Each sample is 16 bits */
+                            // Each sample is 16 bits, a frame has one per channel
+                            let num_samples = CHUNK_SIZE / (2 * data.channels);
+                            let mut buffer =
+                                gst::Buffer::with_size(num_samples * 2 * data.channels).unwrap();
                             let pts = gst::ClockTime::SECOND
                                 .mul_div_floor(data.num_samples, u64::from(SAMPLE_RATE))
                                 .expect("u64 overflow");
@@ -204,10 +250,11 @@ pub fn tutorial_main() {
                                     data.d -= data.c / 1000.0;
                                     let freq = 1100.0 + 1000.0 * data.d;
 
-                                    for sample in samples.iter_mut() {
+                                    let channels = data.channels;
+                                    for frame in samples.chunks_exact_mut(channels) {
                                         data.a += data.b;
                                         data.b -= data.a / freq;
-                                        *sample = 500 * (data.a as i16);
+                                        frame.fill(500 * (data.a as i16));
                                     }
 
                                     data.num_samples += num_samples as u64;
@@ -239,6 +286,7 @@ pub fn tutorial_main() {
     );
 
     let data_weak = Arc::downgrade(&data);
+    let mut printed_layout = false;
     appsink.set_callbacks(
         gst_app::AppSinkCallbacks::builder()
             .new_sample(move |_| {
@@ -251,8 +299,22 @@ pub fn tutorial_main() {
                     data.appsink.clone()
                 };
 
-                if let Ok(_sample) = appsink.pull_sample() {
+                if let Ok(sample) = appsink.pull_sample() {
                     use std::io::{self, Write};
+                    // Print the layout once, with the first buffer
+                    if !printed_layout {
+                        printed_layout = true;
+                        let info = sample
+                            .caps()
+                            .and_then(|caps| AudioInfo::from_caps(caps).ok());
+                        if let Some(info) = info {
+                            println!(
+                                "Receiving {} channels: {:?}",
+                                info.channels(),
+                                info.positions()
+                            );
+                        }
+                    }
                     // The only thing we do in this example is print a * to indicate a received buffer
                     print!("*");
                     let _ = io::stdout().flush();
@@ -264,6 +326,80 @@ pub fn tutorial_main() {
     );
 
     let main_loop = glib::MainLoop::new(None, false);
+
+    // v swaps the visualiser, m swaps the audio sink for a fakesink and back, all while playing
+    #[derive(Clone, Copy)]
+    enum Swap {
+        Visual,
+        Mute,
+    }
+    let keymap = Keymap::new()
+        .bind(KeyCode::Char('v'), Swap::Visual)
+        .bind(KeyCode::Char('m'), Swap::Mute);
+    let pipeline_clone = pipeline.clone();
+    // Only updated once a swap is done, until then the old element is the one in the pipeline
+    let visual = Arc::new(Mutex::new(visual.clone()));
+    let mut mode = 0;
+    let audio_sink = Arc::new(Mutex::new(audio_sink.clone()));
+    let swapping = Arc::new(AtomicBool::new(false));
+    let input = InputController::start(keymap, &main_loop, move |action| {
+        if swapping.load(Ordering::SeqCst) {
+            println!("Still swapping, try again in a moment");
+            return;
+        }
+        let pipeline: &gst::Bin = pipeline_clone.upcast_ref();
+        let (slot, replacement) = match action {
+            Swap::Visual => {
+                mode = (mode + 1) % SCOPE_MODES.len();
+                let replacement = gst::ElementFactory::make("rsscope")
+                    .property_from_str("mode", SCOPE_MODES[mode])
+                    .build()
+                    .unwrap();
+                (visual.clone(), replacement)
+            }
+            Swap::Mute => {
+                let muted = audio_sink
+                    .lock()
+                    .unwrap()
+                    .factory()
+                    .is_some_and(|f| f.name() == "fakesink");
+                let factory = if muted { "autoaudiosink" } else { "fakesink" };
+                let replacement = gst::ElementFactory::make(factory).build().unwrap();
+                if factory == "fakesink" {
+                    // Keep consuming in real time, the other branches wait on it through the tee
+                    replacement.set_property("sync", true);
+                }
+                (audio_sink.clone(), replacement)
+            }
+        };
+        let old = slot.lock().unwrap().clone();
+        swapping.store(true, Ordering::SeqCst);
+        let swapping_clone = swapping.clone();
+        let replacement_clone = replacement.clone();
+        let old_name = old.name();
+        let scheduled = hot_swap::swap_element(pipeline, &old, &replacement, move |result| {
+            match result {
+                Ok(()) => *slot.lock().unwrap() = replacement_clone,
+                Err(err) => eprintln!("Failed to swap {old_name}: {err}"),
+            }
+            swapping_clone.store(false, Ordering::SeqCst);
+        });
+        if let Err(err) = scheduled {
+            swapping.store(false, Ordering::SeqCst);
+            eprintln!("Failed to swap {}: {err}", old.name());
+        }
+    });
+    let _input = match input {
+        Ok(input) => {
+            println!("Press v to change the visualiser, m to mute or unmute");
+            Some(input)
+        }
+        Err(err) => {
+            eprintln!("Failed to read keyboard input, elements can't be swapped: {err}");
+            None
+        }
+    };
+
     let main_loop_clone = main_loop.clone();
     let bus = pipeline.bus().unwrap();
     #[allow(clippy::single_match)]
@@ -280,8 +416,21 @@ pub fn tutorial_main() {
         }
         _ => unreachable!(),
     });
+    // Swapping the audio sink takes its clock away, get a new one by going through PAUSED
+    let pipeline_weak = pipeline.downgrade();
+    bus.connect_message(Some("clock-lost"), move |_, _| {
+        let Some(pipeline) = pipeline_weak.upgrade() else {
+            return;
+        };
+        println!("Clock lost, selecting a new one");
+        let _ = pipeline.set_state(gst::State::Paused);
+        let _ = pipeline.set_state(gst::State::Playing);
+    });
     bus.add_signal_watch();
 
+    // Log every buffer when BUFFER_TRACE is set, view it with buffer_tracer::tutorial_main
+    let _tracer = crate::buffer_tracer::attach_from_env(pipeline.upcast_ref());
+
     pipeline
         .set_state(gst::State::Playing)
         .expect("Unable to set the pipeline to the `Playing` state.");
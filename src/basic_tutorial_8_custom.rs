@@ -1,18 +1,33 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 extern crate gstreamer as gst;
 extern crate gstreamer_app as gst_app;
 extern crate gstreamer_audio as gst_audio;
 
 use byte_slice_cast::*;
+use crossterm::event::KeyCode;
 use glib::source::SourceId;
 use gst::prelude::*;
 use gst_app::{AppSink, AppSrc};
 use gst_audio::AudioInfo;
 
+use crate::input::{InputController, Keymap};
+use crate::qos_stats::QosCollector;
+use crate::tee_branches::TeeBranches;
+
 const CHUNK_SIZE: usize = 1024; // Amount of bytes we are sending in each buffer
 const SAMPLE_RATE: u32 = 44_100; // Samples per second we are sending
 
+// Branches that can be attached to the tee while playing
+const RECORD_BRANCH: &str =
+    "queue ! audioconvert ! audioresample ! wavenc ! filesink location=recording.wav";
+const SPECTRUM_BRANCH: &str =
+    "queue ! audioconvert ! rsscope mode=spectrogram ! videoconvert ! autovideosink";
+const COUNT_BRANCH: &str = "queue ! fakesink name=count_sink sync=true";
+
 #[derive(Debug)]
 struct CustomData {
     source_id: Option<SourceId>,
@@ -49,6 +64,11 @@ pub fn tutorial_main() {
         eprintln!("Failed to initialize Gst: {err}");
         return;
     }
+    // rsscope replaces wavescope, so gst-plugins-bad is not needed
+    if let Err(err) = crate::plugin_prac::register() {
+        eprintln!("Failed to register the gstreamprac plugin: {err}");
+        return;
+    }
 
     let uri = "https://gstreamer.freedesktop.org/data/media/sintel_trailer-480p.webm";
 
@@ -96,10 +116,9 @@ pub fn tutorial_main() {
         .name("audio_convert2")
         .build()
         .unwrap();
-    let visual = gst::ElementFactory::make("wavescope")
+    let visual = gst::ElementFactory::make("rsscope")
         .name("visual")
-        .property_from_str("shader", "none")
-        .property_from_str("style", "lines")
+        .property_from_str("mode", "waveform")
         .build()
         .unwrap();
     let video_convert = gst::ElementFactory::make("videoconvert")
@@ -272,6 +291,35 @@ pub fn tutorial_main() {
     // );
 
     let main_loop = glib::MainLoop::new(None, false);
+
+    // r, s and c attach or detach a recording, a spectrogram and a counting branch
+    let branches = TeeBranches::new(pipeline.upcast_ref(), &tee);
+    let branches_clone = branches.clone();
+    let keymap = Keymap::new()
+        .bind(KeyCode::Char('r'), ("record", RECORD_BRANCH))
+        .bind(KeyCode::Char('s'), ("spectrum", SPECTRUM_BRANCH))
+        .bind(KeyCode::Char('c'), ("count", COUNT_BRANCH));
+    let input = InputController::start(keymap, &main_loop, move |(name, description)| {
+        let result = if branches_clone.contains(name) {
+            branches_clone.remove(name)
+        } else {
+            branches_clone.add_from_description(name, description)
+        };
+        if let Err(err) = result {
+            eprintln!("Failed to toggle branch {name}: {err}");
+        }
+    });
+    let _input = match input {
+        Ok(input) => {
+            println!("Press r, s or c to toggle the record, spectrum and count branches");
+            Some(input)
+        }
+        Err(err) => {
+            eprintln!("Failed to read keyboard input, branches can't be toggled: {err}");
+            None
+        }
+    };
+
     let main_loop_clone = main_loop.clone();
     let bus = pipeline.bus().unwrap();
     #[allow(clippy::single_match)]
@@ -290,12 +338,38 @@ pub fn tutorial_main() {
     });
     bus.add_signal_watch();
 
+    // Find the branch of the tee that falls behind: QoS per element, sink stats and
+    // buffers per pad
+    let qos = QosCollector::new(pipeline.upcast_ref());
+    let qos_clone = qos.clone();
+    bus.connect_message(Some("qos"), move |_, msg| {
+        qos_clone.handle_message(msg);
+    });
+    // uridecodebin adds its pads late, so look for new pads every time
+    let qos_clone = qos.clone();
+    let qos_timeout = glib::timeout_add_seconds_local(5, move || {
+        qos_clone.install_probes();
+        qos_clone.print_table();
+        glib::ControlFlow::Continue
+    });
+
     pipeline
         .set_state(gst::State::Playing)
         .expect("Unable to set the pipeline to the `Playing` state.");
+    qos.install_probes();
 
     main_loop.run();
 
+    qos_timeout.remove();
+    qos.print_table();
+
+    // Detach the recording before stopping, wavenc writes the final header on EOS
+    if branches.contains("record") {
+        if let Err(err) = branches.remove_and_wait("record", Duration::from_secs(5)) {
+            eprintln!("Failed to finish the recording: {err}");
+        }
+    }
+
     pipeline
         .set_state(gst::State::Null)
         .expect("Unable to set the pipeline to the `Null` state.");
@@ -74,7 +74,7 @@ fn print_topology(info: &DiscovererStreamInfo, depth: usize) {
     }
 }
 
-fn on_discovered(
+pub(crate) fn on_discovered(
     _discoverer: &Discoverer,
     discoverer_info: &DiscovererInfo,
     error: Option<&glib::Error>,
@@ -0,0 +1,622 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use gstreamer as gst;
+
+use anyhow::Error;
+use gst::prelude::*;
+
+/// First bytes of a binary trace
+const MAGIC: &[u8; 8] = b"GSTTRC01";
+const CSV_HEADER: &str = "time_ns,pad,kind,pts,dts,duration,flags,size,detail";
+
+/// Differences below this between where a buffer starts and where the previous one ended
+/// are rounding, not gaps
+const GAP_TOLERANCE: u64 = 1_000; // 1 µs
+
+const TAG_PAD: u8 = 0;
+const TAG_BUFFER: u8 = 1;
+const TAG_EVENT: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Csv,
+    Binary,
+}
+
+impl TraceFormat {
+    /// `.bin` files are binary, everything else CSV
+    pub fn from_path(path: &Path) -> TraceFormat {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("bin") => TraceFormat::Binary,
+            _ => TraceFormat::Csv,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceKind {
+    Buffer {
+        pts: Option<u64>,
+        dts: Option<u64>,
+        duration: Option<u64>,
+        flags: u32,
+        size: u64,
+    },
+    /// Event type name (`segment`, `flush-start`, `caps`, `gap`, `eos`, ...) and a one line
+    /// description
+    Event { name: String, detail: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceRecord {
+    /// Nanoseconds since the tracer was attached
+    pub time: u64,
+    /// `element.pad`
+    pub pad: String,
+    pub kind: TraceKind,
+}
+
+fn event_detail(event: &gst::EventRef) -> String {
+    use gst::EventView;
+
+    match event.view() {
+        EventView::Caps(caps) => caps.caps().to_string(),
+        EventView::Segment(segment) => {
+            let segment = segment.segment();
+            match segment.downcast_ref::<gst::ClockTime>() {
+                Some(segment) => format!(
+                    "start={} stop={} base={} rate={}",
+                    segment.start().display(),
+                    segment.stop().display(),
+                    segment.base().display(),
+                    segment.rate()
+                ),
+                None => format!("format={:?}", segment.format()),
+            }
+        }
+        EventView::Gap(gap) => {
+            let (timestamp, duration) = gap.get();
+            format!("timestamp={timestamp} duration={}", duration.display())
+        }
+        _ => String::new(),
+    }
+}
+
+/// Where records go while tracing
+enum Sink {
+    Csv(BufWriter<File>),
+    Binary {
+        writer: BufWriter<File>,
+        /// Pad names are written once and then referred to by index
+        pads: HashMap<String, u32>,
+    },
+}
+
+fn write_opt(writer: &mut impl Write, value: Option<u64>) -> io::Result<()> {
+    writer.write_all(&value.unwrap_or(u64::MAX).to_le_bytes())
+}
+
+fn write_str(writer: &mut impl Write, value: &str) -> io::Result<()> {
+    let bytes = &value.as_bytes()[..value.len().min(u16::MAX as usize)];
+    writer.write_all(&(bytes.len() as u16).to_le_bytes())?;
+    writer.write_all(bytes)
+}
+
+/// Keeps an event detail on one CSV line
+fn escape_detail(detail: &str) -> String {
+    detail
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn unescape_detail(detail: &str) -> String {
+    let mut unescaped = String::with_capacity(detail.len());
+    let mut chars = detail.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+impl Sink {
+    /// Creates `path` and writes the header of the format its extension asks for
+    fn create(path: &Path) -> io::Result<Sink> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        match TraceFormat::from_path(path) {
+            TraceFormat::Csv => {
+                writeln!(writer, "{CSV_HEADER}")?;
+                Ok(Sink::Csv(writer))
+            }
+            TraceFormat::Binary => {
+                writer.write_all(MAGIC)?;
+                Ok(Sink::Binary {
+                    writer,
+                    pads: HashMap::new(),
+                })
+            }
+        }
+    }
+
+    fn write(&mut self, record: &TraceRecord) -> io::Result<()> {
+        match self {
+            Sink::Csv(writer) => {
+                let opt = |v: Option<u64>| v.map(|v| v.to_string()).unwrap_or_default();
+                match &record.kind {
+                    TraceKind::Buffer {
+                        pts,
+                        dts,
+                        duration,
+                        flags,
+                        size,
+                    } => writeln!(
+                        writer,
+                        "{},{},buffer,{},{},{},{flags},{size},",
+                        record.time,
+                        record.pad,
+                        opt(*pts),
+                        opt(*dts),
+                        opt(*duration)
+                    ),
+                    // The detail is the last column, so it may contain commas
+                    TraceKind::Event { name, detail } => writeln!(
+                        writer,
+                        "{},{},{name},,,,,,{}",
+                        record.time,
+                        record.pad,
+                        escape_detail(detail)
+                    ),
+                }
+            }
+            Sink::Binary { writer, pads } => {
+                let next = pads.len() as u32;
+                let pad = *pads.entry(record.pad.clone()).or_insert(next);
+                if pad == next {
+                    writer.write_all(&[TAG_PAD])?;
+                    writer.write_all(&pad.to_le_bytes())?;
+                    write_str(writer, &record.pad)?;
+                }
+
+                match &record.kind {
+                    TraceKind::Buffer {
+                        pts,
+                        dts,
+                        duration,
+                        flags,
+                        size,
+                    } => {
+                        writer.write_all(&[TAG_BUFFER])?;
+                        writer.write_all(&pad.to_le_bytes())?;
+                        writer.write_all(&record.time.to_le_bytes())?;
+                        write_opt(writer, *pts)?;
+                        write_opt(writer, *dts)?;
+                        write_opt(writer, *duration)?;
+                        writer.write_all(&flags.to_le_bytes())?;
+                        writer.write_all(&size.to_le_bytes())
+                    }
+                    TraceKind::Event { name, detail } => {
+                        writer.write_all(&[TAG_EVENT])?;
+                        writer.write_all(&pad.to_le_bytes())?;
+                        writer.write_all(&record.time.to_le_bytes())?;
+                        write_str(writer, name)?;
+                        write_str(writer, detail)
+                    }
+                }
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::Csv(writer) | Sink::Binary { writer, .. } => writer.flush(),
+        }
+    }
+}
+
+/// Logs every buffer and the interesting events on every pad of a pipeline, including pads
+/// and elements added later. The log is flushed when the tracer is dropped.
+pub struct BufferTracer {
+    sink: Arc<Mutex<Sink>>,
+}
+
+impl BufferTracer {
+    pub fn attach(pipeline: &gst::Bin, path: &Path) -> io::Result<BufferTracer> {
+        let sink = Arc::new(Mutex::new(Sink::create(path)?));
+        let start = Instant::now();
+
+        for element in pipeline.iterate_recurse().into_iter().flatten() {
+            watch_element(&element, &sink, start);
+        }
+        let sink_clone = sink.clone();
+        pipeline.connect_deep_element_added(move |_pipeline, _bin, element| {
+            watch_element(element, &sink_clone, start);
+        });
+
+        Ok(BufferTracer { sink })
+    }
+}
+
+impl Drop for BufferTracer {
+    fn drop(&mut self) {
+        if let Err(err) = self.sink.lock().unwrap().flush() {
+            eprintln!("Failed to flush the buffer trace: {err}");
+        }
+    }
+}
+
+fn watch_element(element: &gst::Element, sink: &Arc<Mutex<Sink>>, start: Instant) {
+    for pad in element.pads() {
+        probe_pad(&pad, sink, start);
+    }
+    let sink = sink.clone();
+    element.connect_pad_added(move |_element, pad| probe_pad(pad, &sink, start));
+}
+
+fn probe_pad(pad: &gst::Pad, sink: &Arc<Mutex<Sink>>, start: Instant) {
+    let name = match pad.parent_element() {
+        Some(element) => format!("{}.{}", element.name(), pad.name()),
+        None => pad.name().to_string(),
+    };
+    let sink = sink.clone();
+
+    pad.add_probe(
+        gst::PadProbeType::BUFFER | gst::PadProbeType::BUFFER_LIST | gst::PadProbeType::EVENT_BOTH,
+        move |_pad, info| {
+            let time = start.elapsed().as_nanos() as u64;
+            let buffer_kind = |buffer: &gst::BufferRef| TraceKind::Buffer {
+                pts: buffer.pts().map(gst::ClockTime::nseconds),
+                dts: buffer.dts().map(gst::ClockTime::nseconds),
+                duration: buffer.duration().map(gst::ClockTime::nseconds),
+                flags: buffer.flags().bits(),
+                size: buffer.size() as u64,
+            };
+
+            let kinds: Vec<TraceKind> = match &info.data {
+                Some(gst::PadProbeData::Buffer(buffer)) => vec![buffer_kind(buffer)],
+                Some(gst::PadProbeData::BufferList(list)) => list.iter().map(buffer_kind).collect(),
+                Some(gst::PadProbeData::Event(event)) => vec![TraceKind::Event {
+                    name: event.type_().name().to_string(),
+                    detail: event_detail(event),
+                }],
+                _ => return gst::PadProbeReturn::Ok,
+            };
+
+            let mut sink = sink.lock().unwrap();
+            for kind in kinds {
+                let record = TraceRecord {
+                    time,
+                    pad: name.clone(),
+                    kind,
+                };
+                if let Err(err) = sink.write(&record) {
+                    eprintln!("Failed to write buffer trace: {err}");
+                }
+            }
+            gst::PadProbeReturn::Ok
+        },
+    );
+}
+
+fn read_u8(reader: &mut impl Read) -> io::Result<u8> {
+    let mut bytes = [0; 1];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_opt(reader: &mut impl Read) -> io::Result<Option<u64>> {
+    read_u64(reader).map(|v| Some(v).filter(|v| *v != u64::MAX))
+}
+
+fn read_str(reader: &mut impl Read) -> io::Result<String> {
+    let mut len = [0; 2];
+    reader.read_exact(&mut len)?;
+    let mut bytes = vec![0; u16::from_le_bytes(len) as usize];
+    reader.read_exact(&mut bytes)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_binary(reader: &mut impl Read) -> io::Result<Vec<TraceRecord>> {
+    let mut pads: HashMap<u32, String> = HashMap::new();
+    let mut records = Vec::new();
+
+    loop {
+        let tag = match read_u8(reader) {
+            Ok(tag) => tag,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err),
+        };
+        let pad_id = read_u32(reader)?;
+        if tag == TAG_PAD {
+            pads.insert(pad_id, read_str(reader)?);
+            continue;
+        }
+
+        let pad = pads
+            .get(&pad_id)
+            .cloned()
+            .ok_or_else(|| invalid("unknown pad index"))?;
+        let time = read_u64(reader)?;
+        let kind = match tag {
+            TAG_BUFFER => TraceKind::Buffer {
+                pts: read_opt(reader)?,
+                dts: read_opt(reader)?,
+                duration: read_opt(reader)?,
+                flags: read_u32(reader)?,
+                size: read_u64(reader)?,
+            },
+            TAG_EVENT => TraceKind::Event {
+                name: read_str(reader)?,
+                detail: read_str(reader)?,
+            },
+            _ => return Err(invalid("unknown record tag")),
+        };
+        records.push(TraceRecord { time, pad, kind });
+    }
+
+    Ok(records)
+}
+
+fn read_csv(reader: impl BufRead) -> io::Result<Vec<TraceRecord>> {
+    let mut records = Vec::new();
+
+    for line in reader.lines().skip(1) {
+        let line = line?;
+        let fields: Vec<&str> = line.splitn(9, ',').collect();
+        let [time, pad, kind, pts, dts, duration, flags, size, detail] = fields[..] else {
+            return Err(invalid("short CSV line"));
+        };
+        let opt = |v: &str| v.parse().ok();
+        let time = time.parse().map_err(|_| invalid("bad time"))?;
+
+        let kind = if kind == "buffer" {
+            TraceKind::Buffer {
+                pts: opt(pts),
+                dts: opt(dts),
+                duration: opt(duration),
+                flags: flags.parse().unwrap_or_default(),
+                size: size.parse().unwrap_or_default(),
+            }
+        } else {
+            TraceKind::Event {
+                name: kind.to_string(),
+                detail: unescape_detail(detail),
+            }
+        };
+        records.push(TraceRecord {
+            time,
+            pad: pad.to_string(),
+            kind,
+        });
+    }
+
+    Ok(records)
+}
+
+/// Reads a CSV or binary trace, the format is detected from the content
+pub fn read_trace(path: &Path) -> io::Result<Vec<TraceRecord>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut magic = [0; 8];
+    let is_binary = reader.read_exact(&mut magic).is_ok() && &magic == MAGIC;
+
+    if is_binary {
+        read_binary(&mut reader)
+    } else {
+        read_csv(BufReader::new(File::open(path)?))
+    }
+}
+
+fn format_ns(value: Option<u64>) -> String {
+    value.map_or_else(
+        || "none".to_string(),
+        |v| gst::ClockTime::from_nseconds(v).to_string(),
+    )
+}
+
+/// Prints the records grouped per pad in arrival order, marking where a buffer does not start
+/// where the previous one ended
+pub fn print_timeline(records: &[TraceRecord]) {
+    let mut pads: BTreeMap<&str, Vec<&TraceRecord>> = BTreeMap::new();
+    for record in records {
+        pads.entry(&record.pad).or_default().push(record);
+    }
+
+    for (pad, records) in pads {
+        println!("== {pad} ({} records)", records.len());
+        // End of the previous buffer, forgotten after segments and flushes
+        let mut expected: Option<u64> = None;
+
+        for record in records {
+            let time = gst::ClockTime::from_nseconds(record.time);
+            match &record.kind {
+                TraceKind::Buffer {
+                    pts,
+                    dts,
+                    duration,
+                    flags,
+                    size,
+                } => {
+                    let mark = match (expected, pts) {
+                        (Some(expected), Some(pts)) if pts.abs_diff(expected) > GAP_TOLERANCE => {
+                            if *pts > expected {
+                                format!("  GAP {}", gst::ClockTime::from_nseconds(pts - expected))
+                            } else {
+                                format!(
+                                    "  OVERLAP {}",
+                                    gst::ClockTime::from_nseconds(expected - pts)
+                                )
+                            }
+                        }
+                        _ => String::new(),
+                    };
+                    println!(
+                        "{time:.6} buffer pts {} dts {} duration {} flags {:#x} size {size}{mark}",
+                        format_ns(*pts),
+                        format_ns(*dts),
+                        format_ns(*duration),
+                        flags
+                    );
+                    expected = pts.zip(*duration).map(|(pts, duration)| pts + duration);
+                }
+                TraceKind::Event { name, detail } => {
+                    if matches!(name.as_str(), "segment" | "flush-stop") {
+                        expected = None;
+                    }
+                    println!("{time:.6} {name} {detail}");
+                }
+            }
+        }
+    }
+}
+
+/// Prints the timeline of a trace written by `BufferTracer`, e.g. one made with
+/// `BUFFER_TRACE=trace.csv` by `basic_tutorial_8`
+pub fn tutorial_main() -> Result<(), Error> {
+    let Some(path) = env::args().nth(1) else {
+        eprintln!("Usage: buffer_tracer <trace.csv|trace.bin>");
+        return Ok(());
+    };
+
+    let records = read_trace(Path::new(&path))?;
+    print_timeline(&records);
+
+    Ok(())
+}
+
+/// `BufferTracer::attach` to the file in `BUFFER_TRACE`, if set
+pub fn attach_from_env(pipeline: &gst::Bin) -> Option<BufferTracer> {
+    let path = env::var("BUFFER_TRACE").ok()?;
+    match BufferTracer::attach(pipeline, Path::new(&path)) {
+        Ok(tracer) => {
+            println!("Tracing buffers to {path}");
+            Some(tracer)
+        }
+        Err(err) => {
+            eprintln!("Failed to create buffer trace {path}: {err}");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    fn records() -> Vec<TraceRecord> {
+        vec![
+            TraceRecord {
+                time: 0,
+                pad: "src.src".to_string(),
+                kind: TraceKind::Event {
+                    name: "caps".to_string(),
+                    detail: "audio/x-raw, format=(string)S16LE, channels=(int)2".to_string(),
+                },
+            },
+            TraceRecord {
+                time: 1_000,
+                pad: "src.src".to_string(),
+                kind: TraceKind::Buffer {
+                    pts: Some(0),
+                    dts: None,
+                    duration: Some(23_219_954),
+                    flags: gst::BufferFlags::DISCONT.bits(),
+                    size: 4096,
+                },
+            },
+            TraceRecord {
+                time: 2_000,
+                pad: "sink.sink".to_string(),
+                kind: TraceKind::Buffer {
+                    pts: None,
+                    dts: None,
+                    duration: None,
+                    flags: 0,
+                    size: 0,
+                },
+            },
+            TraceRecord {
+                time: 3_000,
+                pad: "sink.sink".to_string(),
+                kind: TraceKind::Event {
+                    name: "tag".to_string(),
+                    detail: "title=a, b\nsecond line\r\nback\\slash\\n".to_string(),
+                },
+            },
+            TraceRecord {
+                time: 4_000,
+                pad: "src.src".to_string(),
+                kind: TraceKind::Event {
+                    name: "eos".to_string(),
+                    detail: String::new(),
+                },
+            },
+        ]
+    }
+
+    fn round_trip(extension: &str) -> Vec<TraceRecord> {
+        let path =
+            env::temp_dir().join(format!("gstream_prac_trace_{}.{extension}", process::id()));
+        let mut sink = Sink::create(&path).unwrap();
+        for record in records() {
+            sink.write(&record).unwrap();
+        }
+        sink.flush().unwrap();
+        drop(sink);
+
+        let read = read_trace(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        read
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        assert_eq!(round_trip("bin"), records());
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        assert_eq!(round_trip("csv"), records());
+    }
+
+    #[test]
+    fn csv_detail_stays_on_one_line() {
+        let detail = "a, b\nc\\nd";
+        let escaped = escape_detail(detail);
+        assert!(!escaped.contains('\n'));
+        assert_eq!(unescape_detail(&escaped), detail);
+    }
+}
@@ -0,0 +1,247 @@
+use std::fmt;
+
+use gstreamer as gst;
+
+use glib::FlagsClass;
+use gst::prelude::*;
+
+/// Answer to a `gst::query::Buffering` on the pipeline
+#[derive(Debug, Clone)]
+pub struct BufferingStatus {
+    pub percent: i32,
+    pub mode: gst::BufferingMode,
+    /// Average input and output rates in bytes per second, -1 when unknown
+    pub avg_in: i32,
+    pub avg_out: i32,
+    /// Estimated time until buffering is done, None when unknown
+    pub time_left: Option<gst::ClockTime>,
+    /// Ranges already downloaded, in percent of the whole file (download mode only)
+    pub ranges: Vec<(i64, i64)>,
+}
+
+impl BufferingStatus {
+    pub fn query(pipeline: &gst::Element) -> Option<BufferingStatus> {
+        let mut query = gst::query::Buffering::new(gst::Format::Percent);
+        if !pipeline.query(&mut query) {
+            return None;
+        }
+
+        let (_busy, percent) = query.percent();
+        let (mode, avg_in, avg_out, buffering_left) = query.stats();
+        let ranges = query
+            .ranges()
+            .into_iter()
+            // Percent values are scaled to gst::format::Percent::MAX (1 000 000)
+            .map(|(start, stop)| (start.value() / 10_000, stop.value() / 10_000))
+            .collect();
+
+        Some(BufferingStatus {
+            percent,
+            mode,
+            avg_in,
+            avg_out,
+            // Milliseconds, -1 is unknown
+            time_left: u64::try_from(buffering_left).ok().map(gst::ClockTime::from_mseconds),
+            ranges,
+        })
+    }
+}
+
+impl fmt::Display for BufferingStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Buffering {}% ({:?}", self.percent, self.mode)?;
+        if let Some(time_left) = self.time_left {
+            write!(f, ", {time_left:.1} left")?;
+        }
+        if self.avg_in > 0 && self.avg_out > 0 {
+            write!(f, ", in {} kB/s, out {} kB/s", self.avg_in / 1000, self.avg_out / 1000)?;
+        }
+        if !self.ranges.is_empty() {
+            let ranges: Vec<String> = self
+                .ranges
+                .iter()
+                .map(|(start, stop)| format!("{start}-{stop}%"))
+                .collect();
+            write!(f, ", downloaded {}", ranges.join(" "))?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Pauses a non-live pipeline while its queues refill and resumes it once buffering reaches
+/// 100%. Live pipelines are never paused, they would just fall further behind.
+#[derive(Debug)]
+pub struct BufferingController {
+    pipeline: gst::Element,
+    /// State the pipeline should be in when it is not buffering
+    target: gst::State,
+    buffering: bool,
+    /// Set when a state change returned `NoPreroll`, which only live pipelines do
+    no_preroll: bool,
+}
+
+impl BufferingController {
+    pub fn new(pipeline: &gst::Element) -> BufferingController {
+        BufferingController {
+            pipeline: pipeline.clone(),
+            target: gst::State::Playing,
+            buffering: false,
+            no_preroll: false,
+        }
+    }
+
+    pub fn is_buffering(&self) -> bool {
+        self.buffering
+    }
+
+    /// Changes the state the pipeline goes to, deferred until buffering is done
+    pub fn set_target_state(&mut self, state: gst::State) -> Result<(), gst::StateChangeError> {
+        self.target = state;
+        if !self.buffering {
+            self.set_state(state)?;
+        }
+        Ok(())
+    }
+
+    fn set_state(&mut self, state: gst::State) -> Result<(), gst::StateChangeError> {
+        let success = self.pipeline.set_state(state)?;
+        self.no_preroll = success == gst::StateChangeSuccess::NoPreroll;
+        Ok(())
+    }
+
+    /// Asked on every message, the source can change when a new URI is set
+    fn is_live(&self) -> bool {
+        if self.no_preroll {
+            return true;
+        }
+        let mut query = gst::query::Latency::new();
+        self.pipeline.query(&mut query) && query.result().0
+    }
+
+    /// Handles `Buffering` and `ClockLost` messages, returns false for every other message
+    pub fn handle_message(&mut self, message: &gst::Message) -> bool {
+        use gst::MessageView;
+
+        match message.view() {
+            MessageView::Buffering(buffering) => {
+                if self.is_live() {
+                    return true;
+                }
+
+                let percent = buffering.percent();
+                match BufferingStatus::query(&self.pipeline) {
+                    Some(status) => print!("\r{status}    "),
+                    None => print!("\rBuffering {percent}%    "),
+                }
+
+                if percent < 100 && !self.buffering {
+                    self.buffering = true;
+                    if self.target == gst::State::Playing {
+                        let _ = self.set_state(gst::State::Paused);
+                    }
+                } else if percent >= 100 && self.buffering {
+                    println!("\rBuffering done");
+                    self.buffering = false;
+                    let _ = self.set_state(self.target);
+                }
+                true
+            }
+            MessageView::ClockLost(..) => {
+                // Get a new clock by going through PAUSED
+                let _ = self.set_state(gst::State::Paused);
+                if !self.buffering {
+                    let _ = self.set_state(self.target);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Sets the `download` flag of playbin: network streams are saved to a temporary file and
+/// the buffering messages report how much of the whole file is there
+pub fn enable_progressive_download(playbin: &gst::Element) {
+    let flags = playbin.property_value("flags");
+    let flags_class = FlagsClass::with_type(flags.type_()).unwrap();
+
+    let flags = flags_class
+        .builder_with_value(flags)
+        .unwrap()
+        .set_by_nick("download")
+        .build()
+        .unwrap();
+    playbin.set_property_from_value("flags", &flags);
+}
+
+/// `enable_progressive_download` when `PLAYBIN_DOWNLOAD=1`
+pub fn progressive_download_from_env(playbin: &gst::Element) {
+    if std::env::var("PLAYBIN_DOWNLOAD").is_ok_and(|value| value == "1") {
+        enable_progressive_download(playbin);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+    use crate::http_server::{HttpServer, ServerConfig};
+
+    #[test]
+    fn pauses_and_resumes_slow_download() {
+        gst::init().unwrap();
+        let dir = env::temp_dir().join(format!("gstream_prac_buffering_{}", process::id()));
+        // 2 seconds of 44.1 kHz stereo, about 350 kB
+        crate::playlist::generate_test_tracks(&dir, 1).unwrap();
+        // Slower than the audio plays, the queues run dry
+        let config = ServerConfig {
+            bandwidth: Some(64 * 1024),
+            ..Default::default()
+        };
+        let server = HttpServer::start(&dir, config).unwrap();
+
+        let sink = gst::ElementFactory::make("fakesink")
+            .property("sync", true)
+            .build()
+            .unwrap();
+        let playbin = gst::ElementFactory::make("playbin")
+            .property("uri", server.uri("track01.wav"))
+            .property("audio-sink", &sink)
+            .build()
+            .unwrap();
+        let mut controller = BufferingController::new(&playbin);
+        controller.set_target_state(gst::State::Playing).unwrap();
+
+        let bus = playbin.bus().unwrap();
+        let (mut paused, mut resumed, mut eos) = (false, false, false);
+        while let Some(message) = bus.timed_pop(gst::ClockTime::from_seconds(30)) {
+            use gst::MessageView;
+
+            let was_buffering = controller.is_buffering();
+            if controller.handle_message(&message) {
+                match (was_buffering, controller.is_buffering()) {
+                    (false, true) => paused = true,
+                    (true, false) => resumed = true,
+                    _ => (),
+                }
+                continue;
+            }
+            match message.view() {
+                MessageView::Error(err) => panic!("{}", err.error()),
+                MessageView::Eos(..) => {
+                    eos = true;
+                    break;
+                }
+                _ => (),
+            }
+        }
+        playbin.set_state(gst::State::Null).unwrap();
+
+        assert!(paused, "never paused for buffering");
+        assert!(resumed, "never resumed after buffering");
+        assert!(eos, "did not play to the end");
+        // A file over HTTP is not live, the controller had to act on it
+        assert!(!controller.is_live());
+    }
+}
@@ -0,0 +1,177 @@
+use std::{env, f32::consts::FRAC_1_SQRT_2};
+
+use gstreamer as gst;
+use gstreamer_audio as gst_audio;
+
+use gst_audio::AudioChannelPosition as Position;
+
+/// Speaker layouts the generators can produce and the audio branches can mix to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelLayout {
+    Mono,
+    Stereo,
+    Surround51,
+    Surround71,
+}
+
+impl ChannelLayout {
+    /// `mono`, `stereo`, `5.1` or `7.1`
+    pub fn parse(value: &str) -> Option<ChannelLayout> {
+        match value {
+            "mono" | "1" => Some(ChannelLayout::Mono),
+            "stereo" | "2" => Some(ChannelLayout::Stereo),
+            "5.1" | "6" => Some(ChannelLayout::Surround51),
+            "7.1" | "8" => Some(ChannelLayout::Surround71),
+            _ => None,
+        }
+    }
+
+    /// Reads the layout from the environment variable `name`, `default` when unset or unknown
+    pub fn from_env(name: &str, default: ChannelLayout) -> ChannelLayout {
+        let Ok(value) = env::var(name) else {
+            return default;
+        };
+        ChannelLayout::parse(&value).unwrap_or_else(|| {
+            eprintln!("Unknown channel layout {value} in {name}, using {default:?}");
+            default
+        })
+    }
+
+    /// Positions in GStreamer's canonical order, the order of the samples in a frame
+    pub fn positions(self) -> &'static [Position] {
+        match self {
+            ChannelLayout::Mono => &[Position::Mono],
+            ChannelLayout::Stereo => &[Position::FrontLeft, Position::FrontRight],
+            ChannelLayout::Surround51 => &[
+                Position::FrontLeft,
+                Position::FrontRight,
+                Position::FrontCenter,
+                Position::Lfe1,
+                Position::RearLeft,
+                Position::RearRight,
+            ],
+            ChannelLayout::Surround71 => &[
+                Position::FrontLeft,
+                Position::FrontRight,
+                Position::FrontCenter,
+                Position::Lfe1,
+                Position::RearLeft,
+                Position::RearRight,
+                Position::SideLeft,
+                Position::SideRight,
+            ],
+        }
+    }
+
+    pub fn channels(self) -> u32 {
+        self.positions().len() as u32
+    }
+
+    /// What to listen to it on: surround is previewed in stereo, the rest as it is
+    pub fn preview(self) -> ChannelLayout {
+        match self {
+            ChannelLayout::Surround51 | ChannelLayout::Surround71 => ChannelLayout::Stereo,
+            layout => layout,
+        }
+    }
+
+    /// Raw audio of this layout with the positions set explicitly
+    pub fn audio_info(
+        self,
+        format: gst_audio::AudioFormat,
+        rate: u32,
+    ) -> Result<gst_audio::AudioInfo, glib::BoolError> {
+        gst_audio::AudioInfo::builder(format, rate, self.channels())
+            .positions(self.positions())
+            .build()
+    }
+
+    /// Caps that only fix the channels, for a capsfilter behind an `audioconvert`
+    pub fn caps(self) -> gst::Caps {
+        let builder = gst::Caps::builder("audio/x-raw").field("channels", self.channels() as i32);
+        match Position::positions_to_mask(self.positions(), true) {
+            Ok(mask) if self.channels() > 1 => builder
+                .field("channel-mask", gst::Bitmask::new(mask))
+                .build(),
+            _ => builder.build(),
+        }
+    }
+}
+
+fn find(to: &[Position], candidates: &[Position]) -> Option<usize> {
+    to.iter().position(|p| candidates.contains(p))
+}
+
+/// Output channels an input channel ends up in, with their gains
+fn fold(position: Position, to: &[Position]) -> Vec<(usize, f32)> {
+    if let Some(index) = find(to, &[position]) {
+        return vec![(index, 1.0)];
+    }
+    let scaled = |targets: Vec<(usize, f32)>| -> Vec<(usize, f32)> {
+        targets
+            .into_iter()
+            .map(|(index, gain)| (index, gain * FRAC_1_SQRT_2))
+            .collect()
+    };
+
+    match position {
+        // Center goes to a center speaker, otherwise to both fronts at -3 dB
+        Position::Mono | Position::FrontCenter => {
+            match find(to, &[Position::FrontCenter, Position::Mono]) {
+                Some(index) => vec![(index, 1.0)],
+                None => [Position::FrontLeft, Position::FrontRight]
+                    .iter()
+                    .filter_map(|&p| find(to, &[p]))
+                    .map(|index| (index, FRAC_1_SQRT_2))
+                    .collect(),
+            }
+        }
+        // Only happens for a mono output
+        Position::FrontLeft | Position::FrontRight => scaled(fold(Position::FrontCenter, to)),
+        // Rear and side fold into each other, without either into the front at -3 dB
+        Position::RearLeft | Position::SideLeft => {
+            match find(to, &[Position::RearLeft, Position::SideLeft]) {
+                Some(index) => vec![(index, 1.0)],
+                None => scaled(fold(Position::FrontLeft, to)),
+            }
+        }
+        Position::RearRight | Position::SideRight => {
+            match find(to, &[Position::RearRight, Position::SideRight]) {
+                Some(index) => vec![(index, 1.0)],
+                None => scaled(fold(Position::FrontRight, to)),
+            }
+        }
+        // The LFE is dropped, most small speakers can't play it anyway
+        _ => Vec::new(),
+    }
+}
+
+/// Mix matrix from `from` to `to`, one row per output channel and one column per input
+/// channel as `audioconvert` wants it. Downmixing follows ITU-R BS.775: center and
+/// surrounds at -3 dB, LFE dropped. Upmixing only moves channels to the same speakers, a
+/// mono center goes to the center speaker, nothing is synthesized for the others. Rows
+/// adding up to more than 1 are scaled down so the mix can't clip.
+pub fn mix_matrix(from: ChannelLayout, to: ChannelLayout) -> Vec<Vec<f32>> {
+    let to_positions = to.positions();
+    let mut matrix = vec![vec![0.0; from.channels() as usize]; to_positions.len()];
+    for (input, &position) in from.positions().iter().enumerate() {
+        for (output, gain) in fold(position, to_positions) {
+            matrix[output][input] += gain;
+        }
+    }
+
+    for row in &mut matrix {
+        let sum: f32 = row.iter().sum();
+        if sum > 1.0 {
+            row.iter_mut().for_each(|gain| *gain /= sum);
+        }
+    }
+    matrix
+}
+
+/// Makes `convert` (an `audioconvert`) mix `from` to `to`. Put a capsfilter with
+/// `to.caps()` behind it, the matrix only applies when the output channels are fixed.
+pub fn set_mix_matrix(convert: &gst::Element, from: ChannelLayout, to: ChannelLayout) {
+    let matrix = gst::Array::new(mix_matrix(from, to).into_iter().map(gst::Array::new));
+    convert.set_property("mix-matrix", matrix);
+}
@@ -0,0 +1,492 @@
+use std::{
+    env,
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use gstreamer as gst;
+
+use anyhow::Error;
+use crossterm::event::KeyCode;
+use gst::prelude::*;
+
+use crate::input::{InputController, Keymap};
+
+const DEFAULT_WIDTH: i32 = 1280;
+const DEFAULT_HEIGHT: i32 = 720;
+/// Inputs used when none are given on the command line
+const DEFAULT_INPUTS: [&str; 4] = [
+    "pattern:smpte",
+    "pattern:ball",
+    "pattern:snow",
+    "pattern:pinwheel",
+];
+/// Alpha values `a` cycles through
+const ALPHAS: [f64; 3] = [1.0, 0.5, 0.0];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// The first input fills the frame, the others are small in the bottom right corner
+    PictureInPicture,
+    /// `columns` x `rows` cells, zero means worked out from the number of inputs
+    Grid { columns: usize, rows: usize },
+    /// All inputs next to each other in one row
+    SideBySide,
+}
+
+impl Layout {
+    /// `pip`, `side`, `grid` or `grid:COLUMNSxROWS`
+    pub fn parse(s: &str) -> Option<Layout> {
+        match s {
+            "pip" => Some(Layout::PictureInPicture),
+            "side" => Some(Layout::SideBySide),
+            "grid" => Some(Layout::Grid {
+                columns: 0,
+                rows: 0,
+            }),
+            _ => {
+                let (columns, rows) = s.strip_prefix("grid:")?.split_once('x')?;
+                Some(Layout::Grid {
+                    columns: columns.parse().ok()?,
+                    rows: rows.parse().ok()?,
+                })
+            }
+        }
+    }
+
+    pub fn next(self) -> Layout {
+        match self {
+            Layout::PictureInPicture => Layout::Grid {
+                columns: 0,
+                rows: 0,
+            },
+            Layout::Grid { .. } => Layout::SideBySide,
+            Layout::SideBySide => Layout::PictureInPicture,
+        }
+    }
+
+    /// Where each of `count` inputs goes in a `width` x `height` frame
+    pub fn geometry(self, count: usize, width: i32, height: i32) -> Vec<Geometry> {
+        match self {
+            Layout::PictureInPicture => {
+                let (small_width, small_height) = (width / 4, height / 4);
+                let margin = width / 64;
+                (0..count)
+                    .map(|i| match i {
+                        0 => Geometry {
+                            xpos: 0,
+                            ypos: 0,
+                            width,
+                            height,
+                            zorder: 0,
+                        },
+                        // Right to left along the bottom edge
+                        _ => Geometry {
+                            xpos: width - i as i32 * (small_width + margin),
+                            ypos: height - small_height - margin,
+                            width: small_width,
+                            height: small_height,
+                            zorder: i as u32,
+                        },
+                    })
+                    .collect()
+            }
+            Layout::Grid { columns, rows } => {
+                let columns = match columns {
+                    0 => (count as f64).sqrt().ceil().max(1.0) as usize,
+                    columns => columns,
+                };
+                let rows = match rows {
+                    0 => count.div_ceil(columns).max(1),
+                    rows => rows,
+                };
+                let (cell_width, cell_height) = (width / columns as i32, height / rows as i32);
+                // Inputs that don't fit in the grid end up below the frame, out of sight
+                (0..count)
+                    .map(|i| Geometry {
+                        xpos: (i % columns) as i32 * cell_width,
+                        ypos: (i / columns) as i32 * cell_height,
+                        width: cell_width,
+                        height: cell_height,
+                        zorder: i as u32,
+                    })
+                    .collect()
+            }
+            Layout::SideBySide => {
+                let count = count.max(1) as i32;
+                // Scaled down evenly so the aspect ratio is kept, centered vertically
+                let (cell_width, cell_height) = (width / count, height / count);
+                (0..count)
+                    .map(|i| Geometry {
+                        xpos: i * cell_width,
+                        ypos: (height - cell_height) / 2,
+                        width: cell_width,
+                        height: cell_height,
+                        zorder: i as u32,
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Position, size and stacking of one input in the output frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Geometry {
+    pub xpos: i32,
+    pub ypos: i32,
+    pub width: i32,
+    pub height: i32,
+    pub zorder: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Output {
+    /// Windows and the sound card
+    Display,
+    /// Matroska file with H.264 and Opus
+    File(PathBuf),
+    /// Synchronised fake sinks, for running without display or sound card
+    Fake,
+}
+
+/// Links `src_pad` through a bin made from `description` to `sink_pad`
+fn link_through(
+    pipeline: &gst::Bin,
+    src_pad: &gst::Pad,
+    description: &str,
+    sink_pad: &gst::Pad,
+) -> Result<(), Error> {
+    let bin = gst::parse_bin_from_description(description, true)?;
+    pipeline.add(&bin)?;
+    bin.sync_state_with_parent()?;
+    bin.static_pad("src").unwrap().link(sink_pad)?;
+    src_pad.link(&bin.static_pad("sink").unwrap())?;
+    Ok(())
+}
+
+const VIDEO_BRANCH: &str = "queue ! videoconvert ! videoscale";
+const AUDIO_BRANCH: &str = "queue ! audioconvert ! audioresample";
+
+/// Composes several URIs or test patterns into one picture with `compositor` and mixes their
+/// audio with `audiomixer`. Position, size and alpha of every input can be changed while
+/// playing.
+pub struct Compositor {
+    pipeline: gst::Pipeline,
+    /// Sink pads of `compositor`, one per input in the order given
+    video_pads: Vec<gst::Pad>,
+    width: i32,
+    height: i32,
+}
+
+impl Compositor {
+    /// `inputs` are URIs or `pattern:NAME` for a `videotestsrc` pattern with a test tone
+    pub fn new(
+        inputs: &[String],
+        output: &Output,
+        width: i32,
+        height: i32,
+    ) -> Result<Compositor, Error> {
+        let (video_sink, audio_sink) = match output {
+            Output::Display => (
+                "videoconvert ! autovideosink".to_string(),
+                "autoaudiosink".to_string(),
+            ),
+            Output::Fake => (
+                "fakesink sync=true".to_string(),
+                "fakesink sync=true".to_string(),
+            ),
+            Output::File(path) => (
+                format!(
+                    "videoconvert ! x264enc ! queue ! matroskamux name=mux ! filesink location=\"{}\"",
+                    path.display()
+                ),
+                "audioconvert ! opusenc ! queue ! mux.".to_string(),
+            ),
+        };
+        let pipeline = gst::parse_launch(&format!(
+            "compositor name=vmix background=black \
+             ! video/x-raw,width={width},height={height},framerate=30/1 ! {video_sink}  \
+             audiomixer name=amix ! {audio_sink}"
+        ))?
+        .downcast::<gst::Pipeline>()
+        .unwrap();
+        let video_mixer = pipeline.by_name("vmix").unwrap();
+        let audio_mixer = pipeline.by_name("amix").unwrap();
+
+        let mut video_pads = Vec::new();
+        for (i, input) in inputs.iter().enumerate() {
+            let video_pad = video_mixer
+                .request_pad_simple("sink_%u")
+                .ok_or_else(|| glib::bool_error!("Failed to request a compositor pad"))?;
+
+            match input.strip_prefix("pattern:") {
+                Some(pattern) => {
+                    let video = gst::ElementFactory::make("videotestsrc")
+                        .property_from_str("pattern", pattern)
+                        .build()?;
+                    // A different quiet tone for every input
+                    let audio = gst::ElementFactory::make("audiotestsrc")
+                        .property("freq", 220.0 * (i + 1) as f64)
+                        .property("volume", 0.1)
+                        .build()?;
+                    pipeline.add_many([&video, &audio])?;
+                    let audio_pad = audio_mixer
+                        .request_pad_simple("sink_%u")
+                        .ok_or_else(|| glib::bool_error!("Failed to request a mixer pad"))?;
+                    link_through(
+                        pipeline.upcast_ref(),
+                        &video.static_pad("src").unwrap(),
+                        VIDEO_BRANCH,
+                        &video_pad,
+                    )?;
+                    link_through(
+                        pipeline.upcast_ref(),
+                        &audio.static_pad("src").unwrap(),
+                        AUDIO_BRANCH,
+                        &audio_pad,
+                    )?;
+                }
+                None => {
+                    let decodebin = gst::ElementFactory::make("uridecodebin")
+                        .property("uri", input)
+                        .build()?;
+                    pipeline.add(&decodebin)?;
+
+                    // Only the first video and audio stream of every input is used
+                    let pipeline_weak = pipeline.downgrade();
+                    let video_pad_clone = video_pad.clone();
+                    let audio_mixer = audio_mixer.clone();
+                    let audio_linked = AtomicBool::new(false);
+                    decodebin.connect_pad_added(move |_decodebin, src_pad| {
+                        let Some(pipeline) = pipeline_weak.upgrade() else {
+                            return;
+                        };
+                        let Some(caps) = src_pad.current_caps() else {
+                            return;
+                        };
+                        let name = caps.structure(0).map_or("", |s| s.name().as_str());
+
+                        let result = if name.starts_with("video/") {
+                            if video_pad_clone.is_linked() {
+                                return;
+                            }
+                            link_through(
+                                pipeline.upcast_ref(),
+                                src_pad,
+                                VIDEO_BRANCH,
+                                &video_pad_clone,
+                            )
+                        } else if name.starts_with("audio/") {
+                            if audio_linked.swap(true, Ordering::SeqCst) {
+                                return;
+                            }
+                            let Some(audio_pad) = audio_mixer.request_pad_simple("sink_%u") else {
+                                return;
+                            };
+                            link_through(pipeline.upcast_ref(), src_pad, AUDIO_BRANCH, &audio_pad)
+                        } else {
+                            return;
+                        };
+                        if let Err(err) = result {
+                            eprintln!("Failed to link {name} of {}: {err}", src_pad.name());
+                        }
+                    });
+
+                    // Without a video stream the compositor would wait for this pad forever.
+                    // The released pad stays in `video_pads`, its properties just do nothing.
+                    let video_mixer = video_mixer.clone();
+                    let video_pad_clone = video_pad.clone();
+                    decodebin.connect_no_more_pads(move |_decodebin| {
+                        if !video_pad_clone.is_linked() {
+                            println!("No video in {}, releasing its pad", video_pad_clone.name());
+                            video_mixer.release_request_pad(&video_pad_clone);
+                        }
+                    });
+                }
+            }
+            video_pads.push(video_pad);
+        }
+
+        Ok(Compositor {
+            pipeline,
+            video_pads,
+            width,
+            height,
+        })
+    }
+
+    pub fn pipeline(&self) -> &gst::Pipeline {
+        &self.pipeline
+    }
+
+    pub fn input_count(&self) -> usize {
+        self.video_pads.len()
+    }
+
+    pub fn set_geometry(&self, input: usize, geometry: Geometry) {
+        let pad = &self.video_pads[input];
+        pad.set_property("xpos", geometry.xpos);
+        pad.set_property("ypos", geometry.ypos);
+        pad.set_property("width", geometry.width);
+        pad.set_property("height", geometry.height);
+        pad.set_property("zorder", geometry.zorder);
+    }
+
+    pub fn geometry(&self, input: usize) -> Geometry {
+        let pad = &self.video_pads[input];
+        Geometry {
+            xpos: pad.property("xpos"),
+            ypos: pad.property("ypos"),
+            width: pad.property("width"),
+            height: pad.property("height"),
+            zorder: pad.property("zorder"),
+        }
+    }
+
+    pub fn set_alpha(&self, input: usize, alpha: f64) {
+        self.video_pads[input].set_property("alpha", alpha.clamp(0.0, 1.0));
+    }
+
+    pub fn alpha(&self, input: usize) -> f64 {
+        self.video_pads[input].property("alpha")
+    }
+
+    pub fn set_layout(&self, layout: Layout) {
+        let geometry = layout.geometry(self.input_count(), self.width, self.height);
+        for (input, geometry) in geometry.into_iter().enumerate() {
+            self.set_geometry(input, geometry);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Action {
+    Select(usize),
+    NextLayout,
+    CycleAlpha,
+    Quit,
+}
+
+/// Composes the inputs given as arguments, four test patterns by default.
+/// `--layout=pip|grid|grid:CxR|side`, `--output=FILE.mkv`, `--headless` for fake sinks and
+/// `--duration=SECONDS` to stop on its own.
+pub fn tutorial_main() -> Result<(), Error> {
+    // Initialize GStreamer
+    gst::init()?;
+
+    let mut inputs = Vec::new();
+    let mut layout = Layout::Grid {
+        columns: 0,
+        rows: 0,
+    };
+    let mut output = Output::Display;
+    let mut duration = None;
+    for arg in env::args().skip(1) {
+        if let Some(value) = arg.strip_prefix("--layout=") {
+            layout =
+                Layout::parse(value).ok_or_else(|| glib::bool_error!("Unknown layout {value}"))?;
+        } else if let Some(value) = arg.strip_prefix("--output=") {
+            output = Output::File(PathBuf::from(value));
+        } else if arg == "--headless" {
+            output = Output::Fake;
+        } else if let Some(value) = arg.strip_prefix("--duration=") {
+            duration = Some(value.parse::<u32>()?);
+        } else {
+            inputs.push(arg);
+        }
+    }
+    if inputs.is_empty() {
+        inputs = DEFAULT_INPUTS.iter().map(|s| s.to_string()).collect();
+    }
+
+    let compositor = Compositor::new(&inputs, &output, DEFAULT_WIDTH, DEFAULT_HEIGHT)?;
+    compositor.set_layout(layout);
+    let pipeline = compositor.pipeline().clone();
+
+    let main_loop = glib::MainLoop::new(None, false);
+
+    // Handle keyboard input. An EOS instead of quitting right away lets the file be finished.
+    let keymap = Keymap::new()
+        .bind_digits(|digit| Action::Select(digit as usize))
+        .bind(KeyCode::Char('l'), Action::NextLayout)
+        .bind(KeyCode::Char('a'), Action::CycleAlpha)
+        .bind(KeyCode::Char('q'), Action::Quit);
+    let mut selected = 0;
+    let input = InputController::start(keymap, &main_loop, move |action| match action {
+        Action::Select(digit) => {
+            if (1..=compositor.input_count()).contains(&digit) {
+                selected = digit - 1;
+                println!(
+                    "Selected input {digit}: {:?}",
+                    compositor.geometry(selected)
+                );
+            }
+        }
+        Action::NextLayout => {
+            layout = layout.next();
+            println!("Layout {layout:?}");
+            compositor.set_layout(layout);
+        }
+        Action::CycleAlpha => {
+            let alpha = compositor.alpha(selected);
+            let next = ALPHAS
+                .iter()
+                .position(|a| (a - alpha).abs() < 0.01)
+                .map_or(ALPHAS[0], |i| ALPHAS[(i + 1) % ALPHAS.len()]);
+            println!("Alpha of input {} {next}", selected + 1);
+            compositor.set_alpha(selected, next);
+        }
+        Action::Quit => {
+            compositor.pipeline().send_event(gst::event::Eos::new());
+        }
+    });
+    let _input = match input {
+        Ok(input) => {
+            println!("Press 1-9 to select an input, l to change the layout, a to change the alpha, q to stop");
+            Some(input)
+        }
+        Err(err) => {
+            eprintln!("Failed to read keyboard input, running without controls: {err}");
+            None
+        }
+    };
+
+    if let Some(duration) = duration {
+        let pipeline_weak = pipeline.downgrade();
+        glib::timeout_add_seconds_local_once(duration, move || {
+            if let Some(pipeline) = pipeline_weak.upgrade() {
+                pipeline.send_event(gst::event::Eos::new());
+            }
+        });
+    }
+
+    let main_loop_clone = main_loop.clone();
+    let bus = pipeline.bus().unwrap();
+    let _bus_watch = bus.add_watch(move |_bus, message| {
+        use gst::MessageView;
+
+        match message.view() {
+            MessageView::Error(err) => {
+                eprintln!(
+                    "Error received from element {:?}: {}",
+                    err.src().map(|s| s.path_string()),
+                    err.error()
+                );
+                eprintln!("Debugging information: {:?}", err.debug());
+                main_loop_clone.quit();
+            }
+            MessageView::Eos(..) => main_loop_clone.quit(),
+            _ => (),
+        }
+        glib::ControlFlow::Continue
+    })?;
+
+    pipeline.set_state(gst::State::Playing)?;
+
+    main_loop.run();
+
+    pipeline.set_state(gst::State::Null)?;
+
+    Ok(())
+}
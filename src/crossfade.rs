@@ -0,0 +1,290 @@
+use std::{
+    env,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use gstreamer as gst;
+use gstreamer_controller as gst_controller;
+
+use anyhow::Error;
+use gst::prelude::*;
+use gst_controller::prelude::*;
+
+use crate::playlist;
+
+const DEFAULT_FADE_SECONDS: u64 = 5;
+
+/// One track playing into the mixer
+struct Deck {
+    index: usize,
+    bin: gst::Bin,
+    mixer_pad: gst::Pad,
+    /// Drives the `volume` of `mixer_pad`, in stream time of the track
+    volume: gst_controller::InterpolationControlSource,
+}
+
+struct State {
+    entries: Vec<String>,
+    /// Index of the track after the ones on the decks
+    next: usize,
+    /// The playing track first, during a crossfade the incoming one second
+    decks: Vec<Deck>,
+}
+
+/// Plays tracks one after the other through an `audiomixer`, fading each one out while the
+/// next one fades in. Every track gets its own `uridecodebin` branch and mixer pad, the
+/// volumes are ramped by interpolation control sources bound to the pads.
+#[derive(Clone)]
+pub struct Crossfader {
+    pipeline: gst::Pipeline,
+    mixer: gst::Element,
+    fade: gst::ClockTime,
+    state: Arc<Mutex<State>>,
+}
+
+impl Crossfader {
+    pub fn new(entries: Vec<String>, fade: gst::ClockTime) -> Result<Crossfader, Error> {
+        if entries.is_empty() {
+            return Err(glib::bool_error!("The playlist is empty").into());
+        }
+
+        let pipeline = gst::parse_launch(
+            "audiomixer name=mixer ! audioconvert ! audioresample ! autoaudiosink",
+        )?
+        .downcast::<gst::Pipeline>()
+        .unwrap();
+        let mixer = pipeline.by_name("mixer").unwrap();
+
+        Ok(Crossfader {
+            pipeline,
+            mixer,
+            fade,
+            state: Arc::new(Mutex::new(State {
+                entries,
+                next: 0,
+                decks: Vec::new(),
+            })),
+        })
+    }
+
+    pub fn pipeline(&self) -> &gst::Pipeline {
+        &self.pipeline
+    }
+
+    /// Starts the first track at full volume and sets the pipeline to PLAYING
+    pub fn start(&self) -> Result<(), Error> {
+        self.start_next(None)?;
+        self.pipeline.set_state(gst::State::Playing)?;
+        Ok(())
+    }
+
+    /// Adds a deck for the next track. With a fade length the track fades in over it,
+    /// otherwise it starts at full volume. Returns false at the end of the playlist.
+    fn start_next(&self, fade: Option<gst::ClockTime>) -> Result<bool, Error> {
+        let (index, uri) = {
+            let mut state = self.state.lock().unwrap();
+            if state.next >= state.entries.len() {
+                return Ok(false);
+            }
+            state.next += 1;
+            (state.next - 1, state.entries[state.next - 1].clone())
+        };
+
+        // Only audio streams are decoded, video in the files is ignored
+        let bin = gst::parse_bin_from_description(
+            &format!("uridecodebin caps=audio/x-raw uri=\"{uri}\" ! audioconvert ! audioresample"),
+            true,
+        )?;
+        let mixer_pad = self
+            .mixer
+            .request_pad_simple("sink_%u")
+            .ok_or_else(|| glib::bool_error!("Failed to request a mixer pad"))?;
+
+        let volume = gst_controller::InterpolationControlSource::new();
+        volume.set_property("mode", gst_controller::InterpolationMode::Linear);
+        let binding =
+            gst_controller::DirectControlBinding::new_absolute(&mixer_pad, "volume", &volume);
+        mixer_pad.add_control_binding(&binding)?;
+        match fade {
+            Some(fade) => {
+                volume.set(gst::ClockTime::ZERO, 0.0);
+                volume.set(fade, 1.0);
+            }
+            None => {
+                volume.set(gst::ClockTime::ZERO, 1.0);
+            }
+        }
+
+        // The track starts at running time 0, move it to now so the mixer doesn't drop it
+        // as late
+        if let Some(now) = self.pipeline.current_running_time() {
+            mixer_pad.set_offset(now.nseconds() as i64);
+        }
+
+        let src_pad = bin.static_pad("src").unwrap();
+        let this = self.clone();
+        let deck_bin = bin.clone();
+        src_pad.add_probe(
+            gst::PadProbeType::EVENT_DOWNSTREAM,
+            move |_pad, info| match info.data {
+                Some(gst::PadProbeData::Event(ref event))
+                    if event.type_() == gst::EventType::Eos =>
+                {
+                    this.deck_finished(&deck_bin)
+                }
+                _ => gst::PadProbeReturn::Ok,
+            },
+        );
+
+        self.pipeline.add(&bin)?;
+        src_pad.link(&mixer_pad)?;
+        bin.sync_state_with_parent()?;
+
+        println!("Track {}: {uri}", index + 1);
+        self.state.lock().unwrap().decks.push(Deck {
+            index,
+            bin,
+            mixer_pad,
+            volume,
+        });
+        Ok(true)
+    }
+
+    /// Called from the EOS of a deck. The last track lets the EOS through to end the
+    /// pipeline, every other deck is taken out.
+    fn deck_finished(&self, bin: &gst::Bin) -> gst::PadProbeReturn {
+        let (deck, start_next) = {
+            let mut state = self.state.lock().unwrap();
+            let Some(position) = state.decks.iter().position(|deck| &deck.bin == bin) else {
+                return gst::PadProbeReturn::Ok;
+            };
+            let has_more = state.next < state.entries.len();
+            if state.decks.len() == 1 && !has_more {
+                return gst::PadProbeReturn::Ok;
+            }
+            // Ended before the crossfade started, e.g. shorter than the fade
+            let start_next = state.decks.len() == 1;
+            (state.decks.remove(position), start_next)
+        };
+
+        let this = self.clone();
+        deck.bin.call_async(move |bin| {
+            let _ = bin.set_state(gst::State::Null);
+            let _ = this.pipeline.remove(bin);
+            this.mixer.release_request_pad(&deck.mixer_pad);
+            if start_next {
+                if let Err(err) = this.start_next(None) {
+                    eprintln!("Failed to start the next track: {err}");
+                }
+            }
+        });
+        gst::PadProbeReturn::Drop
+    }
+
+    /// Starts the crossfade when the playing track is about to end, call it a few times a
+    /// second
+    pub fn tick(&self) -> Result<(), Error> {
+        let (position, duration) = {
+            let state = self.state.lock().unwrap();
+            if state.decks.len() != 1 || state.next >= state.entries.len() {
+                return Ok(());
+            }
+            let pad = &state.decks[0].mixer_pad;
+            match (
+                pad.peer_query_position::<gst::ClockTime>(),
+                pad.peer_query_duration::<gst::ClockTime>(),
+            ) {
+                (Some(position), Some(duration)) => (position, duration),
+                _ => return Ok(()),
+            }
+        };
+        let remaining = duration.saturating_sub(position);
+        if remaining > self.fade {
+            return Ok(());
+        }
+
+        {
+            let state = self.state.lock().unwrap();
+            let deck = &state.decks[0];
+            println!("Fading out track {} over {remaining:.1}", deck.index + 1);
+            deck.volume.set(position, 1.0);
+            deck.volume.set(duration, 0.0);
+        }
+        self.start_next(Some(remaining))?;
+        Ok(())
+    }
+}
+
+/// Crossfades through the playlist, directory or file given as first argument, or a few
+/// generated tones. `--fade=SECONDS` sets the crossfade length.
+pub fn tutorial_main() -> Result<(), Error> {
+    // Initialize GStreamer
+    gst::init()?;
+
+    let mut location = None;
+    let mut fade = gst::ClockTime::from_seconds(DEFAULT_FADE_SECONDS);
+    for arg in env::args().skip(1) {
+        match arg.strip_prefix("--fade=") {
+            Some(seconds) => fade = gst::ClockTime::from_seconds_f64(seconds.parse()?),
+            None => location = Some(arg),
+        }
+    }
+
+    let entries = match location {
+        Some(location) => playlist::load(&location)?,
+        None => {
+            let dir = env::temp_dir().join("gstream_prac_playlist");
+            println!(
+                "No playlist given, playing tones generated in {}",
+                dir.display()
+            );
+            playlist::generate_test_tracks(&dir, 4)?;
+            playlist::load(&dir.to_string_lossy())?
+        }
+    };
+
+    let crossfader = Crossfader::new(entries, fade)?;
+    let main_loop = glib::MainLoop::new(None, false);
+
+    let crossfader_clone = crossfader.clone();
+    let tick = glib::timeout_add_local(Duration::from_millis(100), move || {
+        if let Err(err) = crossfader_clone.tick() {
+            eprintln!("Failed to start the crossfade: {err}");
+        }
+        glib::ControlFlow::Continue
+    });
+
+    let main_loop_clone = main_loop.clone();
+    let bus = crossfader.pipeline().bus().unwrap();
+    let _bus_watch = bus.add_watch(move |_bus, message| {
+        use gst::MessageView;
+
+        match message.view() {
+            MessageView::Error(err) => {
+                eprintln!(
+                    "Error received from element {:?}: {}",
+                    err.src().map(|s| s.path_string()),
+                    err.error()
+                );
+                eprintln!("Debugging information: {:?}", err.debug());
+                main_loop_clone.quit();
+            }
+            MessageView::Eos(..) => {
+                println!("End of playlist");
+                main_loop_clone.quit();
+            }
+            _ => (),
+        }
+        glib::ControlFlow::Continue
+    })?;
+
+    crossfader.start()?;
+
+    main_loop.run();
+
+    tick.remove();
+    crossfader.pipeline().set_state(gst::State::Null)?;
+
+    Ok(())
+}
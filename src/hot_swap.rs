@@ -0,0 +1,210 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+
+use gstreamer as gst;
+
+use anyhow::Error;
+use gst::prelude::*;
+
+/// The elements from `first` to `last`, following the `src` pads. Fails when the chain
+/// branches off or does not reach `last`.
+fn linear_segment(first: &gst::Element, last: &gst::Element) -> Result<Vec<gst::Element>, Error> {
+    let mut segment = vec![first.clone()];
+    while segment.last() != Some(last) {
+        let current = segment.last().unwrap();
+        if current.src_pads().len() != 1 {
+            return Err(glib::bool_error!("{} is not linear", current.name()).into());
+        }
+        let next = current
+            .static_pad("src")
+            .and_then(|pad| pad.peer())
+            .and_then(|peer| peer.parent_element())
+            .ok_or_else(|| {
+                glib::bool_error!("{} is not linked to {}", first.name(), last.name())
+            })?;
+        segment.push(next);
+    }
+    Ok(segment)
+}
+
+/// Checks that `replacement` can take the caps flowing into the segment now and produce caps
+/// downstream accepts, before anything is touched
+fn check_caps(
+    upstream: &gst::Pad,
+    downstream: Option<&gst::Pad>,
+    replacement: &gst::Element,
+) -> Result<(), Error> {
+    let sink = replacement
+        .static_pad("sink")
+        .ok_or_else(|| glib::bool_error!("{} has no sink pad", replacement.name()))?;
+    if let Some(caps) = upstream.current_caps() {
+        if !sink.query_caps(None).can_intersect(&caps) {
+            return Err(glib::bool_error!("{} does not accept {caps}", replacement.name()).into());
+        }
+    }
+
+    let Some(downstream) = downstream else {
+        return Ok(());
+    };
+    let src = replacement
+        .static_pad("src")
+        .ok_or_else(|| glib::bool_error!("{} has no src pad", replacement.name()))?;
+    let accepted = downstream.query_caps(None);
+    if !src.query_caps(None).can_intersect(&accepted) {
+        return Err(glib::bool_error!(
+            "{} produces nothing {} accepts",
+            replacement.name(),
+            downstream.name()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Called once the swap is done, or failed halfway through
+type Done = Box<dyn FnOnce(Result<(), Error>) + Send + 'static>;
+
+/// Replaces `old` with `replacement` in a running pipeline, see [`swap_segment`]
+pub fn swap_element<F>(
+    pipeline: &gst::Bin,
+    old: &gst::Element,
+    replacement: &gst::Element,
+    done: F,
+) -> Result<(), Error>
+where
+    F: FnOnce(Result<(), Error>) + Send + 'static,
+{
+    swap_segment(pipeline, old, old, replacement, done)
+}
+
+/// Replaces the linked elements from `first` to `last` with `replacement` (an element or a
+/// bin with `sink` and, unless `last` is a sink, `src` pads) without stopping the pipeline.
+///
+/// The pad feeding `first` is blocked, then an EOS pushes the data still inside the segment
+/// out of it. When the EOS comes out of `last` the segment is removed, `replacement` takes
+/// its place and the pad is unblocked again. A segment ending in a sink is swapped right away,
+/// nothing downstream is waiting for its data.
+///
+/// Returns once the swap is scheduled, it completes when data flows into the segment. `done`
+/// is called from another thread then, until that the old elements are still in place.
+pub fn swap_segment<F>(
+    pipeline: &gst::Bin,
+    first: &gst::Element,
+    last: &gst::Element,
+    replacement: &gst::Element,
+    done: F,
+) -> Result<(), Error>
+where
+    F: FnOnce(Result<(), Error>) + Send + 'static,
+{
+    let segment = linear_segment(first, last)?;
+    let first_sink = first
+        .static_pad("sink")
+        .ok_or_else(|| glib::bool_error!("{} has no sink pad", first.name()))?;
+    let upstream = first_sink
+        .peer()
+        .ok_or_else(|| glib::bool_error!("{} is not linked upstream", first.name()))?;
+    let last_src = last.static_pad("src");
+    let downstream = last_src.as_ref().and_then(|pad| pad.peer());
+    check_caps(&upstream, downstream.as_ref(), replacement)?;
+
+    let swap = Swap {
+        pipeline: pipeline.clone(),
+        segment,
+        replacement: replacement.clone(),
+        upstream: upstream.clone(),
+        downstream,
+        block: Arc::new(Mutex::new(None)),
+        done: Arc::new(Mutex::new(Some(Box::new(done)))),
+    };
+
+    let started = AtomicBool::new(false);
+    upstream.add_probe(gst::PadProbeType::BLOCK_DOWNSTREAM, move |_pad, info| {
+        // Returning Ok keeps the pad blocked until the swap removes the probe
+        if started.swap(true, Ordering::SeqCst) {
+            return gst::PadProbeReturn::Ok;
+        }
+        *swap.block.lock().unwrap() = info.id.take();
+
+        match &last_src {
+            Some(last_src) => {
+                let swap = swap.clone();
+                last_src.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |_pad, info| {
+                    let Some(gst::PadProbeData::Event(ref event)) = info.data else {
+                        return gst::PadProbeReturn::Ok;
+                    };
+                    if event.type_() != gst::EventType::Eos {
+                        return gst::PadProbeReturn::Ok;
+                    }
+                    swap.run();
+                    // Downstream keeps playing, it must not see the EOS
+                    gst::PadProbeReturn::Drop
+                });
+                first_sink.send_event(gst::event::Eos::new());
+            }
+            None => swap.run(),
+        }
+        gst::PadProbeReturn::Ok
+    });
+
+    Ok(())
+}
+
+#[derive(Clone)]
+struct Swap {
+    pipeline: gst::Bin,
+    segment: Vec<gst::Element>,
+    replacement: gst::Element,
+    upstream: gst::Pad,
+    downstream: Option<gst::Pad>,
+    /// Probe blocking `upstream`
+    block: Arc<Mutex<Option<gst::PadProbeId>>>,
+    done: Arc<Mutex<Option<Done>>>,
+}
+
+impl Swap {
+    /// State changes from the streaming threads of the segment would deadlock, so the swap
+    /// runs on another thread
+    fn run(&self) {
+        let swap = self.clone();
+        self.pipeline.call_async(move |pipeline| {
+            let result = swap.relink(pipeline);
+            if let Some(block) = swap.block.lock().unwrap().take() {
+                swap.upstream.remove_probe(block);
+            }
+            if let Some(done) = swap.done.lock().unwrap().take() {
+                done(result);
+            }
+        });
+    }
+
+    fn relink(&self, pipeline: &gst::Bin) -> Result<(), Error> {
+        for element in &self.segment {
+            element.set_state(gst::State::Null)?;
+        }
+        pipeline.remove_many(&self.segment)?;
+
+        pipeline.add(&self.replacement)?;
+        self.replacement.sync_state_with_parent()?;
+        self.upstream
+            .link(&self.replacement.static_pad("sink").unwrap())?;
+        if let Some(downstream) = &self.downstream {
+            self.replacement
+                .static_pad("src")
+                .unwrap()
+                .link(downstream)?;
+        }
+        println!(
+            "Swapped {} for {}",
+            self.segment
+                .iter()
+                .map(|element| element.name().to_string())
+                .collect::<Vec<_>>()
+                .join(" ! "),
+            self.replacement.name()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,720 @@
+use std::{
+    env,
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use gstreamer as gst;
+
+use anyhow::Error;
+use gst::prelude::*;
+
+use crate::buffering::BufferingController;
+
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Ways a response can go wrong on purpose
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Answer 404 even if the file exists
+    NotFound,
+    /// Close the connection after sending this many body bytes
+    DropAfter(u64),
+    /// Wait this long between header lines
+    SlowHeaders(Duration),
+}
+
+impl Fault {
+    /// Parses `404`, `drop:<bytes>` or `slow-headers:<ms>`
+    pub fn parse(fault: &str) -> Option<Fault> {
+        match fault.split_once(':') {
+            None if fault == "404" => Some(Fault::NotFound),
+            Some(("drop", bytes)) => bytes.parse().ok().map(Fault::DropAfter),
+            Some(("slow-headers", ms)) => ms
+                .parse()
+                .ok()
+                .map(|ms| Fault::SlowHeaders(Duration::from_millis(ms))),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Delay before each response
+    pub latency: Duration,
+    /// Body bytes per second, None is as fast as possible
+    pub bandwidth: Option<u64>,
+    pub fault: Option<Fault>,
+    /// Apply `fault` to every nth request only, 1 for all of them
+    pub fault_every: u32,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            latency: Duration::ZERO,
+            bandwidth: None,
+            fault: None,
+            fault_every: 1,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Reads `HTTP_LATENCY_MS`, `HTTP_BANDWIDTH` (bytes per second), `HTTP_FAULT` (see
+    /// `Fault::parse`) and `HTTP_FAULT_EVERY`
+    pub fn from_env() -> ServerConfig {
+        let var = |name: &str| env::var(name).ok();
+        let default = ServerConfig::default();
+
+        ServerConfig {
+            latency: var("HTTP_LATENCY_MS")
+                .and_then(|ms| ms.parse().ok())
+                .map_or(default.latency, Duration::from_millis),
+            bandwidth: var("HTTP_BANDWIDTH").and_then(|bandwidth| bandwidth.parse().ok()),
+            fault: var("HTTP_FAULT").and_then(|fault| Fault::parse(&fault)),
+            fault_every: var("HTTP_FAULT_EVERY")
+                .and_then(|every| every.parse().ok())
+                .filter(|every| *every > 0)
+                .unwrap_or(default.fault_every),
+        }
+    }
+}
+
+/// A `Range: bytes=` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ByteRange {
+    /// `bytes=first-` or `bytes=first-last`
+    From { first: u64, last: Option<u64> },
+    /// `bytes=-length`, the last `length` bytes
+    Suffix(u64),
+}
+
+impl ByteRange {
+    fn parse(value: &str) -> Option<ByteRange> {
+        let (first, last) = value.trim().strip_prefix("bytes=")?.split_once('-')?;
+        if first.is_empty() {
+            return Some(ByteRange::Suffix(last.parse().ok()?));
+        }
+        let last = if last.is_empty() {
+            None
+        } else {
+            Some(last.parse().ok()?)
+        };
+        Some(ByteRange::From {
+            first: first.parse().ok()?,
+            last,
+        })
+    }
+
+    /// First and last byte in a file of `size` bytes, None when none of it is in the file or
+    /// the range is reversed
+    fn resolve(self, size: u64) -> Option<(u64, u64)> {
+        match self {
+            ByteRange::From { first, last } => {
+                if first >= size || last.is_some_and(|last| last < first) {
+                    return None;
+                }
+                Some((first, last.map_or(size - 1, |last| last.min(size - 1))))
+            }
+            ByteRange::Suffix(length) => {
+                if length == 0 || size == 0 {
+                    return None;
+                }
+                Some((size - length.min(size), size - 1))
+            }
+        }
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    range: Option<ByteRange>,
+}
+
+fn read_request(stream: &TcpStream) -> io::Result<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "malformed request line",
+        ));
+    };
+    let mut request = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        range: None,
+    };
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        if name.eq_ignore_ascii_case("range") {
+            // A header that doesn't parse is ignored and the whole file is sent
+            request.range = ByteRange::parse(value);
+        }
+    }
+
+    Ok(request)
+}
+
+/// Maps a request path below `root`, refusing anything that climbs out of it
+fn resolve(root: &Path, request_path: &str) -> Option<PathBuf> {
+    let path = request_path.split('?').next()?;
+    let decoded = percent_decode(path)?;
+    let relative = Path::new(decoded.trim_start_matches('/'));
+    if relative
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        return None;
+    }
+    Some(root.join(relative))
+}
+
+fn percent_decode(path: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut iter = path.bytes();
+    while let Some(byte) = iter.next() {
+        if byte == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+    {
+        "webm" => "video/webm",
+        "mkv" => "video/x-matroska",
+        "mp4" | "m4v" => "video/mp4",
+        "ogg" | "ogv" => "video/ogg",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/x-wav",
+        "srt" => "application/x-subrip",
+        "vtt" => "text/vtt",
+        _ => "application/octet-stream",
+    }
+}
+
+fn write_headers(
+    stream: &mut TcpStream,
+    status: &str,
+    headers: &[String],
+    fault: Option<Fault>,
+) -> io::Result<()> {
+    let mut lines = vec![format!("HTTP/1.1 {status}")];
+    lines.extend(headers.iter().cloned());
+    lines.push("Connection: close".to_string());
+
+    for line in lines {
+        if let Some(Fault::SlowHeaders(delay)) = fault {
+            thread::sleep(delay);
+        }
+        write!(stream, "{line}\r\n")?;
+        stream.flush()?;
+    }
+    write!(stream, "\r\n")
+}
+
+fn handle_connection(
+    mut stream: TcpStream,
+    root: &Path,
+    config: &ServerConfig,
+    fault: Option<Fault>,
+) -> io::Result<()> {
+    let request = read_request(&stream)?;
+    thread::sleep(config.latency);
+
+    if request.method != "GET" && request.method != "HEAD" {
+        return write_headers(
+            &mut stream,
+            "405 Method Not Allowed",
+            &["Content-Length: 0".to_string()],
+            fault,
+        );
+    }
+
+    let file = resolve(root, &request.path)
+        .filter(|path| path.is_file() && fault != Some(Fault::NotFound))
+        .and_then(|path| Some((File::open(&path).ok()?, path)));
+    let Some((mut file, path)) = file else {
+        println!("HTTP {} {} -> 404", request.method, request.path);
+        return write_headers(
+            &mut stream,
+            "404 Not Found",
+            &["Content-Length: 0".to_string()],
+            fault,
+        );
+    };
+
+    let size = file.metadata()?.len();
+    let (status, start, end) = match request.range.map(|range| range.resolve(size)) {
+        Some(Some((start, end))) => ("206 Partial Content", start, end),
+        Some(None) => {
+            let headers = [
+                format!("Content-Range: bytes */{size}"),
+                "Content-Length: 0".to_string(),
+            ];
+            return write_headers(&mut stream, "416 Range Not Satisfiable", &headers, fault);
+        }
+        None => ("200 OK", 0, size.saturating_sub(1)),
+    };
+    let length = if size == 0 { 0 } else { end - start + 1 };
+    println!(
+        "HTTP {} {} bytes {start}-{end}/{size} -> {status}",
+        request.method, request.path
+    );
+
+    let mut headers = vec![
+        format!("Content-Type: {}", content_type(&path)),
+        format!("Content-Length: {length}"),
+        "Accept-Ranges: bytes".to_string(),
+    ];
+    if request.range.is_some() {
+        headers.push(format!("Content-Range: bytes {start}-{end}/{size}"));
+    }
+    write_headers(&mut stream, status, &headers, fault)?;
+    if request.method == "HEAD" {
+        return Ok(());
+    }
+
+    file.seek(SeekFrom::Start(start))?;
+    let limit = match fault {
+        Some(Fault::DropAfter(bytes)) => bytes.min(length),
+        _ => length,
+    };
+
+    let started = Instant::now();
+    let mut sent = 0u64;
+    let mut chunk = vec![0u8; CHUNK_SIZE];
+    while sent < limit {
+        let n = (limit - sent).min(CHUNK_SIZE as u64) as usize;
+        file.read_exact(&mut chunk[..n])?;
+        stream.write_all(&chunk[..n])?;
+        sent += n as u64;
+
+        // Sleep until the average rate is back at the limit
+        if let Some(bandwidth) = config.bandwidth.filter(|b| *b > 0) {
+            let due = Duration::from_secs_f64(sent as f64 / bandwidth as f64);
+            if let Some(wait) = due.checked_sub(started.elapsed()) {
+                thread::sleep(wait);
+            }
+        }
+    }
+
+    if sent < length {
+        println!("HTTP dropping connection after {sent} of {length} bytes");
+    }
+    Ok(())
+}
+
+/// Minimal HTTP/1.1 file server on localhost, one thread per connection. Stops when dropped.
+pub struct HttpServer {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl HttpServer {
+    /// Serves the files below `root` on a free port
+    pub fn start(root: &Path, config: ServerConfig) -> io::Result<HttpServer> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let root = root.to_path_buf();
+        let config = Arc::new(config);
+        let requests = Arc::new(AtomicU32::new(0));
+
+        let stop_clone = stop.clone();
+        let thread = thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stop_clone.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok(stream) = stream else {
+                    continue;
+                };
+
+                let n = requests.fetch_add(1, Ordering::SeqCst) + 1;
+                let fault = config.fault.filter(|_| n % config.fault_every == 0);
+                let root = root.clone();
+                let config = config.clone();
+                thread::spawn(move || {
+                    if let Err(err) = handle_connection(stream, &root, &config, fault) {
+                        // Players close connections they no longer need all the time
+                        if err.kind() != io::ErrorKind::BrokenPipe
+                            && err.kind() != io::ErrorKind::ConnectionReset
+                        {
+                            eprintln!("HTTP connection failed: {err}");
+                        }
+                    }
+                });
+            }
+        });
+
+        Ok(HttpServer {
+            addr,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// `http://` URI of `path`, relative to the served root
+    pub fn uri(&self, path: &str) -> String {
+        format!("http://{}/{}", self.addr, path.trim_start_matches('/'))
+    }
+}
+
+impl Drop for HttpServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Wake up the blocking accept
+        let _ = TcpStream::connect(self.addr);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Plays a local file through the HTTP server, so network sources, buffering and error
+/// handling can be tried without the internet. Throttling and faults come from the
+/// environment, see `ServerConfig::from_env`.
+pub fn tutorial_main() -> Result<(), Error> {
+    let Some(file) = env::args().nth(1).map(PathBuf::from) else {
+        eprintln!("Usage: http_server <media file>");
+        return Ok(());
+    };
+    let root = file.parent().map(Path::to_path_buf).unwrap_or_default();
+    let name = file
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+
+    let config = ServerConfig::from_env();
+    println!("Serving {} with {config:?}", root.display());
+    let server = HttpServer::start(&root, config)?;
+
+    // Create the main loop
+    let main_loop = glib::MainLoop::new(None, false);
+
+    // Initialize GStreamer
+    gst::init()?;
+
+    let uri = server.uri(name);
+    println!("Playing {uri}");
+    let playbin = gst::ElementFactory::make("playbin")
+        .name("playbin")
+        .property("uri", &uri)
+        .build()?;
+
+    let main_loop_clone = main_loop.clone();
+    let mut buffering_controller = BufferingController::new(&playbin);
+    let bus = playbin.bus().unwrap();
+    let _bus_watch = bus.add_watch(move |_bus, message| {
+        use gst::MessageView;
+
+        if buffering_controller.handle_message(message) {
+            return glib::ControlFlow::Continue;
+        }
+
+        match message.view() {
+            MessageView::Error(err) => {
+                eprintln!(
+                    "Error received from element {:?} {}",
+                    err.src().map(|s| s.path_string()),
+                    err.error()
+                );
+                eprintln!("Debugging information: {:?}", err.debug());
+                main_loop_clone.quit();
+                glib::ControlFlow::Break
+            }
+            MessageView::Eos(..) => {
+                println!("Reached end of stream");
+                main_loop_clone.quit();
+                glib::ControlFlow::Break
+            }
+            _ => glib::ControlFlow::Continue,
+        }
+    })?;
+
+    // Set to PLAYING
+    playbin.set_state(gst::State::Playing)?;
+
+    // Set GLib mainloop to run
+    main_loop.run();
+
+    // Clean up
+    playbin.set_state(gst::State::Null)?;
+    drop(server);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, process};
+
+    use super::*;
+
+    const FILE_SIZE: usize = 1000;
+
+    /// A directory with `clip.webm` holding the bytes 0, 1, ... 255, 0, 1, ...
+    fn root(name: &str) -> PathBuf {
+        let root = env::temp_dir().join(format!("gstream_prac_http_{}_{name}", process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let data: Vec<u8> = (0..FILE_SIZE).map(|i| i as u8).collect();
+        fs::write(root.join("clip.webm"), data).unwrap();
+        root
+    }
+
+    struct Response {
+        status: String,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    }
+
+    impl Response {
+        fn header(&self, name: &str) -> Option<&str> {
+            self.headers
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        }
+    }
+
+    fn get(server: &HttpServer, path: &str, headers: &[&str]) -> Response {
+        let mut stream = TcpStream::connect(server.addr).unwrap();
+        write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n").unwrap();
+        for header in headers {
+            write!(stream, "{header}\r\n").unwrap();
+        }
+        write!(stream, "\r\n").unwrap();
+
+        // Every response ends with the connection, even the dropped ones
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let split = response
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .expect("no end of headers");
+        let head = String::from_utf8(response[..split].to_vec()).unwrap();
+        let mut lines = head.split("\r\n");
+        let status = lines.next().unwrap().to_string();
+        let headers = lines
+            .filter_map(|line| line.split_once(": "))
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+
+        Response {
+            status,
+            headers,
+            body: response[split + 4..].to_vec(),
+        }
+    }
+
+    fn expected(range: std::ops::RangeInclusive<usize>) -> Vec<u8> {
+        range.map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn full_get() {
+        let server = HttpServer::start(&root("full"), ServerConfig::default()).unwrap();
+        let response = get(&server, "/clip.webm", &[]);
+
+        assert_eq!(response.status, "HTTP/1.1 200 OK");
+        assert_eq!(response.header("Content-Type"), Some("video/webm"));
+        assert_eq!(response.header("Content-Length"), Some("1000"));
+        assert_eq!(response.header("Content-Range"), None);
+        assert_eq!(response.body, expected(0..=999));
+    }
+
+    #[test]
+    fn open_range() {
+        let server = HttpServer::start(&root("open"), ServerConfig::default()).unwrap();
+        let response = get(&server, "/clip.webm", &["Range: bytes=900-"]);
+
+        assert_eq!(response.status, "HTTP/1.1 206 Partial Content");
+        assert_eq!(response.header("Content-Range"), Some("bytes 900-999/1000"));
+        assert_eq!(response.header("Content-Length"), Some("100"));
+        assert_eq!(response.body, expected(900..=999));
+    }
+
+    #[test]
+    fn closed_range() {
+        let server = HttpServer::start(&root("closed"), ServerConfig::default()).unwrap();
+        let response = get(&server, "/clip.webm", &["Range: bytes=10-19"]);
+
+        assert_eq!(response.status, "HTTP/1.1 206 Partial Content");
+        assert_eq!(response.header("Content-Range"), Some("bytes 10-19/1000"));
+        assert_eq!(response.body, expected(10..=19));
+
+        // The end is clamped to the file
+        let response = get(&server, "/clip.webm", &["Range: bytes=990-5000"]);
+        assert_eq!(response.header("Content-Range"), Some("bytes 990-999/1000"));
+        assert_eq!(response.body, expected(990..=999));
+    }
+
+    #[test]
+    fn suffix_range() {
+        let server = HttpServer::start(&root("suffix"), ServerConfig::default()).unwrap();
+        let response = get(&server, "/clip.webm", &["Range: bytes=-10"]);
+
+        assert_eq!(response.status, "HTTP/1.1 206 Partial Content");
+        assert_eq!(response.header("Content-Range"), Some("bytes 990-999/1000"));
+        assert_eq!(response.body, expected(990..=999));
+    }
+
+    #[test]
+    fn unsatisfiable_ranges() {
+        let server = HttpServer::start(&root("unsatisfiable"), ServerConfig::default()).unwrap();
+        for range in ["bytes=1000-", "bytes=500-100", "bytes=-0"] {
+            let response = get(&server, "/clip.webm", &[&format!("Range: {range}")]);
+            assert_eq!(
+                response.status, "HTTP/1.1 416 Range Not Satisfiable",
+                "{range}"
+            );
+            assert_eq!(response.header("Content-Range"), Some("bytes */1000"));
+            assert!(response.body.is_empty());
+        }
+    }
+
+    #[test]
+    fn missing_file() {
+        let server = HttpServer::start(&root("missing"), ServerConfig::default()).unwrap();
+        let response = get(&server, "/nothing.webm", &[]);
+
+        assert_eq!(response.status, "HTTP/1.1 404 Not Found");
+    }
+
+    #[test]
+    fn path_traversal() {
+        let root = Path::new("/srv/media");
+        assert_eq!(
+            resolve(root, "/a/clip.webm?t=1"),
+            Some(root.join("a/clip.webm"))
+        );
+        assert_eq!(resolve(root, "/../etc/passwd"), None);
+        assert_eq!(resolve(root, "/a/../../etc/passwd"), None);
+        assert_eq!(resolve(root, "/%2e%2e/etc/passwd"), None);
+
+        let server = HttpServer::start(&self::root("traversal"), ServerConfig::default()).unwrap();
+        let response = get(&server, "/../etc/passwd", &[]);
+        assert_eq!(response.status, "HTTP/1.1 404 Not Found");
+    }
+
+    #[test]
+    fn fault_not_found() {
+        let config = ServerConfig {
+            fault: Some(Fault::NotFound),
+            ..Default::default()
+        };
+        let server = HttpServer::start(&root("fault_404"), config).unwrap();
+        let response = get(&server, "/clip.webm", &[]);
+
+        assert_eq!(response.status, "HTTP/1.1 404 Not Found");
+    }
+
+    #[test]
+    fn fault_drop_after() {
+        let config = ServerConfig {
+            fault: Some(Fault::DropAfter(100)),
+            ..Default::default()
+        };
+        let server = HttpServer::start(&root("fault_drop"), config).unwrap();
+        let response = get(&server, "/clip.webm", &[]);
+
+        // The headers promise the whole file, the body stops short
+        assert_eq!(response.status, "HTTP/1.1 200 OK");
+        assert_eq!(response.header("Content-Length"), Some("1000"));
+        assert_eq!(response.body, expected(0..=99));
+    }
+
+    #[test]
+    fn fault_slow_headers() {
+        let delay = Duration::from_millis(50);
+        let config = ServerConfig {
+            fault: Some(Fault::SlowHeaders(delay)),
+            ..Default::default()
+        };
+        let server = HttpServer::start(&root("fault_slow"), config).unwrap();
+        let started = Instant::now();
+        let response = get(&server, "/clip.webm", &[]);
+
+        // One delay per header line: status, type, length, ranges and connection
+        assert!(started.elapsed() >= delay * 5);
+        assert_eq!(response.status, "HTTP/1.1 200 OK");
+        assert_eq!(response.body, expected(0..=999));
+    }
+
+    #[test]
+    fn fault_every() {
+        let config = ServerConfig {
+            fault: Some(Fault::NotFound),
+            fault_every: 2,
+            ..Default::default()
+        };
+        let server = HttpServer::start(&root("fault_every"), config).unwrap();
+
+        assert_eq!(get(&server, "/clip.webm", &[]).status, "HTTP/1.1 200 OK");
+        assert_eq!(
+            get(&server, "/clip.webm", &[]).status,
+            "HTTP/1.1 404 Not Found"
+        );
+        assert_eq!(get(&server, "/clip.webm", &[]).status, "HTTP/1.1 200 OK");
+    }
+
+    #[test]
+    fn parse_ranges() {
+        assert_eq!(
+            ByteRange::parse("bytes=100-"),
+            Some(ByteRange::From {
+                first: 100,
+                last: None
+            })
+        );
+        assert_eq!(ByteRange::parse("bytes=-10"), Some(ByteRange::Suffix(10)));
+        assert_eq!(ByteRange::parse("bytes=a-b"), None);
+        assert_eq!(ByteRange::parse("items=1-2"), None);
+
+        assert_eq!(ByteRange::Suffix(5000).resolve(1000), Some((0, 999)));
+        assert_eq!(ByteRange::Suffix(10).resolve(0), None);
+    }
+
+    #[test]
+    fn parse_faults() {
+        assert_eq!(Fault::parse("404"), Some(Fault::NotFound));
+        assert_eq!(Fault::parse("drop:4096"), Some(Fault::DropAfter(4096)));
+        assert_eq!(
+            Fault::parse("slow-headers:200"),
+            Some(Fault::SlowHeaders(Duration::from_millis(200)))
+        );
+        assert_eq!(Fault::parse("drop:lots"), None);
+    }
+}
@@ -0,0 +1,176 @@
+use std::{io, panic, sync::Once, time::Duration};
+
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    terminal::{disable_raw_mode, enable_raw_mode},
+};
+use glib::SourceId;
+
+/// Maps key presses to application actions
+pub struct Keymap<A> {
+    bindings: Vec<(KeyCode, KeyModifiers, A)>,
+    /// Action for the number keys 0-9, if any
+    digits: Option<Box<dyn Fn(u32) -> A>>,
+}
+
+impl<A: Clone> Default for Keymap<A> {
+    fn default() -> Self {
+        Keymap {
+            bindings: Vec::new(),
+            digits: None,
+        }
+    }
+}
+
+impl<A: Clone> Keymap<A> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn bind(self, code: KeyCode, action: A) -> Self {
+        self.bind_with_modifiers(code, KeyModifiers::NONE, action)
+    }
+
+    pub fn bind_with_modifiers(mut self, code: KeyCode, modifiers: KeyModifiers, action: A) -> Self {
+        self.bindings.retain(|(c, m, _)| !(*c == code && *m == modifiers));
+        self.bindings.push((code, modifiers, action));
+        self
+    }
+
+    pub fn bind_digits(mut self, action: impl Fn(u32) -> A + 'static) -> Self {
+        self.digits = Some(Box::new(action));
+        self
+    }
+
+    pub fn lookup(&self, key: &KeyEvent) -> Option<A> {
+        // Shift is already part of characters like '+', so it is not matched on
+        let modifiers = key.modifiers.difference(KeyModifiers::SHIFT);
+
+        let bound = self
+            .bindings
+            .iter()
+            .find(|(code, m, _)| *code == key.code && *m == modifiers)
+            .map(|(_, _, action)| action.clone());
+        if bound.is_some() {
+            return bound;
+        }
+
+        match (key.code, &self.digits) {
+            (KeyCode::Char(c), Some(digits)) if modifiers.is_empty() => c.to_digit(10).map(digits),
+            _ => None,
+        }
+    }
+}
+
+fn install_panic_hook() {
+    static HOOK: Once = Once::new();
+
+    HOOK.call_once(|| {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            // Restore the terminal first, a panic from any thread would otherwise leave it raw
+            let _ = disable_raw_mode();
+            default_hook(info);
+        }));
+    });
+}
+
+fn add_polling_source(mut dispatch: impl FnMut() + 'static) -> SourceId {
+    glib::timeout_add_local(Duration::from_millis(50), move || {
+        dispatch();
+        glib::ControlFlow::Continue
+    })
+}
+
+#[cfg(unix)]
+fn add_input_source(main_loop: glib::MainLoop, mut dispatch: impl FnMut() + 'static) -> SourceId {
+    use std::io::IsTerminal;
+
+    // crossterm reads from /dev/tty when stdin is not a terminal, so only then fall back to polling
+    if !io::stdin().is_terminal() {
+        return add_polling_source(dispatch);
+    }
+
+    glib::unix_fd_add_local(
+        0,
+        glib::IOCondition::IN | glib::IOCondition::HUP,
+        move |_, condition| {
+            if condition.contains(glib::IOCondition::HUP) {
+                main_loop.quit();
+            } else {
+                dispatch();
+            }
+            // The source is removed by the controller, never here
+            glib::ControlFlow::Continue
+        },
+    )
+}
+
+#[cfg(not(unix))]
+fn add_input_source(_main_loop: glib::MainLoop, dispatch: impl FnMut() + 'static) -> SourceId {
+    add_polling_source(dispatch)
+}
+
+/// Reads keys from the terminal on the glib main loop and turns them into actions through a
+/// [`Keymap`]. Ctrl+C quits the main loop, as raw mode keeps it from raising SIGINT.
+///
+/// Raw mode is enabled while the controller is alive. Dropping it (after the main loop quit
+/// on EOS or an error, or while unwinding) restores the terminal.
+pub struct InputController {
+    source_id: Option<SourceId>,
+}
+
+impl InputController {
+    /// Must be called from the thread that runs `main_loop`
+    pub fn start<A, F>(
+        keymap: Keymap<A>,
+        main_loop: &glib::MainLoop,
+        mut on_action: F,
+    ) -> io::Result<InputController>
+    where
+        A: Clone + 'static,
+        F: FnMut(A) + 'static,
+    {
+        enable_raw_mode()?;
+        install_panic_hook();
+
+        let main_loop_clone = main_loop.clone();
+        let dispatch = move || {
+            // Handle everything that is ready without blocking the main loop
+            while let Ok(true) = event::poll(Duration::ZERO) {
+                match event::read() {
+                    Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => {
+                        if key.code == KeyCode::Char('c')
+                            && key.modifiers.contains(KeyModifiers::CONTROL)
+                        {
+                            main_loop_clone.quit();
+                            return;
+                        }
+                        if let Some(action) = keymap.lookup(&key) {
+                            on_action(action);
+                        }
+                    }
+                    Ok(_) => (),
+                    Err(err) => {
+                        eprintln!("Failed to read terminal input: {err}");
+                        main_loop_clone.quit();
+                        return;
+                    }
+                }
+            }
+        };
+
+        Ok(InputController {
+            source_id: Some(add_input_source(main_loop.clone(), dispatch)),
+        })
+    }
+}
+
+impl Drop for InputController {
+    fn drop(&mut self) {
+        if let Some(source_id) = self.source_id.take() {
+            source_id.remove();
+        }
+        let _ = disable_raw_mode();
+    }
+}
@@ -0,0 +1,410 @@
+use std::{env, fs, io, path::Path};
+
+use gstreamer as gst;
+
+use glib::FlagsClass;
+use gst::prelude::*;
+
+const DEFAULT_CONFIG: &str = "languages.conf";
+
+/// ISO 639-1, 639-2/T and 639-2/B codes, so "en", "eng" and "en-US" all compare equal
+const LANGUAGE_CODES: &[(&str, &str, &str)] = &[
+    ("ar", "ara", "ara"),
+    ("cs", "ces", "cze"),
+    ("da", "dan", "dan"),
+    ("de", "deu", "ger"),
+    ("el", "ell", "gre"),
+    ("en", "eng", "eng"),
+    ("es", "spa", "spa"),
+    ("fi", "fin", "fin"),
+    ("fr", "fra", "fre"),
+    ("he", "heb", "heb"),
+    ("hi", "hin", "hin"),
+    ("hu", "hun", "hun"),
+    ("it", "ita", "ita"),
+    ("ja", "jpn", "jpn"),
+    ("ko", "kor", "kor"),
+    ("nl", "nld", "dut"),
+    ("no", "nor", "nor"),
+    ("pl", "pol", "pol"),
+    ("pt", "por", "por"),
+    ("ru", "rus", "rus"),
+    ("sv", "swe", "swe"),
+    ("th", "tha", "tha"),
+    ("tr", "tur", "tur"),
+    ("vi", "vie", "vie"),
+    ("zh", "zho", "chi"),
+];
+
+/// Returns the two letter code for `code`, or `None` for empty/undetermined languages
+pub fn normalize_language(code: &str) -> Option<String> {
+    let code = code.trim().to_ascii_lowercase();
+    let primary = code.split(['-', '_']).next().unwrap_or("");
+    if primary.is_empty() || primary == "und" {
+        return None;
+    }
+
+    let normalized = LANGUAGE_CODES
+        .iter()
+        .find(|(iso1, iso2t, iso2b)| primary == *iso1 || primary == *iso2t || primary == *iso2b)
+        .map(|(iso1, _, _)| iso1.to_string())
+        .unwrap_or_else(|| primary.to_string());
+    Some(normalized)
+}
+
+/// Which languages to pick automatically, most preferred first
+#[derive(Debug, Clone)]
+pub struct LanguagePreferences {
+    pub audio: Vec<String>,
+    pub subtitles: Vec<String>,
+    /// Use a stream without a language tag when no preferred language is available
+    pub untagged_fallback: bool,
+}
+
+impl Default for LanguagePreferences {
+    fn default() -> Self {
+        LanguagePreferences {
+            audio: Vec::new(),
+            subtitles: Vec::new(),
+            untagged_fallback: true,
+        }
+    }
+}
+
+impl LanguagePreferences {
+    /// Parses `key = value` lines, e.g.
+    ///
+    /// ```text
+    /// # most preferred first
+    /// audio = ja, en
+    /// subtitles = en
+    /// untagged_fallback = true
+    /// ```
+    pub fn parse(config: &str) -> LanguagePreferences {
+        let mut preferences = LanguagePreferences::default();
+        let languages = |value: &str| {
+            value
+                .split(',')
+                .filter_map(normalize_language)
+                .collect::<Vec<_>>()
+        };
+
+        for line in config.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                eprintln!("Ignoring malformed language config line: {line}");
+                continue;
+            };
+
+            match key.trim() {
+                "audio" => preferences.audio = languages(value),
+                "subtitles" => preferences.subtitles = languages(value),
+                "untagged_fallback" => preferences.untagged_fallback = value.trim() == "true",
+                other => eprintln!("Ignoring unknown language config key: {other}"),
+            }
+        }
+
+        preferences
+    }
+
+    /// Reads the file named by `LANGUAGE_CONFIG`, or `languages.conf` in the working directory.
+    /// A missing file means no preferences.
+    pub fn load() -> LanguagePreferences {
+        let path = env::var("LANGUAGE_CONFIG").unwrap_or_else(|_| DEFAULT_CONFIG.to_string());
+        match Self::from_file(Path::new(&path)) {
+            Ok(preferences) => preferences,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => LanguagePreferences::default(),
+            Err(err) => {
+                eprintln!("Failed to read language config {path}: {err}");
+                LanguagePreferences::default()
+            }
+        }
+    }
+
+    pub fn from_file(path: &Path) -> io::Result<LanguagePreferences> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+}
+
+/// What we know about one audio or subtitle stream of playbin
+#[derive(Debug, Clone)]
+pub struct StreamLanguage {
+    pub index: i32,
+    pub language: Option<String>,
+    /// Subtitles that only cover foreign dialogue or signs
+    pub forced: bool,
+}
+
+fn stream_languages(playbin: &gst::Element, kind: &str) -> Vec<StreamLanguage> {
+    let n_streams = playbin.property::<i32>(&format!("n-{kind}"));
+
+    (0..n_streams)
+        .map(|index| {
+            let tags = playbin
+                .emit_by_name::<Option<gst::TagList>>(&format!("get-{kind}-tags"), &[&index]);
+            let language = tags
+                .as_ref()
+                .and_then(|tags| tags.get::<gst::tags::LanguageCode>())
+                .and_then(|code| normalize_language(code.get()));
+            // There is no standard forced flag in the tags, containers put it in the title
+            let forced = tags
+                .as_ref()
+                .and_then(|tags| tags.get::<gst::tags::Title>())
+                .map(|title| title.get().to_ascii_lowercase().contains("forced"))
+                .unwrap_or(false);
+
+            StreamLanguage {
+                index,
+                language,
+                forced,
+            }
+        })
+        .collect()
+}
+
+/// Picks the stream of the most preferred language, full streams before forced ones
+pub fn choose_stream(
+    streams: &[StreamLanguage],
+    preferred: &[String],
+    untagged_fallback: bool,
+) -> Option<i32> {
+    for language in preferred {
+        let matches = |s: &&StreamLanguage| s.language.as_deref() == Some(language.as_str());
+        let first = streams
+            .iter()
+            .filter(matches)
+            .find(|s| !s.forced)
+            .or_else(|| streams.iter().find(matches));
+        if let Some(stream) = first {
+            return Some(stream.index);
+        }
+    }
+
+    if untagged_fallback {
+        return streams
+            .iter()
+            .find(|s| s.language.is_none() && !s.forced)
+            .map(|s| s.index);
+    }
+
+    None
+}
+
+/// Picks subtitles for the given audio language. When the audio is already in the chosen
+/// subtitle language, only forced subtitles (if any) are shown.
+pub fn choose_subtitles(
+    streams: &[StreamLanguage],
+    preferences: &LanguagePreferences,
+    audio_language: Option<&str>,
+) -> Option<i32> {
+    let index = choose_stream(
+        streams,
+        &preferences.subtitles,
+        preferences.untagged_fallback,
+    )?;
+    let chosen = streams.iter().find(|s| s.index == index)?;
+
+    match (&chosen.language, audio_language) {
+        (Some(language), Some(audio)) if language == audio => streams
+            .iter()
+            .find(|s| s.forced && s.language.as_deref() == Some(audio))
+            .map(|s| s.index),
+        _ => Some(index),
+    }
+}
+
+/// Switches `current-audio` to the preferred language and returns the language playing now
+pub fn apply_audio_preferences(
+    playbin: &gst::Element,
+    preferences: &LanguagePreferences,
+) -> Option<String> {
+    let streams = stream_languages(playbin, "audio");
+
+    // Without preferences keep whatever playbin picked
+    let choice = if preferences.audio.is_empty() {
+        None
+    } else {
+        choose_stream(&streams, &preferences.audio, preferences.untagged_fallback)
+    };
+    if let Some(index) = choice {
+        if playbin.property::<i32>("current-audio") != index {
+            println!("Selecting audio stream {index} from language preferences");
+            playbin.set_property("current-audio", index);
+        }
+    }
+
+    let current = playbin.property::<i32>("current-audio");
+    streams
+        .into_iter()
+        .find(|s| s.index == current)
+        .and_then(|s| s.language)
+}
+
+/// Switches `current-text` to the preferred language, or turns subtitles off when there is
+/// nothing to show
+pub fn apply_subtitle_preferences(
+    playbin: &gst::Element,
+    preferences: &LanguagePreferences,
+    audio_language: Option<&str>,
+) {
+    if preferences.subtitles.is_empty() {
+        return;
+    }
+
+    let streams = stream_languages(playbin, "text");
+    let choice = choose_subtitles(&streams, preferences, audio_language);
+
+    match choice {
+        Some(index) => {
+            println!("Selecting subtitle stream {index} from language preferences");
+            playbin.set_property("current-text", index);
+            set_subtitles_visible(playbin, true);
+        }
+        None => {
+            println!("No subtitles match the language preferences, hiding subtitles");
+            set_subtitles_visible(playbin, false);
+        }
+    }
+}
+
+/// Sets or unsets the `text` flag of playbin
+pub fn set_subtitles_visible(playbin: &gst::Element, visible: bool) {
+    let flags = playbin.property_value("flags");
+    let flags_class = FlagsClass::with_type(flags.type_()).unwrap();
+    let builder = flags_class.builder_with_value(flags).unwrap();
+    let flags = if visible {
+        builder.set_by_nick("text")
+    } else {
+        builder.unset_by_nick("text")
+    }
+    .build()
+    .unwrap();
+    playbin.set_property_from_value("flags", &flags);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(index: i32, language: Option<&str>, forced: bool) -> StreamLanguage {
+        StreamLanguage {
+            index,
+            language: language.map(str::to_string),
+            forced,
+        }
+    }
+
+    fn languages(codes: &[&str]) -> Vec<String> {
+        codes.iter().map(|code| code.to_string()).collect()
+    }
+
+    #[test]
+    fn normalize() {
+        assert_eq!(normalize_language("en").as_deref(), Some("en"));
+        assert_eq!(normalize_language("eng").as_deref(), Some("en"));
+        assert_eq!(normalize_language(" en-US ").as_deref(), Some("en"));
+        assert_eq!(normalize_language("pt_BR").as_deref(), Some("pt"));
+        // 639-2/T and 639-2/B
+        assert_eq!(normalize_language("deu").as_deref(), Some("de"));
+        assert_eq!(normalize_language("GER").as_deref(), Some("de"));
+        assert_eq!(normalize_language("chi").as_deref(), Some("zh"));
+        // Unknown codes are kept as they are
+        assert_eq!(normalize_language("tlh").as_deref(), Some("tlh"));
+        assert_eq!(normalize_language("und"), None);
+        assert_eq!(normalize_language(""), None);
+    }
+
+    #[test]
+    fn parse_config() {
+        let preferences = LanguagePreferences::parse(
+            "# most preferred first\n\
+             audio = jpn, en-GB\n\
+             \n\
+             subtitles=fre,,und\n\
+             untagged_fallback = false\n\
+             not a setting\n\
+             volume = 11\n",
+        );
+        assert_eq!(preferences.audio, ["ja", "en"]);
+        assert_eq!(preferences.subtitles, ["fr"]);
+        assert!(!preferences.untagged_fallback);
+
+        let preferences = LanguagePreferences::parse("");
+        assert!(preferences.audio.is_empty());
+        assert!(preferences.subtitles.is_empty());
+        assert!(preferences.untagged_fallback);
+    }
+
+    #[test]
+    fn choose_in_preference_order() {
+        let streams = [
+            stream(0, Some("en"), false),
+            stream(1, Some("fr"), true),
+            stream(2, Some("ja"), false),
+            stream(3, Some("fr"), false),
+        ];
+        assert_eq!(
+            choose_stream(&streams, &languages(&["ja", "en"]), true),
+            Some(2)
+        );
+        assert_eq!(
+            choose_stream(&streams, &languages(&["de", "en"]), true),
+            Some(0)
+        );
+        // Full streams before forced ones of the same language
+        assert_eq!(choose_stream(&streams, &languages(&["fr"]), true), Some(3));
+        // A forced stream is still better than another language
+        assert_eq!(
+            choose_stream(&streams[..2], &languages(&["fr", "en"]), true),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn choose_without_match() {
+        let streams = [
+            stream(0, Some("en"), false),
+            stream(1, None, true),
+            stream(2, None, false),
+        ];
+        // Untagged, but never forced
+        assert_eq!(choose_stream(&streams, &languages(&["de"]), true), Some(2));
+        assert_eq!(choose_stream(&streams, &languages(&["de"]), false), None);
+        assert_eq!(
+            choose_stream(&streams[..2], &languages(&["de"]), true),
+            None
+        );
+        assert_eq!(choose_stream(&streams, &[], true), Some(2));
+    }
+
+    #[test]
+    fn subtitles_for_audio_language() {
+        let streams = [
+            stream(0, Some("en"), false),
+            stream(1, Some("en"), true),
+            stream(2, Some("de"), false),
+        ];
+        let preferences = LanguagePreferences {
+            subtitles: languages(&["en"]),
+            ..Default::default()
+        };
+        // Foreign audio gets full subtitles
+        assert_eq!(
+            choose_subtitles(&streams, &preferences, Some("ja")),
+            Some(0)
+        );
+        assert_eq!(choose_subtitles(&streams, &preferences, None), Some(0));
+        // Audio in the subtitle language only gets the forced ones
+        assert_eq!(
+            choose_subtitles(&streams, &preferences, Some("en")),
+            Some(1)
+        );
+        assert_eq!(
+            choose_subtitles(&[stream(0, Some("en"), false)], &preferences, Some("en")),
+            None
+        );
+    }
+}
@@ -0,0 +1,237 @@
+use std::{
+    env,
+    sync::{Arc, Mutex},
+};
+
+use gstreamer as gst;
+
+use anyhow::Error;
+use gst::prelude::*;
+
+use crate::plugin_prac;
+
+/// Caps of the `ReferenceTimestampMeta` the source probe adds
+const STAMP_CAPS: &str = "timestamp/x-latency-probe";
+
+const VIDEO_PIPELINE: &str =
+    "videotestsrc name=src is-live=true num-buffers=300 ! videoconvert ! queue ! autovideosink name=sink";
+const AUDIO_PIPELINE: &str =
+    "psychedelicsrc name=src is-live=true num-buffers=500 ! audioconvert ! queue \
+     ! autoaudiosink name=sink";
+
+/// Latency the pipeline reports: whether it is live and the min/max latency of its sinks
+pub fn query_latency(
+    pipeline: &gst::Element,
+) -> Option<(bool, gst::ClockTime, Option<gst::ClockTime>)> {
+    let mut query = gst::query::Latency::new();
+    if pipeline.query(&mut query) {
+        Some(query.result())
+    } else {
+        None
+    }
+}
+
+fn print_latency(pipeline: &gst::Element) {
+    match query_latency(pipeline) {
+        Some((live, min, max)) => println!(
+            "Pipeline latency: live {live}, min {min}, max {}",
+            max.display()
+        ),
+        None => eprintln!("Latency query failed"),
+    }
+}
+
+/// Summary of measured end-to-end latencies
+#[derive(Debug, Clone, Copy)]
+pub struct LatencySummary {
+    pub count: usize,
+    pub min: gst::ClockTime,
+    pub avg: gst::ClockTime,
+    pub max: gst::ClockTime,
+    pub p50: gst::ClockTime,
+    pub p95: gst::ClockTime,
+    pub p99: gst::ClockTime,
+}
+
+impl LatencySummary {
+    pub fn new(samples: &[gst::ClockTime]) -> Option<LatencySummary> {
+        if samples.is_empty() {
+            return None;
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort();
+        // Nearest rank
+        let percentile = |p: usize| sorted[((sorted.len() * p).div_ceil(100)).saturating_sub(1)];
+        let total: u64 = sorted.iter().map(|t| t.nseconds()).sum();
+
+        Some(LatencySummary {
+            count: sorted.len(),
+            min: sorted[0],
+            avg: gst::ClockTime::from_nseconds(total / sorted.len() as u64),
+            max: sorted[sorted.len() - 1],
+            p50: percentile(50),
+            p95: percentile(95),
+            p99: percentile(99),
+        })
+    }
+}
+
+impl std::fmt::Display for LatencySummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} buffers: min {:.3} avg {:.3} max {:.3} p50 {:.3} p95 {:.3} p99 {:.3}",
+            self.count, self.min, self.avg, self.max, self.p50, self.p95, self.p99
+        )
+    }
+}
+
+/// Stamps every buffer leaving `src` with the pipeline clock time in a
+/// `ReferenceTimestampMeta` and, when it reaches the sink pad of `sink`, records how long it
+/// took. Measures the processing latency of everything in between; the sink then still waits
+/// for the configured pipeline latency before rendering.
+pub struct LatencyProbe {
+    samples: Arc<Mutex<Vec<gst::ClockTime>>>,
+}
+
+impl LatencyProbe {
+    pub fn install(
+        pipeline: &gst::Pipeline,
+        src: &gst::Element,
+        sink: &gst::Element,
+    ) -> Option<LatencyProbe> {
+        let src_pad = src.static_pad("src")?;
+        let sink_pad = sink.static_pad("sink").or_else(|| {
+            // Auto sinks are bins, use the pad of the real sink inside
+            sink.downcast_ref::<gst::Bin>()?
+                .iterate_sinks()
+                .into_iter()
+                .flatten()
+                .next()?
+                .static_pad("sink")
+        })?;
+        let samples = Arc::new(Mutex::new(Vec::new()));
+        let stamp_caps = gst::Caps::new_empty_simple(STAMP_CAPS);
+
+        let pipeline_weak = pipeline.downgrade();
+        src_pad.add_probe(gst::PadProbeType::BUFFER, move |_pad, info| {
+            let Some(now) = pipeline_weak
+                .upgrade()
+                .and_then(|p| p.clock())
+                .and_then(|c| c.time())
+            else {
+                return gst::PadProbeReturn::Ok;
+            };
+            if let Some(gst::PadProbeData::Buffer(ref mut buffer)) = info.data {
+                gst::ReferenceTimestampMeta::add(
+                    buffer.make_mut(),
+                    &stamp_caps,
+                    now,
+                    gst::ClockTime::NONE,
+                );
+            }
+            gst::PadProbeReturn::Ok
+        });
+
+        let pipeline_weak = pipeline.downgrade();
+        let samples_clone = samples.clone();
+        sink_pad.add_probe(gst::PadProbeType::BUFFER, move |_pad, info| {
+            let Some(now) = pipeline_weak
+                .upgrade()
+                .and_then(|p| p.clock())
+                .and_then(|c| c.time())
+            else {
+                return gst::PadProbeReturn::Ok;
+            };
+            if let Some(gst::PadProbeData::Buffer(ref buffer)) = info.data {
+                let stamp = buffer
+                    .iter_meta::<gst::ReferenceTimestampMeta>()
+                    .find(|meta| {
+                        meta.reference()
+                            .structure(0)
+                            .is_some_and(|s| s.has_name(STAMP_CAPS))
+                    })
+                    .map(|meta| meta.timestamp());
+                if let Some(stamp) = stamp {
+                    samples_clone
+                        .lock()
+                        .unwrap()
+                        .push(now.saturating_sub(stamp));
+                }
+            }
+            gst::PadProbeReturn::Ok
+        });
+
+        Some(LatencyProbe { samples })
+    }
+
+    pub fn summary(&self) -> Option<LatencySummary> {
+        LatencySummary::new(&self.samples.lock().unwrap())
+    }
+}
+
+/// Runs a live pipeline (`video`, the default, or `audio` for our `psychedelicsrc`) and
+/// prints the reported pipeline latency and the measured latency every second
+pub fn tutorial_main() -> Result<(), Error> {
+    // Initialize GStreamer
+    gst::init()?;
+    plugin_prac::register()?;
+
+    let description = match env::args().nth(1).as_deref() {
+        Some("audio") => AUDIO_PIPELINE,
+        _ => VIDEO_PIPELINE,
+    };
+    let pipeline = gst::parse_launch(description)?
+        .downcast::<gst::Pipeline>()
+        .unwrap();
+    let src = pipeline.by_name("src").unwrap();
+    let sink = pipeline.by_name("sink").unwrap();
+
+    pipeline.set_state(gst::State::Playing)?;
+    // Auto sinks only create the real sink on the way to PAUSED
+    pipeline.state(gst::ClockTime::NONE).0?;
+    let probe =
+        LatencyProbe::install(&pipeline, &src, &sink).expect("Failed to install latency probes");
+
+    let bus = pipeline.bus().unwrap();
+    loop {
+        let Some(msg) = bus.timed_pop(gst::ClockTime::SECOND) else {
+            if let Some(summary) = probe.summary() {
+                println!("Measured latency over {summary}");
+            }
+            continue;
+        };
+
+        use gst::MessageView;
+        match msg.view() {
+            MessageView::Latency(..) => {
+                // A latency changed somewhere, distribute the new one and show it
+                if let Err(err) = pipeline.recalculate_latency() {
+                    eprintln!("Failed to recalculate latency: {err}");
+                }
+                print_latency(pipeline.upcast_ref());
+            }
+            MessageView::Error(err) => {
+                eprintln!(
+                    "Error received from element {:?}: {}",
+                    err.src().map(|s| s.path_string()),
+                    err.error()
+                );
+                eprintln!("Debugging information: {:?}", err.debug());
+                break;
+            }
+            MessageView::Eos(..) => break,
+            _ => (),
+        }
+    }
+
+    print_latency(pipeline.upcast_ref());
+    match probe.summary() {
+        Some(summary) => println!("Final measured latency over {summary}"),
+        None => println!("No buffers reached the sink"),
+    }
+
+    pipeline.set_state(gst::State::Null)?;
+
+    Ok(())
+}
@@ -0,0 +1,521 @@
+use std::{
+    collections::VecDeque,
+    env,
+    f64::consts::PI,
+    path::{Path, PathBuf},
+};
+
+use gstreamer as gst;
+use gstreamer_app as gst_app;
+use gstreamer_audio as gst_audio;
+use gstreamer_pbutils as gst_pbutils;
+
+use anyhow::Error;
+use byte_slice_cast::*;
+use gst::prelude::*;
+
+use crate::{basic_tutorial_9, playlist, plugin_prac};
+
+/// EBU R128 programme loudness
+const DEFAULT_TARGET: f64 = -23.0;
+/// Highest true peak the gain may push the audio to, in dBTP
+const DEFAULT_CEILING: f64 = -1.0;
+/// Largest gain `rsgain` takes, +20 dB
+const MAX_GAIN: f64 = 10.0;
+/// Blocks quieter than this never count, in LUFS
+const ABSOLUTE_GATE: f64 = -70.0;
+/// Blocks are built from 100 ms segments: 4 for momentary, 30 for short-term loudness
+const MOMENTARY_SEGMENTS: usize = 4;
+const SHORT_TERM_SEGMENTS: usize = 30;
+/// Taps per phase of the true peak interpolation filter
+const INTERPOLATION_TAPS: usize = 12;
+
+/// Measurements of one file, loudness in LUFS, range in LU and peak in dBTP. Loudness is None
+/// when the audio never gets above the absolute gate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Loudness {
+    pub integrated: Option<f64>,
+    pub range: Option<f64>,
+    pub max_momentary: Option<f64>,
+    pub max_short_term: Option<f64>,
+    pub true_peak: f64,
+    pub duration: gst::ClockTime,
+}
+
+impl Loudness {
+    /// Gain in dB that brings the integrated loudness to `target` without the true peak going
+    /// over `ceiling`, and whether the ceiling made it smaller
+    pub fn gain_to(&self, target: f64, ceiling: f64) -> Option<(f64, bool)> {
+        let gain = target - self.integrated?;
+        let headroom = ceiling - self.true_peak;
+        Some(if gain > headroom {
+            (headroom, true)
+        } else {
+            (gain, false)
+        })
+    }
+}
+
+fn energy_to_lufs(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+fn format_lufs(value: Option<f64>) -> String {
+    match value {
+        Some(value) => format!("{value:.1} LUFS"),
+        None => "-inf LUFS".to_string(),
+    }
+}
+
+/// Second order IIR filter, transposed direct form II
+#[derive(Debug, Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// The K-weighting of ITU-R BS.1770: a high shelf for the head followed by a high pass. The
+/// coefficients are designed for `rate`, at 48 kHz they are the ones printed in the standard.
+fn k_weighting(rate: u32) -> [Biquad; 2] {
+    let rate = rate as f64;
+
+    let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    [shelf, high_pass]
+}
+
+/// Weight of a channel in the sum: surrounds count 1.41, the LFE not at all
+fn channel_weight(position: gst_audio::AudioChannelPosition) -> f64 {
+    use gst_audio::AudioChannelPosition as Position;
+
+    match position {
+        Position::RearLeft | Position::RearRight | Position::SideLeft | Position::SideRight => 1.41,
+        Position::Lfe1 | Position::Lfe2 => 0.0,
+        _ => 1.0,
+    }
+}
+
+/// Estimates the peak between samples by oversampling with a windowed sinc, 4 times below
+/// 96 kHz and 2 times below 192 kHz as BS.1770 asks
+struct TruePeak {
+    /// One row of taps per phase, phase 0 is the sample itself
+    phases: Vec<[f64; INTERPOLATION_TAPS]>,
+    /// Last samples of every channel, oldest first
+    history: Vec<VecDeque<f64>>,
+    peak: f64,
+}
+
+impl TruePeak {
+    fn new(rate: u32, channels: usize) -> TruePeak {
+        let factor = match rate {
+            0..=95_999 => 4,
+            96_000..=191_999 => 2,
+            _ => 1,
+        };
+        let center = (INTERPOLATION_TAPS / 2 - 1) as f64;
+        let half_width = (INTERPOLATION_TAPS / 2) as f64;
+        let phases = (0..factor)
+            .map(|phase| {
+                let mut taps = [0.0; INTERPOLATION_TAPS];
+                for (j, tap) in taps.iter_mut().enumerate() {
+                    let d = center + phase as f64 / factor as f64 - j as f64;
+                    let sinc = if d == 0.0 {
+                        1.0
+                    } else {
+                        (PI * d).sin() / (PI * d)
+                    };
+                    let window = 0.5 * (1.0 + (PI * d / half_width).cos());
+                    *tap = sinc * window;
+                }
+                taps
+            })
+            .collect();
+
+        TruePeak {
+            phases,
+            history: vec![VecDeque::from(vec![0.0; INTERPOLATION_TAPS]); channels],
+            peak: 0.0,
+        }
+    }
+
+    fn push(&mut self, channel: usize, sample: f64) {
+        let history = &mut self.history[channel];
+        history.pop_front();
+        history.push_back(sample);
+        for taps in &self.phases {
+            let value: f64 = taps.iter().zip(history.iter()).map(|(t, x)| t * x).sum();
+            self.peak = self.peak.max(value.abs());
+        }
+    }
+}
+
+/// Measures loudness as EBU R128 describes it, fed with interleaved F64 samples
+pub struct LoudnessMeter {
+    filters: Vec<[Biquad; 2]>,
+    weights: Vec<f64>,
+    /// Frames in a 100 ms segment
+    segment_frames: usize,
+    /// Frames and squared filtered samples per channel of the segment being filled
+    frames: usize,
+    sums: Vec<f64>,
+    /// Weighted mean square of the last segments, newest last
+    segments: VecDeque<f64>,
+    /// Energy of every 400 ms and 3 s block, overlapping by all but one segment
+    momentary: Vec<f64>,
+    short_term: Vec<f64>,
+    true_peak: TruePeak,
+    total_frames: u64,
+    rate: u32,
+}
+
+impl LoudnessMeter {
+    pub fn new(info: &gst_audio::AudioInfo) -> LoudnessMeter {
+        let channels = info.channels() as usize;
+        let weights = match info.positions() {
+            Some(positions) => positions.iter().map(|&p| channel_weight(p)).collect(),
+            None => vec![1.0; channels],
+        };
+
+        LoudnessMeter {
+            filters: vec![k_weighting(info.rate()); channels],
+            weights,
+            segment_frames: (info.rate() / 10) as usize,
+            frames: 0,
+            sums: vec![0.0; channels],
+            segments: VecDeque::with_capacity(SHORT_TERM_SEGMENTS),
+            momentary: Vec::new(),
+            short_term: Vec::new(),
+            true_peak: TruePeak::new(info.rate(), channels),
+            total_frames: 0,
+            rate: info.rate(),
+        }
+    }
+
+    pub fn push(&mut self, samples: &[f64]) {
+        let channels = self.sums.len();
+        for frame in samples.chunks_exact(channels) {
+            for (channel, &sample) in frame.iter().enumerate() {
+                let [shelf, high_pass] = &mut self.filters[channel];
+                let filtered = high_pass.process(shelf.process(sample));
+                self.sums[channel] += filtered * filtered;
+                self.true_peak.push(channel, sample);
+            }
+            self.frames += 1;
+            self.total_frames += 1;
+            if self.frames == self.segment_frames {
+                self.finish_segment();
+            }
+        }
+    }
+
+    fn finish_segment(&mut self) {
+        let energy: f64 = self
+            .sums
+            .iter()
+            .zip(&self.weights)
+            .map(|(sum, weight)| weight * sum / self.frames as f64)
+            .sum();
+        self.sums.iter_mut().for_each(|sum| *sum = 0.0);
+        self.frames = 0;
+
+        if self.segments.len() == SHORT_TERM_SEGMENTS {
+            self.segments.pop_front();
+        }
+        self.segments.push_back(energy);
+        let mean =
+            |count: usize| self.segments.iter().rev().take(count).sum::<f64>() / count as f64;
+        if self.segments.len() >= MOMENTARY_SEGMENTS {
+            self.momentary.push(mean(MOMENTARY_SEGMENTS));
+        }
+        if self.segments.len() == SHORT_TERM_SEGMENTS {
+            self.short_term.push(mean(SHORT_TERM_SEGMENTS));
+        }
+    }
+
+    /// Energies above the absolute gate and the relative gate `relative` LU under their mean
+    fn gated(blocks: &[f64], relative: f64) -> Vec<f64> {
+        let absolute: Vec<f64> = blocks
+            .iter()
+            .copied()
+            .filter(|&energy| energy_to_lufs(energy) > ABSOLUTE_GATE)
+            .collect();
+        if absolute.is_empty() {
+            return absolute;
+        }
+        let gate = energy_to_lufs(absolute.iter().sum::<f64>() / absolute.len() as f64) + relative;
+        absolute
+            .into_iter()
+            .filter(|&energy| energy_to_lufs(energy) > gate)
+            .collect()
+    }
+
+    pub fn result(&self) -> Loudness {
+        let integrated = Self::gated(&self.momentary, -10.0);
+        let integrated = (!integrated.is_empty())
+            .then(|| energy_to_lufs(integrated.iter().sum::<f64>() / integrated.len() as f64));
+
+        // EBU Tech 3342: spread between the 10th and 95th percentile of the short-term loudness
+        let mut short_term: Vec<f64> = Self::gated(&self.short_term, -20.0)
+            .into_iter()
+            .map(energy_to_lufs)
+            .collect();
+        short_term.sort_by(f64::total_cmp);
+        let percentile = |p: f64| short_term[((short_term.len() - 1) as f64 * p).round() as usize];
+        let range = (!short_term.is_empty()).then(|| percentile(0.95) - percentile(0.10));
+
+        let max = |blocks: &[f64]| {
+            blocks
+                .iter()
+                .copied()
+                .reduce(f64::max)
+                .map(energy_to_lufs)
+                .filter(|&lufs| lufs > ABSOLUTE_GATE)
+        };
+
+        Loudness {
+            integrated,
+            range,
+            max_momentary: max(&self.momentary),
+            max_short_term: max(&self.short_term),
+            true_peak: 20.0 * self.true_peak.peak.log10(),
+            duration: gst::ClockTime::SECOND
+                .mul_div_floor(self.total_frames, u64::from(self.rate))
+                .expect("u64 overflow"),
+        }
+    }
+}
+
+/// Decodes the audio of `uri` as fast as possible and measures it. Only the first audio
+/// stream is measured.
+pub fn measure(uri: &str) -> Result<Loudness, Error> {
+    let pipeline = gst::parse_launch(&format!(
+        "uridecodebin uri=\"{uri}\" caps=audio/x-raw ! audioconvert \
+         ! appsink name=sink sync=false caps=audio/x-raw,format=F64LE,layout=interleaved"
+    ))?
+    .downcast::<gst::Pipeline>()
+    .unwrap();
+    let appsink = pipeline
+        .by_name("sink")
+        .unwrap()
+        .downcast::<gst_app::AppSink>()
+        .unwrap();
+
+    pipeline.set_state(gst::State::Playing)?;
+    let bus = pipeline.bus().unwrap();
+    let mut meter: Option<LoudnessMeter> = None;
+    let mut error = None;
+    loop {
+        if let Some(sample) = appsink.try_pull_sample(gst::ClockTime::from_mseconds(100)) {
+            if meter.is_none() {
+                let info = gst_audio::AudioInfo::from_caps(sample.caps().unwrap())?;
+                meter = Some(LoudnessMeter::new(&info));
+            }
+            let meter = meter.as_mut().unwrap();
+            let map = sample.buffer().unwrap().map_readable()?;
+            meter.push(map.as_slice_of::<f64>()?);
+            continue;
+        }
+        if appsink.is_eos() {
+            break;
+        }
+        if let Some(msg) = bus.pop_filtered(&[gst::MessageType::Error]) {
+            if let gst::MessageView::Error(err) = msg.view() {
+                error = Some(err.error());
+            }
+            break;
+        }
+    }
+    pipeline.set_state(gst::State::Null)?;
+    if let Some(err) = error {
+        return Err(err.into());
+    }
+
+    meter
+        .map(|meter| meter.result())
+        .ok_or_else(|| glib::bool_error!("No audio in {uri}").into())
+}
+
+/// Encoder for a transcode output, picked by extension
+fn encoder_description(path: &Path) -> Result<&'static str, Error> {
+    Ok(match path.extension().and_then(|e| e.to_str()) {
+        Some("wav") => "wavenc",
+        Some("flac") => "flacenc",
+        Some("ogg" | "oga") => "vorbisenc ! oggmux",
+        Some("opus") => "opusenc ! oggmux",
+        _ => {
+            return Err(glib::bool_error!(
+                "Unknown output type {}, use wav, flac, ogg or opus",
+                path.display()
+            )
+            .into())
+        }
+    })
+}
+
+/// Plays `uri`, or writes its audio to `output`, with `gain` dB applied by `rsgain`
+pub fn apply_gain(uri: &str, gain: f64, output: Option<&Path>) -> Result<(), Error> {
+    let sink = match output {
+        Some(path) => format!(
+            "audioconvert ! {} ! filesink location=\"{}\"",
+            encoder_description(path)?,
+            path.display()
+        ),
+        None => "audioconvert ! audioresample ! autoaudiosink".to_string(),
+    };
+    let pipeline = gst::parse_launch(&format!(
+        "uridecodebin uri=\"{uri}\" caps=audio/x-raw ! audioconvert ! rsgain gain={} ! {sink}",
+        10f64.powf(gain / 20.0)
+    ))?;
+
+    pipeline.set_state(gst::State::Playing)?;
+
+    // Wait until error or EOS
+    let bus = pipeline.bus().unwrap();
+    let mut result = Ok(());
+    for msg in bus.iter_timed(gst::ClockTime::NONE) {
+        use gst::MessageView;
+
+        match msg.view() {
+            MessageView::Error(err) => {
+                eprintln!("Debugging information: {:?}", err.debug());
+                result = Err(err.error().into());
+                break;
+            }
+            MessageView::Eos(..) => break,
+            _ => (),
+        }
+    }
+
+    pipeline.set_state(gst::State::Null)?;
+    result
+}
+
+fn print_loudness(loudness: &Loudness) {
+    println!("Loudness:");
+    println!("  Integrated: {}", format_lufs(loudness.integrated));
+    match loudness.range {
+        Some(range) => println!("  Range: {range:.1} LU"),
+        None => println!("  Range: not measured, shorter than 3 s or silent"),
+    }
+    println!("  Max momentary: {}", format_lufs(loudness.max_momentary));
+    println!("  Max short-term: {}", format_lufs(loudness.max_short_term));
+    println!("  True peak: {:.1} dBTP", loudness.true_peak);
+    println!("  Measured: {:.1}", loudness.duration);
+}
+
+/// Prints what the discoverer knows about `uri`, as basic tutorial 9 does
+fn discover(uri: &str) -> Result<(), Error> {
+    let discoverer = gst_pbutils::Discoverer::new(5 * gst::ClockTime::SECOND)?;
+    match discoverer.discover_uri(uri) {
+        Ok(info) => basic_tutorial_9::on_discovered(&discoverer, &info, None),
+        Err(err) => println!("Failed to discover {uri}: {err}"),
+    }
+    Ok(())
+}
+
+/// Measures the loudness of the file, directory or playlist given as first argument and
+/// prints it with the stream info. `--target=LUFS` (default -23) and `--ceiling=DBTP`
+/// (default -1) set the gain, `--play` plays every file with it and `--output=FILE`
+/// (.wav, .flac, .ogg, .opus) writes a single file with it and measures the result.
+pub fn tutorial_main() -> Result<(), Error> {
+    // Initialize GStreamer
+    gst::init()?;
+    plugin_prac::register()?;
+
+    let mut location = None;
+    let mut target = DEFAULT_TARGET;
+    let mut ceiling = DEFAULT_CEILING;
+    let mut play = false;
+    let mut output = None;
+    for arg in env::args().skip(1) {
+        if !arg.starts_with("--") {
+            location = Some(arg);
+            continue;
+        }
+        let (name, value) = arg.split_once('=').unwrap_or((arg.as_str(), ""));
+        match name {
+            "--target" => target = value.parse()?,
+            "--ceiling" => ceiling = value.parse()?,
+            "--play" => play = true,
+            "--output" => output = Some(PathBuf::from(value)),
+            _ => return Err(glib::bool_error!("Unknown option {arg}").into()),
+        }
+    }
+
+    let location = location.ok_or_else(|| glib::bool_error!("No file given"))?;
+    let entries = playlist::load(&location)?;
+    if output.is_some() && entries.len() != 1 {
+        return Err(glib::bool_error!("--output needs a single file").into());
+    }
+
+    for uri in &entries {
+        discover(uri)?;
+        let loudness = match measure(uri) {
+            Ok(loudness) => loudness,
+            Err(err) => {
+                eprintln!("Failed to measure {uri}: {err}");
+                continue;
+            }
+        };
+        print_loudness(&loudness);
+
+        let Some((gain, limited)) = loudness.gain_to(target, ceiling) else {
+            println!("Silent, no gain to apply\n");
+            continue;
+        };
+        let gain = gain.min(20.0 * MAX_GAIN.log10());
+        println!(
+            "Gain to {target:.1} LUFS: {gain:+.1} dB{}\n",
+            if limited {
+                format!(", limited by the {ceiling:.1} dBTP ceiling")
+            } else {
+                String::new()
+            }
+        );
+
+        if play {
+            println!("Playing with {gain:+.1} dB");
+            apply_gain(uri, gain, None)?;
+        }
+        if let Some(path) = &output {
+            apply_gain(uri, gain, Some(path.as_path()))?;
+            let uri = glib::filename_to_uri(path.canonicalize()?, None)?;
+            println!("Wrote {}", path.display());
+            print_loudness(&measure(&uri)?);
+        }
+    }
+
+    Ok(())
+}
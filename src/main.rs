@@ -1,15 +1,36 @@
+mod audio_matrix;
 mod basic_tutorial_1;
 mod basic_tutorial_2;
 mod basic_tutorial_3;
 mod basic_tutorial_4;
 mod basic_tutorial_6;
+mod buffer_tracer;
+mod buffering;
+mod channel_layout;
+mod compositor;
+mod crossfade;
+mod language_preferences;
 mod playback_tutorial_1;
 mod playback_tutorial_2;
+mod playbin3_streams;
+mod playlist;
+mod qos_stats;
+mod subtitle;
+mod tee_branches;
+mod subtitle_extract;
+mod test_pattern;
+mod video_matrix;
+mod timecode;
 mod get_frame;
+mod hot_swap;
+mod http_server;
+mod input;
+mod latency;
+mod loudness;
 mod basic_tutorial_9;
 mod basic_tutorial_8;
 mod basic_tutorial_8_custom;
-// mod plugin_prac;
+mod plugin_prac;
 
 fn main() {
     // tutorials_common::run is only required to set up the application environment on macOS
@@ -25,6 +46,18 @@ fn main() {
 
     // playback_tutorial_1::tutorial_main();
     // playback_tutorial_2::tutorial_main();
+    // playbin3_streams::tutorial_main();
+    // playlist::tutorial_main();
+    // crossfade::tutorial_main();
+    // compositor::tutorial_main();
+    // test_pattern::tutorial_main();
+    // video_matrix::tutorial_main();
+    // audio_matrix::tutorial_main();
+    // loudness::tutorial_main();
+    // subtitle_extract::tutorial_main();
+    // http_server::tutorial_main();
+    // latency::tutorial_main();
+    // buffer_tracer::tutorial_main();
 
     // get_frame::main();
 
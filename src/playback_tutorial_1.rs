@@ -1,14 +1,12 @@
-use std::{thread, time};
-
 use gstreamer as gst;
 
 use anyhow::Error;
 use glib::FlagsClass;
 use gst::prelude::*;
-use crossterm::{
-    event::{KeyCode, KeyEvent, KeyModifiers, read},
-    terminal::{disable_raw_mode, enable_raw_mode},
-};
+
+use crate::buffering::{self, BufferingController};
+use crate::input::{InputController, Keymap};
+use crate::language_preferences::{self, LanguagePreferences};
 
 
 fn analyze_streams(playbin: &gst::Element) {
@@ -62,52 +60,27 @@ fn analyze_streams(playbin: &gst::Element) {
     println!(
         "Currently playing video stream {current_video}, audio stream {current_audio}, text stream {current_text}"
     );
-    println!("Type any number and hit ENTER to select a different audio stream");
+    println!("Press a number to select a different audio stream, Ctrl+C to quit");
 }
 
-fn handle_keyboard(playbin: &gst::Element, main_loop: &glib::MainLoop) {
-    enable_raw_mode().expect("Failed to enable raw mode");
-
-    loop {
-        if let Ok(event) = read() {
-            match event {
-                crossterm::event::Event::Key(KeyEvent {
-                                                 code,
-                                                 kind: KeyEventKind,
-                                                 modifiers,
-                                                 state,
-                                             }) => {
-                    if let KeyCode::Char(c) = code {
-                        if let Some(index) = c.to_digit(10) {
-                            // Here index can only be 0-9
-                            let index = index as i32;
-                            let n_audio = playbin.property::<i32>("n-audio");
-
-                            if index < n_audio {
-                                println!("Setting current audio stream to {}", index);
-                                playbin.set_property("current-audio", index);
-                            } else {
-                                eprintln!("Index out of bounds");
-                            }
-                        }
-                    }
-                }
-                crossterm::event::Event::Key(KeyEvent {
-                                                 code: KeyCode::Char('c'),
-                                                 modifiers: KeyModifiers::CONTROL,
-                                                 state: state,
-                                                 kind: kind,
-                                             }) => {
-                    main_loop.quit();
-                    break;
-                }
-                _ => continue,
-            };
+#[derive(Debug, Clone, Copy)]
+enum Action {
+    SelectAudio(i32),
+}
+
+fn handle_action(playbin: &gst::Element, action: Action) {
+    match action {
+        Action::SelectAudio(index) => {
+            let n_audio = playbin.property::<i32>("n-audio");
+
+            if index < n_audio {
+                println!("Setting current audio stream to {}", index);
+                playbin.set_property("current-audio", index);
+            } else {
+                eprintln!("Index out of bounds");
+            }
         }
-        thread::sleep(time::Duration::from_millis(50));
     }
-
-    disable_raw_mode().expect("Failed to disable raw mode");
 }
 
 
@@ -142,18 +115,30 @@ pub fn tutorial_main() -> Result<(), Error> {
         .build()
         .unwrap();
     playbin.set_property_from_value("flags", &flags);
+    buffering::progressive_download_from_env(&playbin);
 
-    // Handle keyboard input
+    // Handle keyboard input, number keys pick the audio stream
+    let keymap = Keymap::new().bind_digits(|index| Action::SelectAudio(index as i32));
     let playbin_clone = playbin.clone();
-    let main_loop_clone = main_loop.clone();
-    thread::spawn(move || handle_keyboard(&playbin_clone, &main_loop_clone));
+    let input = InputController::start(keymap, &main_loop, move |action| {
+        handle_action(&playbin_clone, action)
+    })?;
 
     // Add a bus watch, so we get notified when a message arrives
     let playbin_clone = playbin.clone();
     let main_loop_clone = main_loop.clone();
+    let preferences = LanguagePreferences::load();
+    let mut preferences_applied = false;
+    let mut buffering_controller = BufferingController::new(&playbin);
     let bus = playbin.bus().unwrap();
     let _bus_watch = bus.add_watch(move |_bus, message| {
         use gst::MessageView;
+
+        // Pause while the network queues refill
+        if buffering_controller.handle_message(message) {
+            return glib::ControlFlow::Continue;
+        }
+
         match message.view() {
             MessageView::Error(err) => {
                 eprintln!(
@@ -172,6 +157,14 @@ pub fn tutorial_main() -> Result<(), Error> {
                     .unwrap_or(false)
                     && state_changed.current() == gst::State::Playing
                 {
+                    if !preferences_applied {
+                        let audio_language =
+                            language_preferences::apply_audio_preferences(&playbin_clone, &preferences);
+                        if let Some(language) = audio_language {
+                            println!("Playing audio in language {language}");
+                        }
+                        preferences_applied = true;
+                    }
                     analyze_streams(&playbin_clone);
                 }
                 glib::ControlFlow::Continue
@@ -191,6 +184,9 @@ pub fn tutorial_main() -> Result<(), Error> {
     // Set GLib mainlooop to run
     main_loop.run();
 
+    // Restore the terminal, the main loop also quits on EOS and errors
+    drop(input);
+
     // Clean up
     playbin.set_state(gst::State::Null)?;
 
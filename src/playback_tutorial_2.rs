@@ -1,4 +1,8 @@
-use std::{thread, time};
+use std::{
+    env,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
 
 
 use gstreamer as gst;
@@ -7,10 +11,126 @@ use anyhow::Error;
 use glib::FlagsClass;
 use gst::prelude::*;
 
-use crossterm::{
-    event::{KeyCode, KeyEvent, KeyModifiers, read},
-    terminal::{disable_raw_mode, enable_raw_mode},
-};
+use crossterm::event::KeyCode;
+
+use crate::buffering::{self, BufferingController};
+use crate::input::{InputController, Keymap};
+use crate::language_preferences::{self, LanguagePreferences};
+use crate::plugin_prac::{self, overlay::Overlay};
+use crate::subtitle::{self, SubtitleFormat, SubtitleWriter};
+
+const TEXT_OFFSET_STEP: i64 = 100_000_000; // Subtitle delay step in nanoseconds (100 ms)
+
+/// Look of the rendered subtitles, read from `SUBTITLE_FONT`, `SUBTITLE_SIZE`,
+/// `SUBTITLE_COLOR` and `SUBTITLE_OUTLINE_COLOR` (colours as ARGB hex, e.g. `ffffff00`)
+struct SubtitleStyle {
+    font: String,
+    size: u32,
+    color: u32,
+    outline_color: u32,
+}
+
+impl SubtitleStyle {
+    fn from_env() -> SubtitleStyle {
+        let color = |name: &str, default: u32| {
+            env::var(name)
+                .ok()
+                .and_then(|v| u32::from_str_radix(v.trim_start_matches("0x"), 16).ok())
+                .unwrap_or(default)
+        };
+
+        SubtitleStyle {
+            font: env::var("SUBTITLE_FONT").unwrap_or_else(|_| "Sans".to_string()),
+            size: env::var("SUBTITLE_SIZE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(18),
+            color: color("SUBTITLE_COLOR", 0xffff_ffff),
+            outline_color: color("SUBTITLE_OUTLINE_COLOR", 0xff00_0000),
+        }
+    }
+
+    fn font_desc(&self) -> String {
+        format!("{}, {}", self.font, self.size)
+    }
+}
+
+/// Copies every cue of the active subtitle stream into an SRT file until dropped
+struct SubtitleDump {
+    pad: gst::Pad,
+    probe_id: Option<gst::PadProbeId>,
+    path: PathBuf,
+}
+
+impl SubtitleDump {
+    fn start(playbin: &gst::Element) -> Option<SubtitleDump> {
+        let current = playbin.property::<i32>("current-text");
+        if current < 0 {
+            eprintln!("No subtitle stream selected");
+            return None;
+        }
+
+        let pad = playbin.emit_by_name::<Option<gst::Pad>>("get-text-pad", &[&current])?;
+        let language = playbin
+            .emit_by_name::<Option<gst::TagList>>("get-text-tags", &[&current])
+            .and_then(|tags| tags.get::<gst::tags::LanguageCode>().map(|l| l.get().to_string()))
+            .unwrap_or_else(|| "und".to_string());
+        let path = PathBuf::from(format!("subtitle_{current}.{language}.srt"));
+
+        let writer = match SubtitleWriter::create(&path, SubtitleFormat::Srt) {
+            Ok(writer) => Mutex::new(writer),
+            Err(err) => {
+                eprintln!("Failed to create {}: {err}", path.display());
+                return None;
+            }
+        };
+
+        let probe_id = pad.add_probe(gst::PadProbeType::BUFFER, move |pad, info| {
+            let Some(gst::PadProbeData::Buffer(ref buffer)) = info.data else {
+                return gst::PadProbeReturn::Ok;
+            };
+            let (Some(pts), Some(duration)) = (buffer.pts(), buffer.duration()) else {
+                return gst::PadProbeReturn::Ok;
+            };
+
+            // Cues are written in stream time so they line up with the media
+            let start = pad
+                .sticky_event::<gst::event::Segment>(0)
+                .and_then(|event| {
+                    event
+                        .segment()
+                        .downcast_ref::<gst::ClockTime>()
+                        .and_then(|segment| segment.to_stream_time(pts))
+                })
+                .unwrap_or(pts);
+
+            if let Ok(map) = buffer.map_readable() {
+                let text = subtitle::strip_markup(&String::from_utf8_lossy(&map));
+                if let Err(err) = writer.lock().unwrap().write_cue(start, start + duration, &text) {
+                    eprintln!("Failed to write subtitle cue: {err}");
+                }
+            }
+
+            gst::PadProbeReturn::Ok
+        })?;
+
+        println!("Dumping subtitle stream {current} to {}", path.display());
+        Some(SubtitleDump {
+            pad,
+            probe_id: Some(probe_id),
+            path,
+        })
+    }
+}
+
+impl Drop for SubtitleDump {
+    fn drop(&mut self) {
+        if let Some(probe_id) = self.probe_id.take() {
+            self.pad.remove_probe(probe_id);
+        }
+        println!("Stopped dumping subtitles to {}", self.path.display());
+    }
+}
 
 fn analyze_streams(playbin: &gst::Element) {
     let n_video = playbin.property::<i32>("n-video");
@@ -65,52 +185,119 @@ fn analyze_streams(playbin: &gst::Element) {
     println!(
         "Currently playing video stream {current_video}, audio stream {current_audio}, subtitle stream {current_text}"
     );
-    println!("Type any number and hit ENTER to select a different subtitle stream");
+    println!("Press a number to select a different subtitle stream, Ctrl+C to quit");
+    println!("Press + / - to delay / advance the subtitles, d to start or stop dumping them");
 }
 
-fn handle_keyboard(playbin: &gst::Element, main_loop: &glib::MainLoop) {
-    enable_raw_mode().expect("Failed to enable raw mode");
-
-    loop {
-        if let Ok(event) = read() {
-            match event {
-                crossterm::event::Event::Key(KeyEvent {
-                                                 code,
-                                                 kind: kind,
-                                                 modifiers,
-                                                 state,
-                                             }) => {
-                    if let KeyCode::Char(c) = code {
-                        if let Some(index) = c.to_digit(10) {
-                            // Here index can only be 0-9
-                            let index = index as i32;
-                            let n_text = playbin.property::<i32>("n-text");
-
-                            if index < n_text {
-                                println!("Setting current subtitle stream to {}", index);
-                                playbin.set_property("current-text", index);
-                            } else {
-                                eprintln!("Index out of bounds");
-                            }
-                        }
-                    }
-                }
-                crossterm::event::Event::Key(KeyEvent {
-                                                 code: KeyCode::Char('c'),
-                                                 modifiers: KeyModifiers::CONTROL,
-                                                 state,
-                                                 kind,
-                                             }) => {
-                    main_loop.quit();
-                    break;
-                }
-                _ => continue,
+/// Picks the media and subtitle URIs. A local file given on the command line gets the
+/// first sidecar subtitle file found next to it.
+fn media_uris() -> Result<(String, Option<String>), Error> {
+    let Some(arg) = env::args().nth(1) else {
+        return Ok((
+            "https://gstreamer.freedesktop.org/data/media/sintel_trailer-480p.ogv".to_string(),
+            Some("https://gstreamer.freedesktop.org/data/media/sintel_trailer_gr.srt".to_string()),
+        ));
+    };
+
+    if arg.contains("://") {
+        return Ok((arg, None));
+    }
+
+    let path = Path::new(&arg).canonicalize()?;
+    let uri = glib::filename_to_uri(&path, None)?.to_string();
+
+    let sidecars = subtitle::find_sidecars(&path);
+    for sidecar in &sidecars {
+        println!(
+            "Found {:?} subtitles {} (language: {})",
+            sidecar.format,
+            sidecar.path.display(),
+            sidecar.language.as_deref().unwrap_or("unknown")
+        );
+    }
+    let subtitle_uri = match sidecars.first() {
+        Some(sidecar) => Some(sidecar.uri()?.to_string()),
+        None => None,
+    };
+
+    Ok((uri, subtitle_uri))
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Action {
+    SelectText(i32),
+    /// Shift the subtitles by this many nanoseconds
+    Delay(i64),
+    ToggleDump,
+}
+
+fn handle_action(playbin: &gst::Element, dump: &mut Option<SubtitleDump>, action: Action) {
+    match action {
+        Action::SelectText(index) => {
+            let n_text = playbin.property::<i32>("n-text");
+
+            if index < n_text {
+                println!("Setting current subtitle stream to {}", index);
+                playbin.set_property("current-text", index);
+                // The language preferences may have hidden the subtitles
+                language_preferences::set_subtitles_visible(playbin, true);
+            } else {
+                eprintln!("Index out of bounds");
+            }
+        }
+        Action::Delay(step) => {
+            let offset = playbin.property::<i64>("text-offset") + step;
+            playbin.set_property("text-offset", offset);
+            println!("Subtitle delay is now {} ms", offset / 1_000_000);
+        }
+        Action::ToggleDump => {
+            // Dropping the running dump stops it
+            *dump = match dump.take() {
+                Some(_) => None,
+                None => SubtitleDump::start(playbin),
             };
         }
-        thread::sleep(time::Duration::from_millis(50));
     }
+}
+
+fn current_language(playbin: &gst::Element, tags_signal: &str, current_property: &str) -> String {
+    let current = playbin.property::<i32>(current_property);
+    if current < 0 {
+        return "off".to_string();
+    }
+    playbin
+        .emit_by_name::<Option<gst::TagList>>(tags_signal, &[&current])
+        .and_then(|tags| tags.get::<gst::tags::LanguageCode>().map(|language| language.get().to_string()))
+        .unwrap_or_else(|| "und".to_string())
+}
+
+/// Video filter that burns the position and the current audio/subtitle languages into the
+/// picture, enabled with `DEBUG_OVERLAY=1`
+fn debug_overlay(playbin: &gst::Element) -> Result<gst::Element, Error> {
+    plugin_prac::register()?;
+
+    let bin = gst::parse_bin_from_description("videoconvert ! rsoverlay name=overlay ! videoconvert", true)?;
+    let overlay = bin.by_name("overlay").unwrap().downcast::<Overlay>().unwrap();
 
-    disable_raw_mode().expect("Failed to disable raw mode");
+    let playbin_weak = playbin.downgrade();
+    overlay.set_draw_callback(move |frame| {
+        let Some(playbin) = playbin_weak.upgrade() else {
+            return Vec::new();
+        };
+        let audio = current_language(&playbin, "get-audio-tags", "current-audio");
+        let text = current_language(&playbin, "get-text-tags", "current-text");
+        // Bottom left, clear of the built-in timing lines
+        frame
+            .text(
+                epaint::pos2(6.0, frame.height - 30.0),
+                format!("audio: {audio}  subtitles: {text}"),
+                16.0,
+                epaint::Color32::YELLOW,
+            )
+            .into()
+    });
+
+    Ok(bin.upcast())
 }
 
 pub fn tutorial_main() -> Result<(), Error> {
@@ -120,19 +307,42 @@ pub fn tutorial_main() -> Result<(), Error> {
     // Initialize GStreamer
     gst::init()?;
 
-    let uri = "https://gstreamer.freedesktop.org/data/media/sintel_trailer-480p.ogv";
-    let subtitle_uri = "https://gstreamer.freedesktop.org/data/media/sintel_trailer_gr.srt";
+    let (uri, subtitle_uri) = media_uris()?;
+    let style = SubtitleStyle::from_env();
 
     // Create PlayBin element
     let playbin = gst::ElementFactory::make("playbin")
         .name("playbin")
         // Set URI to play
-        .property("uri", uri)
-        // Set the subtitle URI and font description
-        .property("suburi", subtitle_uri)
-        .property("subtitle-font-desc", "Sans, 18")
+        .property("uri", &uri)
+        // Set the font description
+        .property("subtitle-font-desc", style.font_desc())
         .build()?;
 
+    // Set the subtitle URI, if there is one
+    if let Some(subtitle_uri) = &subtitle_uri {
+        playbin.set_property("suburi", subtitle_uri);
+    }
+
+    if env::var("DEBUG_OVERLAY").is_ok_and(|value| value == "1") {
+        playbin.set_property("video-filter", debug_overlay(&playbin)?);
+    }
+
+    // Colours are only exposed by the textoverlay inside playbin, so style it once it is created
+    playbin
+        .downcast_ref::<gst::Bin>()
+        .unwrap()
+        .connect_deep_element_added(move |_playbin, _bin, element| {
+            let is_textoverlay = element
+                .factory()
+                .map(|factory| factory.name() == "textoverlay")
+                .unwrap_or(false);
+            if is_textoverlay {
+                element.set_property("color", style.color);
+                element.set_property("outline-color", style.outline_color);
+            }
+        });
+
     // Set flags to show Audio, Video and Subtitles
     let flags = playbin.property_value("flags");
     let flags_class = FlagsClass::with_type(flags.type_()).unwrap();
@@ -146,18 +356,35 @@ pub fn tutorial_main() -> Result<(), Error> {
         .build()
         .unwrap();
     playbin.set_property_from_value("flags", &flags);
+    buffering::progressive_download_from_env(&playbin);
 
     // Add a keyboard watch so we get notified of keystrokes
+    let keymap = Keymap::new()
+        .bind_digits(|index| Action::SelectText(index as i32))
+        .bind(KeyCode::Char('+'), Action::Delay(TEXT_OFFSET_STEP))
+        .bind(KeyCode::Char('-'), Action::Delay(-TEXT_OFFSET_STEP))
+        .bind(KeyCode::Char('d'), Action::ToggleDump);
     let playbin_clone = playbin.clone();
-    let main_loop_clone = main_loop.clone();
-    thread::spawn(move || handle_keyboard(&playbin_clone, &main_loop_clone));
+    let mut dump = None;
+    let input = InputController::start(keymap, &main_loop, move |action| {
+        handle_action(&playbin_clone, &mut dump, action)
+    })?;
 
     // Add a bus watch, so we get notified when a message arrives
     let playbin_clone = playbin.clone();
     let main_loop_clone = main_loop.clone();
+    let preferences = LanguagePreferences::load();
+    let mut preferences_applied = false;
+    let mut buffering_controller = BufferingController::new(&playbin);
     let bus = playbin.bus().unwrap();
     let _bus_watch = bus.add_watch(move |_bus, message| {
         use gst::MessageView;
+
+        // Pause while the network queues refill
+        if buffering_controller.handle_message(message) {
+            return glib::ControlFlow::Continue;
+        }
+
         match message.view() {
             MessageView::Error(err) => {
                 eprintln!(
@@ -176,6 +403,16 @@ pub fn tutorial_main() -> Result<(), Error> {
                     .unwrap_or(false)
                     && state_changed.current() == gst::State::Playing
                 {
+                    if !preferences_applied {
+                        let audio_language =
+                            language_preferences::apply_audio_preferences(&playbin_clone, &preferences);
+                        language_preferences::apply_subtitle_preferences(
+                            &playbin_clone,
+                            &preferences,
+                            audio_language.as_deref(),
+                        );
+                        preferences_applied = true;
+                    }
                     analyze_streams(&playbin_clone);
                 }
                 glib::ControlFlow::Continue
@@ -195,6 +432,9 @@ pub fn tutorial_main() -> Result<(), Error> {
     // Set GLib mainloop to run
     main_loop.run();
 
+    // Restore the terminal and stop a running subtitle dump
+    drop(input);
+
     // Clean up
     playbin.set_state(gst::State::Null)?;
 
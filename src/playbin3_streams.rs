@@ -0,0 +1,225 @@
+use std::sync::{Arc, Mutex};
+
+use gstreamer as gst;
+
+use anyhow::Error;
+use glib::FlagsClass;
+use gst::prelude::*;
+use crossterm::event::KeyCode;
+
+use crate::buffering::{self, BufferingController};
+use crate::input::{InputController, Keymap};
+
+#[derive(Default)]
+struct StreamState {
+    /// Latest collection posted by playbin3
+    collection: Option<gst::StreamCollection>,
+    /// Stream IDs that will be sent with the next SelectStreams event
+    pending: Vec<String>,
+}
+
+fn stream_type_name(stream_type: gst::StreamType) -> &'static str {
+    if stream_type.contains(gst::StreamType::VIDEO) {
+        "video"
+    } else if stream_type.contains(gst::StreamType::AUDIO) {
+        "audio"
+    } else if stream_type.contains(gst::StreamType::TEXT) {
+        "text"
+    } else if stream_type.contains(gst::StreamType::CONTAINER) {
+        "container"
+    } else {
+        "unknown"
+    }
+}
+
+fn print_collection(state: &StreamState) {
+    let Some(collection) = &state.collection else {
+        println!("No stream collection yet");
+        return;
+    };
+
+    println!("Stream collection with {} stream(s):", collection.len());
+    for (i, stream) in collection.iter().enumerate() {
+        let stream_id = stream.stream_id().unwrap_or_default();
+        let selected = if state.pending.iter().any(|id| id.as_str() == stream_id.as_str()) {
+            "*"
+        } else {
+            " "
+        };
+        println!(
+            "{selected} {i}: {} stream {stream_id}",
+            stream_type_name(stream.stream_type())
+        );
+
+        if let Some(caps) = stream.caps() {
+            println!("    caps: {caps}");
+        }
+        if let Some(tags) = stream.tags() {
+            if let Some(codec) = tags.get::<gst::tags::Codec>() {
+                println!("    codec: {}", codec.get());
+            }
+            if let Some(language) = tags.get::<gst::tags::LanguageCode>() {
+                println!("    language: {}", language.get());
+            }
+            if let Some(bitrate) = tags.get::<gst::tags::Bitrate>() {
+                println!("    bitrate: {}", bitrate.get());
+            }
+        }
+    }
+    println!("Press a number to toggle a stream, ENTER to select the marked streams, l to list them");
+}
+
+fn toggle_stream(state: &mut StreamState, index: usize) {
+    let Some(stream) = state.collection.as_ref().and_then(|c| c.stream(index as u32)) else {
+        eprintln!("Index out of bounds");
+        return;
+    };
+    let Some(stream_id) = stream.stream_id() else {
+        eprintln!("Stream {index} has no stream ID");
+        return;
+    };
+
+    if let Some(pos) = state.pending.iter().position(|id| id.as_str() == stream_id.as_str()) {
+        state.pending.remove(pos);
+        println!("Unmarked {} stream {stream_id}", stream_type_name(stream.stream_type()));
+    } else {
+        state.pending.push(stream_id.to_string());
+        println!("Marked {} stream {stream_id}", stream_type_name(stream.stream_type()));
+    }
+}
+
+/// Sends the marked streams to playbin3. Leaving out every stream of a type disables it,
+/// marking several of the same type plays all of them.
+fn select_streams(playbin: &gst::Element, state: &StreamState) {
+    let stream_ids: Vec<&str> = state.pending.iter().map(String::as_str).collect();
+    println!("Selecting streams {stream_ids:?}");
+
+    if !playbin.send_event(gst::event::SelectStreams::new(&stream_ids)) {
+        eprintln!("Failed to send SelectStreams event");
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Action {
+    Toggle(usize),
+    List,
+    Select,
+}
+
+fn handle_action(playbin: &gst::Element, state: &Mutex<StreamState>, action: Action) {
+    let mut state = state.lock().unwrap();
+    match action {
+        Action::Toggle(index) => toggle_stream(&mut state, index),
+        Action::List => print_collection(&state),
+        Action::Select => select_streams(playbin, &state),
+    }
+}
+
+pub fn tutorial_main() -> Result<(), Error> {
+    // Create the main loop
+    let main_loop = glib::MainLoop::new(None, false);
+
+    // Initialize GStreamer
+    gst::init()?;
+
+    let uri = "https://gstreamer.freedesktop.org/data/media/sintel_cropped_multilingual.webm";
+
+    // Create PlayBin3 element
+    let playbin = gst::ElementFactory::make("playbin3")
+        .name("playbin")
+        // Set URI to play
+        .property("uri", uri)
+        .build()?;
+
+    // Set flags to show Audio, Video and Subtitles
+    let flags = playbin.property_value("flags");
+    let flags_class = FlagsClass::with_type(flags.type_()).unwrap();
+
+    let flags = flags_class
+        .builder_with_value(flags)
+        .unwrap()
+        .set_by_nick("audio")
+        .set_by_nick("video")
+        .set_by_nick("text")
+        .build()
+        .unwrap();
+    playbin.set_property_from_value("flags", &flags);
+    buffering::progressive_download_from_env(&playbin);
+
+    let state = Arc::new(Mutex::new(StreamState::default()));
+
+    // Handle keyboard input
+    let keymap = Keymap::new()
+        .bind_digits(|index| Action::Toggle(index as usize))
+        .bind(KeyCode::Char('l'), Action::List)
+        .bind(KeyCode::Enter, Action::Select);
+    let playbin_clone = playbin.clone();
+    let state_clone = state.clone();
+    let input = InputController::start(keymap, &main_loop, move |action| {
+        handle_action(&playbin_clone, &state_clone, action)
+    })?;
+
+    // Add a bus watch, so we get notified when a message arrives
+    let main_loop_clone = main_loop.clone();
+    let mut buffering_controller = BufferingController::new(&playbin);
+    let bus = playbin.bus().unwrap();
+    let _bus_watch = bus.add_watch(move |_bus, message| {
+        use gst::MessageView;
+
+        // Pause while the network queues refill
+        if buffering_controller.handle_message(message) {
+            return glib::ControlFlow::Continue;
+        }
+
+        match message.view() {
+            MessageView::Error(err) => {
+                eprintln!(
+                    "Error received from element {:?} {}",
+                    err.src().map(|s| s.path_string()),
+                    err.error()
+                );
+                eprintln!("Debugging information: {:?}", err.debug());
+                main_loop_clone.quit();
+                glib::ControlFlow::Break
+            }
+            MessageView::StreamCollection(collection) => {
+                // A new collection replaces the previous one, marks from the old one are stale
+                let mut state = state.lock().unwrap();
+                state.collection = Some(collection.stream_collection());
+                state.pending.clear();
+                print_collection(&state);
+                glib::ControlFlow::Continue
+            }
+            MessageView::StreamsSelected(selected) => {
+                let mut state = state.lock().unwrap();
+                state.pending = selected
+                    .streams()
+                    .into_iter()
+                    .filter_map(|stream| stream.stream_id().map(|id| id.to_string()))
+                    .collect();
+                println!("Currently selected streams: {:?}", state.pending);
+                glib::ControlFlow::Continue
+            }
+            MessageView::Eos(..) => {
+                println!("Reached end of stream");
+                main_loop_clone.quit();
+                glib::ControlFlow::Break
+            }
+            _ => glib::ControlFlow::Continue,
+        }
+    })?;
+
+    // Set to PLAYING
+    playbin.set_state(gst::State::Playing)?;
+
+    // Set GLib mainloop to run
+    main_loop.run();
+
+    // Restore the terminal
+    drop(input);
+
+    // Clean up
+    playbin.set_state(gst::State::Null)?;
+
+    Ok(())
+}
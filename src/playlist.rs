@@ -0,0 +1,691 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use gstreamer as gst;
+
+use anyhow::Error;
+use crossterm::event::KeyCode;
+use gst::prelude::*;
+
+use crate::input::{InputController, Keymap};
+
+/// Extensions picked up when a directory is used as playlist
+const MEDIA_EXTENSIONS: [&str; 10] = [
+    "wav", "ogg", "oga", "opus", "flac", "mp3", "m4a", "webm", "mkv", "mp4",
+];
+
+fn is_uri(location: &str) -> bool {
+    location.contains("://")
+}
+
+/// Turns a playlist entry into a URI, relative paths are relative to the playlist
+fn entry_uri(entry: &str, base: &Path) -> Option<String> {
+    if is_uri(entry) {
+        return Some(entry.to_string());
+    }
+    path_uri(&base.join(entry))
+}
+
+/// URI of a local file, relative paths are relative to the working directory
+fn path_uri(path: &Path) -> Option<String> {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    glib::filename_to_uri(path, None)
+        .ok()
+        .map(|uri| uri.to_string())
+}
+
+fn parse_m3u(content: &str, base: &Path) -> Vec<String> {
+    content
+        .lines()
+        .map(str::trim)
+        // #EXTM3U, #EXTINF and other directives
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| entry_uri(line, base))
+        .collect()
+}
+
+fn parse_pls(content: &str, base: &Path) -> Vec<String> {
+    let mut entries: Vec<(u32, String)> = content
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.trim().split_once('=')?;
+            let index = key.strip_prefix("File")?.parse().ok()?;
+            Some((index, entry_uri(value.trim(), base)?))
+        })
+        .collect();
+    // FileN entries may come in any order
+    entries.sort_by_key(|(index, _)| *index);
+    entries.into_iter().map(|(_, uri)| uri).collect()
+}
+
+/// URIs of an M3U/M3U8/PLS file, of the media files in a directory (sorted by name) or of a
+/// single media file or URI
+pub fn load(location: &str) -> io::Result<Vec<String>> {
+    if is_uri(location) {
+        return Ok(vec![location.to_string()]);
+    }
+
+    let path = Path::new(location);
+    if path.is_dir() {
+        let mut files: Vec<PathBuf> = fs::read_dir(path)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .and_then(|e| e.to_str())
+                    .is_some_and(|e| MEDIA_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+            })
+            .collect();
+        files.sort();
+        return Ok(files
+            .iter()
+            // read_dir already joined the directory to the names
+            .filter_map(|file| path_uri(file))
+            .collect());
+    }
+
+    let base = path.parent().unwrap_or(Path::new("."));
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_lowercase);
+    match extension.as_deref() {
+        Some("m3u") | Some("m3u8") => Ok(parse_m3u(&fs::read_to_string(path)?, base)),
+        Some("pls") => Ok(parse_pls(&fs::read_to_string(path)?, base)),
+        _ => Ok(entry_uri(location, Path::new(".")).into_iter().collect()),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repeat {
+    Off,
+    /// The current track over and over
+    One,
+    /// Start over after the last track
+    All,
+}
+
+impl Repeat {
+    pub fn next(self) -> Repeat {
+        match self {
+            Repeat::Off => Repeat::All,
+            Repeat::All => Repeat::One,
+            Repeat::One => Repeat::Off,
+        }
+    }
+}
+
+/// What happened to the playlist, from `PlaylistPlayer::handle_message`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlaylistEvent {
+    /// The track at `index` in the playlist started playing
+    TrackChanged { index: usize, uri: String },
+    /// The last track ended and nothing repeats
+    Finished,
+}
+
+/// Order the tracks are played in, reshuffled every time it starts over
+struct Queue {
+    entries: Vec<String>,
+    /// Indexes into `entries`, in playing order
+    order: Vec<usize>,
+    /// Position in `order` of the track playing now
+    position: usize,
+    /// Position queued from `about-to-finish` that has not started yet
+    queued: Option<usize>,
+    shuffle: bool,
+    repeat: Repeat,
+    seed: u64,
+}
+
+impl Queue {
+    fn reorder(&mut self) {
+        self.order = (0..self.entries.len()).collect();
+        if !self.shuffle {
+            return;
+        }
+        // Fisher-Yates with xorshift, good enough to mix up a playlist
+        for i in (1..self.order.len()).rev() {
+            self.seed ^= self.seed << 13;
+            self.seed ^= self.seed >> 7;
+            self.seed ^= self.seed << 17;
+            self.order.swap(i, (self.seed % (i as u64 + 1)) as usize);
+        }
+    }
+
+    /// Position of the track after the current one, `skip` ignores repeating a single track
+    fn next(&mut self, skip: bool) -> Option<usize> {
+        if self.repeat == Repeat::One && !skip {
+            return Some(self.position);
+        }
+        if self.position + 1 < self.order.len() {
+            return Some(self.position + 1);
+        }
+        if self.repeat == Repeat::Off {
+            return None;
+        }
+        let current = self.order[self.position];
+        self.reorder();
+        // Don't play the same track twice in a row when the shuffled order starts over
+        if self.order.len() > 1 && self.order[0] == current {
+            self.order.swap(0, 1);
+        }
+        self.position = 0;
+        Some(0)
+    }
+
+    fn set_shuffle(&mut self, shuffle: bool) {
+        if self.shuffle == shuffle {
+            return;
+        }
+        self.shuffle = shuffle;
+        // Keep the current track: shuffled it goes first, in order it stays where it is
+        let current = self.order[self.position];
+        let queued = self.queued.map(|position| self.order[position]);
+        self.reorder();
+        if shuffle {
+            let position = self.order.iter().position(|&i| i == current).unwrap();
+            self.order.swap(0, position);
+            self.position = 0;
+        } else {
+            self.position = current;
+        }
+        // playbin already has the queued track, it has to start at its new position
+        self.queued = queued.map(|index| self.order.iter().position(|&i| i == index).unwrap());
+    }
+
+    fn uri(&self, position: usize) -> &str {
+        &self.entries[self.order[position]]
+    }
+}
+
+/// Plays a list of URIs through one playbin without gaps: the next URI is set from the
+/// `about-to-finish` signal, so playbin prepares it while the current one is still playing.
+#[derive(Clone)]
+pub struct PlaylistPlayer {
+    playbin: gst::Element,
+    queue: Arc<Mutex<Queue>>,
+}
+
+impl PlaylistPlayer {
+    pub fn new(entries: Vec<String>) -> Result<PlaylistPlayer, Error> {
+        if entries.is_empty() {
+            return Err(glib::bool_error!("The playlist is empty").into());
+        }
+
+        let playbin = gst::ElementFactory::make("playbin").build()?;
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(1, |d| d.as_nanos() as u64 | 1);
+        let mut queue = Queue {
+            entries,
+            order: Vec::new(),
+            position: 0,
+            queued: None,
+            shuffle: false,
+            repeat: Repeat::Off,
+            seed,
+        };
+        queue.reorder();
+        playbin.set_property("uri", queue.uri(0));
+
+        let queue = Arc::new(Mutex::new(queue));
+        let queue_clone = queue.clone();
+        // Emitted from a streaming thread, setting the uri here is allowed
+        playbin.connect("about-to-finish", false, move |values| {
+            let playbin = values[0].get::<gst::Element>().unwrap();
+            let mut queue = queue_clone.lock().unwrap();
+            if let Some(next) = queue.next(false) {
+                playbin.set_property("uri", queue.uri(next));
+                queue.queued = Some(next);
+            }
+            None
+        });
+
+        Ok(PlaylistPlayer { playbin, queue })
+    }
+
+    pub fn playbin(&self) -> &gst::Element {
+        &self.playbin
+    }
+
+    pub fn set_shuffle(&self, shuffle: bool) {
+        self.queue.lock().unwrap().set_shuffle(shuffle);
+    }
+
+    pub fn shuffle(&self) -> bool {
+        self.queue.lock().unwrap().shuffle
+    }
+
+    pub fn set_repeat(&self, repeat: Repeat) {
+        self.queue.lock().unwrap().repeat = repeat;
+    }
+
+    pub fn repeat(&self) -> Repeat {
+        self.queue.lock().unwrap().repeat
+    }
+
+    /// Jumps to the next track right away. Returns false at the end of the playlist.
+    pub fn skip(&self) -> Result<bool, Error> {
+        let uri = {
+            let mut queue = self.queue.lock().unwrap();
+            let Some(next) = queue.next(true) else {
+                return Ok(false);
+            };
+            queue.queued = Some(next);
+            queue.uri(next).to_string()
+        };
+
+        // Changing the uri of a running playbin needs a restart
+        self.playbin.set_state(gst::State::Ready)?;
+        self.playbin.set_property("uri", uri);
+        self.playbin.set_state(gst::State::Playing)?;
+        Ok(true)
+    }
+
+    /// Turns `StreamStart` and `Eos` messages into playlist events
+    pub fn handle_message(&self, message: &gst::Message) -> Option<PlaylistEvent> {
+        use gst::MessageView;
+
+        match message.view() {
+            // Posted when the first track and every queued track start
+            MessageView::StreamStart(..) => {
+                let mut queue = self.queue.lock().unwrap();
+                if let Some(queued) = queue.queued.take() {
+                    queue.position = queued;
+                }
+                let index = queue.order[queue.position];
+                Some(PlaylistEvent::TrackChanged {
+                    index,
+                    uri: queue.entries[index].clone(),
+                })
+            }
+            MessageView::Eos(..) => Some(PlaylistEvent::Finished),
+            _ => None,
+        }
+    }
+}
+
+/// Renders `count` short sine tones of different pitch as WAV files into `dir`, to try the
+/// player without any media around
+pub fn generate_test_tracks(dir: &Path, count: usize) -> Result<Vec<PathBuf>, Error> {
+    fs::create_dir_all(dir)?;
+    let mut tracks = Vec::new();
+
+    for i in 0..count {
+        let path = dir.join(format!("track{:02}.wav", i + 1));
+        // 2 seconds at 44100 Hz in buffers of 1024 samples
+        let pipeline = gst::parse_launch(&format!(
+            "audiotestsrc freq={} num-buffers=87 samplesperbuffer=1024 \
+             ! audio/x-raw,rate=44100,channels=2 ! wavenc ! filesink location=\"{}\"",
+            220.0 * 2f64.powf(i as f64 / 4.0),
+            path.display()
+        ))?;
+        pipeline.set_state(gst::State::Playing)?;
+        let bus = pipeline.bus().unwrap();
+        let msg = bus.timed_pop_filtered(
+            gst::ClockTime::NONE,
+            &[gst::MessageType::Eos, gst::MessageType::Error],
+        );
+        pipeline.set_state(gst::State::Null)?;
+        if let Some(gst::MessageView::Error(err)) = msg.as_ref().map(|msg| msg.view()) {
+            return Err(err.error().into());
+        }
+        tracks.push(path);
+    }
+
+    Ok(tracks)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Action {
+    Skip,
+    ToggleShuffle,
+    CycleRepeat,
+}
+
+/// Plays the playlist, directory or file given as first argument, or a few generated tones.
+/// `--shuffle` and `--repeat=one|all` set the initial modes.
+pub fn tutorial_main() -> Result<(), Error> {
+    // Initialize GStreamer
+    gst::init()?;
+
+    let mut location = None;
+    let mut shuffle = false;
+    let mut repeat = Repeat::Off;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--shuffle" => shuffle = true,
+            "--repeat=one" => repeat = Repeat::One,
+            "--repeat=all" => repeat = Repeat::All,
+            _ => location = Some(arg),
+        }
+    }
+
+    let entries = match location {
+        Some(location) => load(&location)?,
+        None => {
+            let dir = env::temp_dir().join("gstream_prac_playlist");
+            println!(
+                "No playlist given, playing tones generated in {}",
+                dir.display()
+            );
+            generate_test_tracks(&dir, 4)?;
+            load(&dir.to_string_lossy())?
+        }
+    };
+    println!("{} tracks", entries.len());
+
+    let player = PlaylistPlayer::new(entries)?;
+    player.set_shuffle(shuffle);
+    player.set_repeat(repeat);
+
+    let main_loop = glib::MainLoop::new(None, false);
+
+    // Handle keyboard input
+    let keymap = Keymap::new()
+        .bind(KeyCode::Char('n'), Action::Skip)
+        .bind(KeyCode::Char('s'), Action::ToggleShuffle)
+        .bind(KeyCode::Char('r'), Action::CycleRepeat);
+    let player_clone = player.clone();
+    let main_loop_clone = main_loop.clone();
+    let _input = InputController::start(keymap, &main_loop, move |action| match action {
+        Action::Skip => match player_clone.skip() {
+            Ok(true) => (),
+            Ok(false) => main_loop_clone.quit(),
+            Err(err) => eprintln!("Failed to skip: {err}"),
+        },
+        Action::ToggleShuffle => {
+            player_clone.set_shuffle(!player_clone.shuffle());
+            println!("Shuffle {}", player_clone.shuffle());
+        }
+        Action::CycleRepeat => {
+            player_clone.set_repeat(player_clone.repeat().next());
+            println!("Repeat {:?}", player_clone.repeat());
+        }
+    })?;
+    println!("Press n for the next track, s to toggle shuffle, r to change repeat");
+
+    let player_clone = player.clone();
+    let main_loop_clone = main_loop.clone();
+    let bus = player.playbin().bus().unwrap();
+    let _bus_watch = bus.add_watch(move |_bus, message| {
+        match player_clone.handle_message(message) {
+            Some(PlaylistEvent::TrackChanged { index, uri }) => {
+                println!("Now playing track {}: {uri}", index + 1);
+            }
+            Some(PlaylistEvent::Finished) => {
+                println!("End of playlist");
+                main_loop_clone.quit();
+            }
+            None => {
+                if let gst::MessageView::Error(err) = message.view() {
+                    eprintln!(
+                        "Error received from element {:?}: {}",
+                        err.src().map(|s| s.path_string()),
+                        err.error()
+                    );
+                    eprintln!("Debugging information: {:?}", err.debug());
+                    main_loop_clone.quit();
+                }
+            }
+        }
+        glib::ControlFlow::Continue
+    })?;
+
+    player.playbin().set_state(gst::State::Playing)?;
+
+    main_loop.run();
+
+    player.playbin().set_state(gst::State::Null)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("gstream_prac_playlist_{}_{name}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn queue(len: usize, shuffle: bool, repeat: Repeat) -> Queue {
+        let mut queue = Queue {
+            entries: (0..len).map(|i| format!("file:///track{i}.wav")).collect(),
+            order: Vec::new(),
+            position: 0,
+            queued: None,
+            shuffle,
+            repeat,
+            seed: 0x2545_f491_4f6c_dd1d,
+        };
+        queue.reorder();
+        queue
+    }
+
+    fn is_permutation(order: &[usize]) -> bool {
+        let mut sorted = order.to_vec();
+        sorted.sort();
+        sorted.into_iter().eq(0..order.len())
+    }
+
+    #[test]
+    fn m3u() {
+        let content = [
+            "#EXTM3U",
+            "#EXTINF:2,First",
+            "first.wav",
+            "",
+            "  sub/second.ogg ",
+            "/music/third.flac",
+            "http://example.com/fourth.mp3",
+        ]
+        .join("\n");
+        assert_eq!(
+            parse_m3u(&content, Path::new("/playlists")),
+            [
+                "file:///playlists/first.wav",
+                "file:///playlists/sub/second.ogg",
+                "file:///music/third.flac",
+                "http://example.com/fourth.mp3",
+            ]
+        );
+    }
+
+    #[test]
+    fn pls_out_of_order() {
+        let content = [
+            "[playlist]",
+            "File2=second.wav",
+            "Title2=Second",
+            "File10=http://example.com/tenth.ogg",
+            "File1=/music/first.wav",
+            "NumberOfEntries=3",
+            "Version=2",
+        ]
+        .join("\n");
+        assert_eq!(
+            parse_pls(&content, Path::new("/playlists")),
+            [
+                "file:///music/first.wav",
+                "file:///playlists/second.wav",
+                "http://example.com/tenth.ogg",
+            ]
+        );
+    }
+
+    #[test]
+    fn load_directory() {
+        let dir = temp_dir("directory");
+        for name in ["b.WAV", "a.ogg", "notes.txt", "c.flac"] {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+        fs::create_dir(dir.join("sub.mp3")).unwrap();
+
+        // Sorted by name, only media files, extensions in any case
+        let expected: Vec<String> = ["a.ogg", "b.WAV", "c.flac"]
+            .iter()
+            .map(|name| path_uri(&dir.join(name)).unwrap())
+            .collect();
+        assert_eq!(load(&dir.to_string_lossy()).unwrap(), expected);
+    }
+
+    #[test]
+    fn load_m3u_relative_to_playlist() {
+        let dir = temp_dir("m3u");
+        fs::create_dir(dir.join("lists")).unwrap();
+        fs::write(dir.join("lists/one.wav"), b"").unwrap();
+        fs::write(dir.join("two.wav"), b"").unwrap();
+        fs::write(dir.join("lists/list.m3u"), "one.wav\n../two.wav\n").unwrap();
+
+        assert_eq!(
+            load(&dir.join("lists/list.m3u").to_string_lossy()).unwrap(),
+            [
+                path_uri(&dir.join("lists/one.wav")).unwrap(),
+                path_uri(&dir.join("two.wav")).unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn next_repeat_off() {
+        let mut queue = queue(3, false, Repeat::Off);
+        assert_eq!(queue.next(false), Some(1));
+        queue.position = 1;
+        assert_eq!(queue.next(false), Some(2));
+        queue.position = 2;
+        assert_eq!(queue.next(false), None);
+        assert_eq!(queue.next(true), None);
+    }
+
+    #[test]
+    fn next_repeat_one() {
+        let mut queue = queue(3, false, Repeat::One);
+        queue.position = 2;
+        assert_eq!(queue.next(false), Some(2));
+        // Skipping leaves the track, and starts over from the last one
+        queue.position = 1;
+        assert_eq!(queue.next(true), Some(2));
+        queue.position = 2;
+        assert_eq!(queue.next(true), Some(0));
+        assert_eq!(queue.order, [0, 1, 2]);
+    }
+
+    #[test]
+    fn next_repeat_all() {
+        let mut queue = queue(3, false, Repeat::All);
+        queue.position = 2;
+        assert_eq!(queue.next(false), Some(0));
+        assert_eq!(queue.position, 0);
+        assert_eq!(queue.order, [0, 1, 2]);
+    }
+
+    #[test]
+    fn next_shuffled() {
+        let mut queue = queue(8, true, Repeat::All);
+        assert!(is_permutation(&queue.order));
+
+        for _ in 0..20 {
+            queue.position = queue.order.len() - 1;
+            let last = queue.order[queue.position];
+            assert_eq!(queue.next(false), Some(0));
+            // Reshuffled, without playing the last track again right away
+            assert!(is_permutation(&queue.order));
+            assert_ne!(queue.order[0], last);
+        }
+    }
+
+    #[test]
+    fn shuffle_keeps_current_and_queued() {
+        let mut queue = queue(8, false, Repeat::Off);
+        queue.position = 3;
+        queue.queued = Some(4);
+
+        queue.set_shuffle(true);
+        assert!(is_permutation(&queue.order));
+        assert_eq!(queue.position, 0);
+        assert_eq!(queue.order[0], 3);
+        assert_eq!(queue.queued.map(|position| queue.order[position]), Some(4));
+
+        queue.set_shuffle(false);
+        assert_eq!(queue.order, (0..8).collect::<Vec<_>>());
+        assert_eq!(queue.position, 3);
+        assert_eq!(queue.queued, Some(4));
+    }
+
+    /// Plays `player` as fast as possible, returns its events up to the end
+    fn play(player: &PlaylistPlayer) -> Vec<PlaylistEvent> {
+        let sink = gst::ElementFactory::make("fakesink")
+            .property("sync", false)
+            .build()
+            .unwrap();
+        player.playbin().set_property("audio-sink", &sink);
+        player.playbin().set_state(gst::State::Playing).unwrap();
+
+        let bus = player.playbin().bus().unwrap();
+        let mut events = Vec::new();
+        while let Some(message) = bus.timed_pop(gst::ClockTime::from_seconds(10)) {
+            if let gst::MessageView::Error(err) = message.view() {
+                panic!("{}", err.error());
+            }
+            if let Some(event) = player.handle_message(&message) {
+                let finished = event == PlaylistEvent::Finished;
+                events.push(event);
+                if finished {
+                    break;
+                }
+            }
+        }
+        player.playbin().set_state(gst::State::Null).unwrap();
+        events
+    }
+
+    #[test]
+    fn track_changed_events() {
+        gst::init().unwrap();
+        let dir = temp_dir("tracks");
+        generate_test_tracks(&dir, 3).unwrap();
+        let entries = load(&dir.to_string_lossy()).unwrap();
+
+        let player = PlaylistPlayer::new(entries.clone()).unwrap();
+        let mut expected: Vec<PlaylistEvent> = entries
+            .iter()
+            .enumerate()
+            .map(|(index, uri)| PlaylistEvent::TrackChanged {
+                index,
+                uri: uri.clone(),
+            })
+            .collect();
+        expected.push(PlaylistEvent::Finished);
+        assert_eq!(play(&player), expected);
+
+        // Shuffled, every track still plays exactly once
+        let player = PlaylistPlayer::new(entries).unwrap();
+        player.set_shuffle(true);
+        let events = play(&player);
+        let order: Vec<usize> = events
+            .iter()
+            .filter_map(|event| match event {
+                PlaylistEvent::TrackChanged { index, .. } => Some(*index),
+                PlaylistEvent::Finished => None,
+            })
+            .collect();
+        assert!(is_permutation(&order));
+        assert_eq!(events.last(), Some(&PlaylistEvent::Finished));
+    }
+
+    #[test]
+    fn empty_playlist() {
+        gst::init().unwrap();
+        assert!(PlaylistPlayer::new(Vec::new()).is_err());
+    }
+}
@@ -1,4 +1,332 @@
+extern crate gstreamer as gst;
+
+use gst::prelude::*;
+
+mod canvas;
+mod colorfilter;
+mod gain;
+pub mod overlay;
+mod psychedelicsrc;
+mod scope;
+
+fn plugin_init(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gain::register(plugin)?;
+    colorfilter::register(plugin)?;
+    psychedelicsrc::register(plugin)?;
+    scope::register(plugin)?;
+    overlay::register(plugin)?;
+    Ok(())
+}
+
+gst::plugin_define!(
+    gstreamprac,
+    "Elements written while practising GStreamer in Rust",
+    plugin_init,
+    env!("CARGO_PKG_VERSION"),
+    "unknown",
+    env!("CARGO_PKG_NAME"),
+    env!("CARGO_PKG_NAME"),
+    "https://github.com/sglee487/gstream_prac"
+);
+
+/// Registers the elements of this crate (`rsgain`, `rscolorfilter`, `psychedelicsrc`, `rsscope`,
+/// `rsoverlay`) so they can be made by name, e.g. in `gst::parse_launch`. Call after `gst::init()`.
+pub fn register() -> Result<(), glib::BoolError> {
+    plugin_register_static()
+}
 
 pub fn main() {
+    // Initialize GStreamer
+    gst::init().unwrap();
+    register().expect("Failed to register the gstreamprac plugin");
+
+    // Use the elements in a normal pipeline
+    let pipeline = gst::parse_launch(
+        "videotestsrc num-buffers=300 ! videoconvert ! rscolorfilter mode=invert ! rsoverlay text=gstreamprac ! videoconvert ! autovideosink \
+         psychedelicsrc num-buffers=500 is-live=true ! audioconvert ! rsgain gain=0.8 fade-in=3000000000 ! audioconvert ! autoaudiosink",
+    )
+    .expect("Failed to build pipeline")
+    .downcast::<gst::Pipeline>()
+    .unwrap();
+
+    pipeline
+        .set_state(gst::State::Playing)
+        .expect("Unable to set the pipeline to the `Playing` state");
+
+    // Wait until error or EOS
+    let bus = pipeline.bus().unwrap();
+    for msg in bus.iter_timed(gst::ClockTime::NONE) {
+        use gst::MessageView;
+
+        match msg.view() {
+            MessageView::Error(err) => {
+                eprintln!(
+                    "Error received from element {:?}: {}",
+                    err.src().map(|s| s.path_string()),
+                    err.error()
+                );
+                eprintln!("Debugging information: {:?}", err.debug());
+                break;
+            }
+            MessageView::Eos(..) => break,
+            _ => (),
+        }
+    }
+
+    pipeline
+        .set_state(gst::State::Null)
+        .expect("Unable to set the pipeline to the `Null` state");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Once;
+
+    use gstreamer_audio as gst_audio;
+    use gstreamer_check as gst_check;
+    use gstreamer_video as gst_video;
+
+    use byte_slice_cast::*;
+
+    use super::*;
+
+    fn init() {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            gst::init().unwrap();
+            register().unwrap();
+        });
+    }
+
+    fn s16_buffer(samples: &[i16]) -> gst::Buffer {
+        gst::Buffer::from_mut_slice(
+            samples
+                .iter()
+                .flat_map(|s| s.to_ne_bytes())
+                .collect::<Vec<u8>>(),
+        )
+    }
+
+    fn f32_buffer(samples: &[f32]) -> gst::Buffer {
+        gst::Buffer::from_mut_slice(
+            samples
+                .iter()
+                .flat_map(|s| s.to_ne_bytes())
+                .collect::<Vec<u8>>(),
+        )
+    }
+
+    #[test]
+    fn gain_halves_s16_samples() {
+        init();
+        let mut h = gst_check::Harness::new("rsgain");
+        h.element().unwrap().set_property("gain", 0.5f64);
+        let caps = gst_audio::AudioInfo::builder(gst_audio::AUDIO_FORMAT_S16, 44_100, 1)
+            .build()
+            .unwrap()
+            .to_caps()
+            .unwrap();
+        h.set_src_caps(caps);
+
+        h.push(s16_buffer(&[1000, -2000, i16::MAX, 0])).unwrap();
+        let buffer = h.pull().unwrap();
+        let map = buffer.map_readable().unwrap();
+        let samples = map.as_slice_of::<i16>().unwrap();
+
+        assert_eq!(samples, [500, -1000, i16::MAX / 2, 0]);
+    }
+
+    #[test]
+    fn gain_fades_in() {
+        init();
+        let mut h = gst_check::Harness::new("rsgain");
+        // 10 ms at 1 kHz is a 10 frame fade
+        h.element().unwrap().set_property("fade-in", 10_000_000u64);
+        let caps = gst_audio::AudioInfo::builder(gst_audio::AUDIO_FORMAT_F32, 1000, 2)
+            .build()
+            .unwrap()
+            .to_caps()
+            .unwrap();
+        h.set_src_caps(caps);
+
+        h.push(f32_buffer(&[1.0; 2 * 20])).unwrap();
+        let buffer = h.pull().unwrap();
+        let map = buffer.map_readable().unwrap();
+        let samples = map.as_slice_of::<f32>().unwrap();
+
+        assert_eq!(samples[..2], [0.0, 0.0]);
+        assert!((samples[2 * 5] - 0.5).abs() < 1e-6);
+        assert!(samples[2 * 10..].iter().all(|s| *s == 1.0));
+    }
+
+    /// Fills a 2x2 RGBx frame with `input` and checks every output pixel is `expected`
+    fn check_color_filter(mode: &str, input: [u8; 4], expected: [u8; 4]) {
+        init();
+        let mut h = gst_check::Harness::new("rscolorfilter");
+        h.element().unwrap().set_property_from_str("mode", mode);
+        let info = gst_video::VideoInfo::builder(gst_video::VideoFormat::Rgbx, 2, 2)
+            .build()
+            .unwrap();
+        h.set_src_caps(info.to_caps().unwrap());
+
+        let data: Vec<u8> = input.iter().copied().cycle().take(info.size()).collect();
+        h.push(gst::Buffer::from_mut_slice(data)).unwrap();
+        let buffer = h.pull().unwrap();
+        let map = buffer.map_readable().unwrap();
+
+        // The x byte is left alone
+        for pixel in map.chunks_exact(4) {
+            assert_eq!(pixel, expected);
+        }
+    }
+
+    #[test]
+    fn color_filter_grayscale() {
+        check_color_filter("grayscale", [255, 0, 0, 7], [76, 76, 76, 7]);
+    }
+
+    #[test]
+    fn color_filter_invert() {
+        check_color_filter("invert", [10, 20, 30, 7], [245, 235, 225, 7]);
+    }
+
+    #[test]
+    fn psychedelicsrc_timestamps_channels_and_num_buffers() {
+        init();
+        let mut h = gst_check::Harness::new("psychedelicsrc");
+        let src = h.element().unwrap();
+        src.set_property("samples-per-buffer", 512u32);
+        src.set_property("channels", 2u32);
+        src.set_property("num-buffers", 2i32);
+        h.play();
+
+        let first = h.pull().unwrap();
+        let second = h.pull().unwrap();
+        assert!(h.pull_until_eos().unwrap().is_none());
+
+        let duration = gst::ClockTime::SECOND.mul_div_floor(512, 44_100).unwrap();
+        assert_eq!(first.pts(), Some(gst::ClockTime::ZERO));
+        assert_eq!(first.duration(), Some(duration));
+        assert_eq!(second.pts(), Some(duration));
+
+        let map = first.map_readable().unwrap();
+        let samples = map.as_slice_of::<i16>().unwrap();
+        assert_eq!(samples.len(), 2 * 512);
+        assert!(samples.chunks_exact(2).all(|frame| frame[0] == frame[1]));
+    }
+
+    #[test]
+    fn psychedelicsrc_seek() {
+        init();
+        let mut h = gst_check::Harness::new("psychedelicsrc");
+        let src = h.element().unwrap();
+        src.set_property("samples-per-buffer", 4410u32);
+        // Sent before starting, the source applies it when it starts
+        let seek = gst::event::Seek::new(
+            1.0,
+            gst::SeekFlags::FLUSH,
+            gst::SeekType::Set,
+            gst::ClockTime::SECOND,
+            gst::SeekType::Set,
+            gst::ClockTime::from_mseconds(1500),
+        );
+        assert!(src.send_event(seek));
+        h.play();
+
+        let first = h.pull().unwrap();
+        assert_eq!(first.pts(), Some(gst::ClockTime::SECOND));
+        assert_eq!(first.offset(), 44_100);
+
+        // Half a second of frames until the stop of the seek
+        let mut frames = first.offset_end() - first.offset();
+        while let Some(buffer) = h.pull_until_eos().unwrap() {
+            assert_eq!(buffer.offset(), 44_100 + frames);
+            frames += buffer.offset_end() - buffer.offset();
+        }
+        assert_eq!(frames, 22_050);
+    }
+
+    /// Pushes two and a half video frames of a sine into rsscope in `mode`
+    fn check_scope(mode: &str) {
+        init();
+        let mut h = gst_check::Harness::new("rsscope");
+        let scope = h.element().unwrap();
+        scope.set_property_from_str("mode", mode);
+        scope.set_property("width", 32u32);
+        scope.set_property("height", 16u32);
+        // 30 fps at 3 kHz is 100 frames per video frame
+        let caps = gst_audio::AudioInfo::builder(gst_audio::AUDIO_FORMAT_F32, 3000, 2)
+            .build()
+            .unwrap()
+            .to_caps()
+            .unwrap();
+        h.set_src_caps(caps);
+
+        let samples: Vec<f32> = (0..250)
+            .flat_map(|i| {
+                let phase = i as f32 * 2.0 * std::f32::consts::PI / 25.0;
+                [phase.sin() * 0.8, phase.cos() * 0.8]
+            })
+            .collect();
+        h.push(f32_buffer(&samples)).unwrap();
+
+        let first = h.pull().unwrap();
+        let second = h.pull().unwrap();
+        assert_eq!(first.pts(), Some(gst::ClockTime::ZERO));
+        assert_eq!(second.pts(), Some(gst::ClockTime::SECOND / 30));
+        // Half a video frame is still pending
+        assert!(h.try_pull().is_none());
+
+        // The trace left some green on the black background
+        let map = second.map_readable().unwrap();
+        assert_eq!(map.len(), 32 * 16 * 4);
+        assert!(map.chunks_exact(4).any(|pixel| pixel[1] > 0));
+    }
+
+    #[test]
+    fn scope_waveform() {
+        check_scope("waveform");
+    }
+
+    #[test]
+    fn scope_lissajous() {
+        check_scope("lissajous");
+    }
+
+    #[test]
+    fn scope_spectrogram() {
+        check_scope("spectrogram");
+    }
+
+    #[test]
+    fn overlay_draws_text_and_callback_shapes() {
+        init();
+        let mut h = gst_check::Harness::new("rsoverlay");
+        let overlay = h.element().unwrap().downcast::<overlay::Overlay>().unwrap();
+        overlay.set_property("show-timecode", false);
+        overlay.set_property("show-position", false);
+        overlay.set_property("text", "Hi");
+        // Right half red
+        overlay.set_draw_callback(|frame| {
+            let rect = epaint::Rect::from_min_max(
+                epaint::pos2(frame.width / 2.0, 0.0),
+                epaint::pos2(frame.width, frame.height),
+            );
+            vec![epaint::Shape::rect_filled(rect, 0.0, epaint::Color32::RED)]
+        });
+        let info = gst_video::VideoInfo::builder(gst_video::VideoFormat::Rgbx, 64, 32)
+            .build()
+            .unwrap();
+        h.set_src_caps(info.to_caps().unwrap());
+
+        h.push(gst::Buffer::from_mut_slice(vec![0u8; info.size()])).unwrap();
+        let buffer = h.pull().unwrap();
+        let map = buffer.map_readable().unwrap();
+        let pixel = |x: usize, y: usize| &map[y * 64 * 4 + x * 4..][..4];
 
-}
\ No newline at end of file
+        // The callback fills the right half, the x byte is left alone
+        assert_eq!(pixel(48, 16), [255, 0, 0, 0]);
+        // The text leaves something on the left half
+        assert!((0..32).any(|y| (0..32).any(|x| pixel(x, y)[..3] != [0, 0, 0])));
+    }
+}
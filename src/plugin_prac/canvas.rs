@@ -0,0 +1,179 @@
+use gstreamer_video as gst_video;
+
+use epaint::{Color32, FontImage, Mesh, Pos2, Shape, TessellationOptions, Tessellator, Vertex};
+
+/// Packed 4 byte formats a `Canvas` can draw on, with the byte offsets of R, G and B
+pub const FORMATS: [(gst_video::VideoFormat, [usize; 3]); 8] = [
+    (gst_video::VideoFormat::Rgbx, [0, 1, 2]),
+    (gst_video::VideoFormat::Rgba, [0, 1, 2]),
+    (gst_video::VideoFormat::Bgrx, [2, 1, 0]),
+    (gst_video::VideoFormat::Bgra, [2, 1, 0]),
+    (gst_video::VideoFormat::Xrgb, [1, 2, 3]),
+    (gst_video::VideoFormat::Argb, [1, 2, 3]),
+    (gst_video::VideoFormat::Xbgr, [3, 2, 1]),
+    (gst_video::VideoFormat::Abgr, [3, 2, 1]),
+];
+
+/// Software target for epaint shapes: a packed 4 byte per pixel RGB plane.
+///
+/// epaint only tessellates shapes into triangle meshes, so this rasterises the triangles
+/// itself and blends the premultiplied vertex colours into the frame. The fourth byte of a
+/// pixel (x or alpha) is never touched.
+pub struct Canvas<'a> {
+    data: &'a mut [u8],
+    width: usize,
+    height: usize,
+    stride: usize,
+    /// Byte offsets of R, G and B in a pixel
+    rgb: [usize; 3],
+}
+
+impl<'a> Canvas<'a> {
+    pub fn new(
+        data: &'a mut [u8],
+        width: usize,
+        height: usize,
+        stride: usize,
+        rgb: [usize; 3],
+    ) -> Self {
+        Canvas {
+            data,
+            width,
+            height,
+            stride,
+            rgb,
+        }
+    }
+
+    pub fn fill(&mut self, color: Color32) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                self.set_pixel(x, y, color);
+            }
+        }
+    }
+
+    /// Overwrites a pixel, ignoring the alpha of `color`
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Color32) {
+        let offset = y * self.stride + x * 4;
+        let [r, g, b] = self.rgb;
+        self.data[offset + r] = color.r();
+        self.data[offset + g] = color.g();
+        self.data[offset + b] = color.b();
+    }
+
+    /// Blends a premultiplied colour over a pixel, `coverage` scales it further (0..=1)
+    fn blend_pixel(&mut self, x: usize, y: usize, color: [f32; 4], coverage: f32) {
+        let offset = y * self.stride + x * 4;
+        let keep = 1.0 - color[3] * coverage;
+        for (channel, value) in self.rgb.into_iter().zip(color) {
+            let dst = &mut self.data[offset + channel];
+            *dst = (value * coverage + f32::from(*dst) * keep)
+                .round()
+                .clamp(0.0, 255.0) as u8;
+        }
+    }
+
+    /// Tessellates and draws `shapes` in order. Text shapes need the `fonts` they were laid
+    /// out with, everything else samples the white texel at `epaint::WHITE_UV`.
+    pub fn draw_shapes(
+        &mut self,
+        shapes: impl IntoIterator<Item = Shape>,
+        fonts: Option<&epaint::text::Fonts>,
+    ) {
+        let image = fonts.map(|fonts| fonts.image());
+        let tex_size = image.as_ref().map_or([1, 1], |image| image.size);
+        let mut tessellator =
+            Tessellator::new(1.0, TessellationOptions::default(), tex_size, vec![]);
+
+        for shape in shapes {
+            let mut mesh = Mesh::default();
+            tessellator.tessellate_shape(shape, &mut mesh);
+            self.draw_mesh(&mesh, image.as_ref());
+        }
+    }
+
+    pub fn draw_mesh(&mut self, mesh: &Mesh, texture: Option<&FontImage>) {
+        for triangle in mesh.indices.chunks_exact(3) {
+            let v = [
+                &mesh.vertices[triangle[0] as usize],
+                &mesh.vertices[triangle[1] as usize],
+                &mesh.vertices[triangle[2] as usize],
+            ];
+            self.draw_triangle(v, texture);
+        }
+    }
+
+    fn draw_triangle(&mut self, v: [&Vertex; 3], texture: Option<&FontImage>) {
+        let area = edge(v[0].pos, v[1].pos, v[2].pos);
+        if area.abs() < f32::EPSILON {
+            return;
+        }
+
+        let min_x = v
+            .iter()
+            .map(|v| v.pos.x)
+            .fold(f32::INFINITY, f32::min)
+            .floor()
+            .max(0.0);
+        let min_y = v
+            .iter()
+            .map(|v| v.pos.y)
+            .fold(f32::INFINITY, f32::min)
+            .floor()
+            .max(0.0);
+        let max_x = v
+            .iter()
+            .map(|v| v.pos.x)
+            .fold(f32::NEG_INFINITY, f32::max)
+            .ceil();
+        let max_y = v
+            .iter()
+            .map(|v| v.pos.y)
+            .fold(f32::NEG_INFINITY, f32::max)
+            .ceil();
+        let max_x = max_x.min(self.width as f32) as usize;
+        let max_y = max_y.min(self.height as f32) as usize;
+
+        let colors = v.map(|v| v.color.to_array().map(f32::from));
+
+        for y in min_y as usize..max_y {
+            for x in min_x as usize..max_x {
+                // Sample at the pixel centre
+                let p = Pos2::new(x as f32 + 0.5, y as f32 + 0.5);
+                let w0 = edge(v[1].pos, v[2].pos, p) / area;
+                let w1 = edge(v[2].pos, v[0].pos, p) / area;
+                let w2 = 1.0 - w0 - w1;
+                if w0 < 0.0 || w1 < 0.0 || w2 < 0.0 {
+                    continue;
+                }
+
+                let mut color = [0.0; 4];
+                for (i, c) in color.iter_mut().enumerate() {
+                    *c = w0 * colors[0][i] + w1 * colors[1][i] + w2 * colors[2][i];
+                }
+                let coverage = texture.map_or(1.0, |texture| {
+                    let tu = w0 * v[0].uv.x + w1 * v[1].uv.x + w2 * v[2].uv.x;
+                    let tv = w0 * v[0].uv.y + w1 * v[1].uv.y + w2 * v[2].uv.y;
+                    sample(texture, tu, tv)
+                });
+                if coverage > 0.0 {
+                    self.blend_pixel(x, y, color, coverage);
+                }
+            }
+        }
+    }
+}
+
+/// Twice the signed area of the triangle `a`, `b`, `p`
+fn edge(a: Pos2, b: Pos2, p: Pos2) -> f32 {
+    (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x)
+}
+
+/// Nearest texel coverage at normalized `u`, `v`
+fn sample(texture: &FontImage, u: f32, v: f32) -> f32 {
+    let [width, height] = texture.size;
+    let x = ((u * width as f32) as usize).min(width - 1);
+    let y = ((v * height as f32) as usize).min(height - 1);
+    texture.pixels[y * width + x]
+}
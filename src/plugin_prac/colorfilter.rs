@@ -0,0 +1,229 @@
+use gstreamer as gst;
+use gstreamer_base as gst_base;
+
+use gst::prelude::*;
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRsColorFilterMode")]
+pub enum ColorFilterMode {
+    #[enum_value(name = "Grayscale", nick = "grayscale")]
+    Grayscale = 0,
+    #[enum_value(name = "Invert colours", nick = "invert")]
+    Invert = 1,
+}
+
+mod imp {
+    use std::sync::{LazyLock, Mutex};
+
+    use gstreamer as gst;
+    use gstreamer_base as gst_base;
+    use gstreamer_video as gst_video;
+
+    use gst::prelude::*;
+    use gst::subclass::prelude::*;
+    use gst_base::subclass::prelude::*;
+
+    use super::super::canvas::FORMATS;
+    use super::ColorFilterMode;
+
+    static CAT: LazyLock<gst::DebugCategory> = LazyLock::new(|| {
+        gst::DebugCategory::new(
+            "rscolorfilter",
+            gst::DebugColorFlags::empty(),
+            Some("Rust grayscale/invert video filter"),
+        )
+    });
+
+    const DEFAULT_MODE: ColorFilterMode = ColorFilterMode::Grayscale;
+
+    struct State {
+        info: gst_video::VideoInfo,
+        /// Byte offsets of R, G and B in a pixel
+        rgb: [usize; 3],
+    }
+
+    pub struct ColorFilter {
+        mode: Mutex<ColorFilterMode>,
+        state: Mutex<Option<State>>,
+    }
+
+    impl Default for ColorFilter {
+        fn default() -> Self {
+            ColorFilter {
+                mode: Mutex::new(DEFAULT_MODE),
+                state: Mutex::new(None),
+            }
+        }
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for ColorFilter {
+        const NAME: &'static str = "GstRsColorFilter";
+        type Type = super::ColorFilter;
+        type ParentType = gst_base::BaseTransform;
+    }
+
+    impl ObjectImpl for ColorFilter {
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: LazyLock<Vec<glib::ParamSpec>> = LazyLock::new(|| {
+                vec![glib::ParamSpecEnum::builder_with_default("mode", DEFAULT_MODE)
+                    .nick("Mode")
+                    .blurb("How the colours are changed")
+                    .mutable_playing()
+                    .build()]
+            });
+
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+            match pspec.name() {
+                "mode" => {
+                    let mode = value.get().expect("type checked upstream");
+                    gst::info!(CAT, imp: self, "Changing mode to {:?}", mode);
+                    *self.mode.lock().unwrap() = mode;
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            match pspec.name() {
+                "mode" => self.mode.lock().unwrap().to_value(),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    impl GstObjectImpl for ColorFilter {}
+
+    impl ElementImpl for ColorFilter {
+        fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+            static ELEMENT_METADATA: LazyLock<gst::subclass::ElementMetadata> =
+                LazyLock::new(|| {
+                    gst::subclass::ElementMetadata::new(
+                        "Color filter",
+                        "Filter/Effect/Video",
+                        "Turns RGB video to grayscale or inverts its colours",
+                        "gstream_prac",
+                    )
+                });
+
+            Some(&*ELEMENT_METADATA)
+        }
+
+        fn pad_templates() -> &'static [gst::PadTemplate] {
+            static PAD_TEMPLATES: LazyLock<Vec<gst::PadTemplate>> = LazyLock::new(|| {
+                let caps = gst_video::VideoCapsBuilder::new()
+                    .format_list(FORMATS.iter().map(|(format, _)| *format))
+                    .build();
+                let src_pad_template = gst::PadTemplate::new(
+                    "src",
+                    gst::PadDirection::Src,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+                let sink_pad_template = gst::PadTemplate::new(
+                    "sink",
+                    gst::PadDirection::Sink,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                vec![src_pad_template, sink_pad_template]
+            });
+
+            PAD_TEMPLATES.as_ref()
+        }
+    }
+
+    impl BaseTransformImpl for ColorFilter {
+        const MODE: gst_base::subclass::BaseTransformMode =
+            gst_base::subclass::BaseTransformMode::AlwaysInPlace;
+        const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+        const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+
+        fn set_caps(&self, incaps: &gst::Caps, outcaps: &gst::Caps) -> Result<(), gst::LoggableError> {
+            let info = gst_video::VideoInfo::from_caps(incaps)
+                .map_err(|_| gst::loggable_error!(CAT, "Failed to parse input caps {}", incaps))?;
+            let rgb = FORMATS
+                .iter()
+                .find(|(format, _)| *format == info.format())
+                .map(|(_, rgb)| *rgb)
+                .ok_or_else(|| gst::loggable_error!(CAT, "Unsupported format {:?}", info.format()))?;
+
+            gst::debug!(CAT, imp: self, "Configured for caps {} to {}", incaps, outcaps);
+            *self.state.lock().unwrap() = Some(State { info, rgb });
+
+            Ok(())
+        }
+
+        fn stop(&self) -> Result<(), gst::ErrorMessage> {
+            *self.state.lock().unwrap() = None;
+            gst::info!(CAT, imp: self, "Stopped");
+
+            Ok(())
+        }
+
+        fn transform_ip(&self, buf: &mut gst::BufferRef) -> Result<gst::FlowSuccess, gst::FlowError> {
+            let mode = *self.mode.lock().unwrap();
+            let state_guard = self.state.lock().unwrap();
+            let state = state_guard.as_ref().ok_or_else(|| {
+                gst::element_imp_error!(self, gst::CoreError::Negotiation, ["Have no state yet"]);
+                gst::FlowError::NotNegotiated
+            })?;
+
+            let mut frame = gst_video::VideoFrameRef::from_buffer_ref_writable(buf, &state.info)
+                .map_err(|_| {
+                    gst::element_imp_error!(self, gst::CoreError::Failed, ["Failed to map buffer writable"]);
+                    gst::FlowError::Error
+                })?;
+
+            let width = frame.width() as usize;
+            let height = frame.height() as usize;
+            let stride = frame.plane_stride()[0] as usize;
+            let [r, g, b] = state.rgb;
+            let data = frame.plane_data_mut(0).map_err(|_| gst::FlowError::Error)?;
+
+            for line in data.chunks_mut(stride).take(height) {
+                for pixel in line[..width * 4].chunks_exact_mut(4) {
+                    match mode {
+                        ColorFilterMode::Grayscale => {
+                            // BT.601 luma in 8.8 fixed point
+                            let luma = (77 * u32::from(pixel[r])
+                                + 150 * u32::from(pixel[g])
+                                + 29 * u32::from(pixel[b]))
+                                >> 8;
+                            pixel[r] = luma as u8;
+                            pixel[g] = luma as u8;
+                            pixel[b] = luma as u8;
+                        }
+                        ColorFilterMode::Invert => {
+                            pixel[r] = 255 - pixel[r];
+                            pixel[g] = 255 - pixel[g];
+                            pixel[b] = 255 - pixel[b];
+                        }
+                    }
+                }
+            }
+
+            Ok(gst::FlowSuccess::Ok)
+        }
+    }
+}
+
+glib::wrapper! {
+    pub struct ColorFilter(ObjectSubclass<imp::ColorFilter>) @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "rscolorfilter",
+        gst::Rank::None,
+        ColorFilter::static_type(),
+    )
+}
@@ -0,0 +1,244 @@
+use gstreamer as gst;
+use gstreamer_base as gst_base;
+
+use gst::prelude::*;
+
+mod imp {
+    use std::sync::{LazyLock, Mutex};
+
+    use gstreamer as gst;
+    use gstreamer_audio as gst_audio;
+    use gstreamer_base as gst_base;
+
+    use byte_slice_cast::*;
+    use gst::prelude::*;
+    use gst::subclass::prelude::*;
+    use gst_base::subclass::prelude::*;
+
+    static CAT: LazyLock<gst::DebugCategory> = LazyLock::new(|| {
+        gst::DebugCategory::new(
+            "rsgain",
+            gst::DebugColorFlags::empty(),
+            Some("Rust gain and fade in filter"),
+        )
+    });
+
+    const DEFAULT_GAIN: f64 = 1.0;
+    const DEFAULT_FADE_IN: u64 = 0;
+
+    #[derive(Debug, Clone, Copy)]
+    struct Settings {
+        /// Linear gain applied to every sample
+        gain: f64,
+        /// Duration of the fade from silence to `gain`, in nanoseconds
+        fade_in: u64,
+    }
+
+    impl Default for Settings {
+        fn default() -> Self {
+            Settings {
+                gain: DEFAULT_GAIN,
+                fade_in: DEFAULT_FADE_IN,
+            }
+        }
+    }
+
+    struct State {
+        info: gst_audio::AudioInfo,
+        /// Frames processed since the last caps/start, drives the fade
+        frames: u64,
+    }
+
+    #[derive(Default)]
+    pub struct Gain {
+        settings: Mutex<Settings>,
+        state: Mutex<Option<State>>,
+    }
+
+    impl Gain {
+        fn factor(settings: &Settings, fade_frames: u64, frame: u64) -> f64 {
+            if frame >= fade_frames {
+                settings.gain
+            } else {
+                settings.gain * frame as f64 / fade_frames as f64
+            }
+        }
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for Gain {
+        const NAME: &'static str = "GstRsGain";
+        type Type = super::Gain;
+        type ParentType = gst_base::BaseTransform;
+    }
+
+    impl ObjectImpl for Gain {
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: LazyLock<Vec<glib::ParamSpec>> = LazyLock::new(|| {
+                vec![
+                    glib::ParamSpecDouble::builder("gain")
+                        .nick("Gain")
+                        .blurb("Linear gain applied to every sample")
+                        .minimum(0.0)
+                        .maximum(10.0)
+                        .default_value(DEFAULT_GAIN)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecUInt64::builder("fade-in")
+                        .nick("Fade in")
+                        .blurb("Duration of the fade in from silence, in nanoseconds")
+                        .default_value(DEFAULT_FADE_IN)
+                        .mutable_ready()
+                        .build(),
+                ]
+            });
+
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+            let mut settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "gain" => {
+                    let gain = value.get().expect("type checked upstream");
+                    gst::info!(CAT, imp: self, "Changing gain from {} to {}", settings.gain, gain);
+                    settings.gain = gain;
+                }
+                "fade-in" => settings.fade_in = value.get().expect("type checked upstream"),
+                _ => unreachable!(),
+            }
+        }
+
+        fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            let settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "gain" => settings.gain.to_value(),
+                "fade-in" => settings.fade_in.to_value(),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    impl GstObjectImpl for Gain {}
+
+    impl ElementImpl for Gain {
+        fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+            static ELEMENT_METADATA: LazyLock<gst::subclass::ElementMetadata> =
+                LazyLock::new(|| {
+                    gst::subclass::ElementMetadata::new(
+                        "Gain",
+                        "Filter/Effect/Audio",
+                        "Applies a gain with an optional fade in",
+                        "gstream_prac",
+                    )
+                });
+
+            Some(&*ELEMENT_METADATA)
+        }
+
+        fn pad_templates() -> &'static [gst::PadTemplate] {
+            static PAD_TEMPLATES: LazyLock<Vec<gst::PadTemplate>> = LazyLock::new(|| {
+                let caps = gst_audio::AudioCapsBuilder::new_interleaved()
+                    .format_list([gst_audio::AUDIO_FORMAT_S16, gst_audio::AUDIO_FORMAT_F32])
+                    .build();
+                let src_pad_template = gst::PadTemplate::new(
+                    "src",
+                    gst::PadDirection::Src,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+                let sink_pad_template = gst::PadTemplate::new(
+                    "sink",
+                    gst::PadDirection::Sink,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                vec![src_pad_template, sink_pad_template]
+            });
+
+            PAD_TEMPLATES.as_ref()
+        }
+    }
+
+    impl BaseTransformImpl for Gain {
+        const MODE: gst_base::subclass::BaseTransformMode =
+            gst_base::subclass::BaseTransformMode::AlwaysInPlace;
+        const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+        const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+
+        fn set_caps(&self, incaps: &gst::Caps, outcaps: &gst::Caps) -> Result<(), gst::LoggableError> {
+            let info = gst_audio::AudioInfo::from_caps(incaps)
+                .map_err(|_| gst::loggable_error!(CAT, "Failed to parse input caps {}", incaps))?;
+
+            gst::debug!(CAT, imp: self, "Configured for caps {} to {}", incaps, outcaps);
+            *self.state.lock().unwrap() = Some(State { info, frames: 0 });
+
+            Ok(())
+        }
+
+        fn stop(&self) -> Result<(), gst::ErrorMessage> {
+            *self.state.lock().unwrap() = None;
+            gst::info!(CAT, imp: self, "Stopped");
+
+            Ok(())
+        }
+
+        fn transform_ip(&self, buf: &mut gst::BufferRef) -> Result<gst::FlowSuccess, gst::FlowError> {
+            let settings = *self.settings.lock().unwrap();
+            let mut state_guard = self.state.lock().unwrap();
+            let state = state_guard.as_mut().ok_or_else(|| {
+                gst::element_imp_error!(self, gst::CoreError::Negotiation, ["Have no state yet"]);
+                gst::FlowError::NotNegotiated
+            })?;
+
+            let channels = state.info.channels() as usize;
+            let fade_frames = (u128::from(settings.fade_in) * u128::from(state.info.rate())
+                / u128::from(gst::ClockTime::SECOND.nseconds())) as u64;
+
+            let mut map = buf.map_writable().map_err(|_| {
+                gst::element_imp_error!(self, gst::CoreError::Failed, ["Failed to map buffer writable"]);
+                gst::FlowError::Error
+            })?;
+
+            let frames = if state.info.format() == gst_audio::AUDIO_FORMAT_F32 {
+                let samples = map.as_mut_slice_of::<f32>().map_err(|_| gst::FlowError::Error)?;
+                for (i, frame) in samples.chunks_exact_mut(channels).enumerate() {
+                    let factor = Self::factor(&settings, fade_frames, state.frames + i as u64);
+                    for sample in frame {
+                        *sample = (*sample as f64 * factor) as f32;
+                    }
+                }
+                samples.len() / channels
+            } else {
+                let samples = map.as_mut_slice_of::<i16>().map_err(|_| gst::FlowError::Error)?;
+                for (i, frame) in samples.chunks_exact_mut(channels).enumerate() {
+                    let factor = Self::factor(&settings, fade_frames, state.frames + i as u64);
+                    for sample in frame {
+                        *sample = (*sample as f64 * factor)
+                            .clamp(i16::MIN as f64, i16::MAX as f64) as i16;
+                    }
+                }
+                samples.len() / channels
+            };
+            state.frames += frames as u64;
+
+            Ok(gst::FlowSuccess::Ok)
+        }
+    }
+}
+
+glib::wrapper! {
+    pub struct Gain(ObjectSubclass<imp::Gain>) @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "rsgain",
+        gst::Rank::None,
+        Gain::static_type(),
+    )
+}
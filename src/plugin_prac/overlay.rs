@@ -0,0 +1,401 @@
+use gstreamer as gst;
+use gstreamer_base as gst_base;
+
+use epaint::text::{FontId, Fonts};
+use epaint::{Color32, Pos2, Rect, Shape};
+use glib::subclass::prelude::*;
+use gst::prelude::*;
+
+const TEXT_PADDING: f32 = 3.0;
+
+/// What the draw callback of an `rsoverlay` gets for each frame
+pub struct OverlayFrame<'a> {
+    /// Buffer timestamp
+    pub pts: Option<gst::ClockTime>,
+    /// Stream time of the buffer, what a player shows as the position
+    pub position: Option<gst::ClockTime>,
+    /// Duration reported upstream, refreshed about once a second
+    pub duration: Option<gst::ClockTime>,
+    /// Frame number at `position`, unknown for variable framerates
+    pub frame: Option<u64>,
+    pub width: f32,
+    pub height: f32,
+    fonts: &'a Fonts,
+}
+
+impl OverlayFrame<'_> {
+    /// Monospace text on a translucent box, `pos` is the top left corner of the text
+    pub fn text(&self, pos: Pos2, text: impl Into<String>, size: f32, color: Color32) -> [Shape; 2] {
+        text_shapes(self.fonts, pos, text.into(), size, color)
+    }
+}
+
+fn text_shapes(fonts: &Fonts, pos: Pos2, text: String, size: f32, color: Color32) -> [Shape; 2] {
+    let galley = fonts.layout_no_wrap(text, FontId::monospace(size), color);
+    let rect = Rect::from_min_size(pos, galley.size()).expand(TEXT_PADDING);
+    [
+        Shape::rect_filled(rect, 2.0, Color32::from_black_alpha(160)),
+        Shape::galley(pos, galley, color),
+    ]
+}
+
+mod imp {
+    use std::sync::{LazyLock, Mutex};
+
+    use gstreamer as gst;
+    use gstreamer_base as gst_base;
+    use gstreamer_video as gst_video;
+
+    use epaint::text::{FontDefinitions, Fonts};
+    use epaint::{pos2, Color32, Shape};
+    use gst::prelude::*;
+    use gst::subclass::prelude::*;
+    use gst_base::prelude::*;
+    use gst_base::subclass::prelude::*;
+
+    use crate::timecode::{self, Timecode};
+
+    use super::super::canvas::{Canvas, FORMATS};
+    use super::{text_shapes, OverlayFrame, TEXT_PADDING};
+
+    static CAT: LazyLock<gst::DebugCategory> = LazyLock::new(|| {
+        gst::DebugCategory::new(
+            "rsoverlay",
+            gst::DebugColorFlags::empty(),
+            Some("Rust epaint text and shape overlay"),
+        )
+    });
+
+    const DEFAULT_TEXT: &str = "";
+    const DEFAULT_SHOW_TIMECODE: bool = true;
+    const DEFAULT_SHOW_POSITION: bool = true;
+    const DEFAULT_FONT_SIZE: f32 = 16.0;
+    const DEFAULT_COLOR: u32 = 0xffffffff;
+
+    /// Largest font atlas side, epaint grows the atlas up to this
+    const MAX_TEXTURE_SIDE: usize = 2048;
+    /// Frames between duration queries, the duration of live and growing files changes
+    const DURATION_QUERY_INTERVAL: u64 = 30;
+
+    pub(super) type DrawCallback = Box<dyn FnMut(&OverlayFrame) -> Vec<Shape> + Send + 'static>;
+
+    #[derive(Debug, Clone)]
+    struct Settings {
+        text: String,
+        show_timecode: bool,
+        show_position: bool,
+        font_size: f32,
+        /// ARGB, like the `color` property of `textoverlay`
+        color: u32,
+    }
+
+    impl Default for Settings {
+        fn default() -> Self {
+            Settings {
+                text: DEFAULT_TEXT.to_string(),
+                show_timecode: DEFAULT_SHOW_TIMECODE,
+                show_position: DEFAULT_SHOW_POSITION,
+                font_size: DEFAULT_FONT_SIZE,
+                color: DEFAULT_COLOR,
+            }
+        }
+    }
+
+    struct State {
+        info: gst_video::VideoInfo,
+        /// Byte offsets of R, G and B in a pixel
+        rgb: [usize; 3],
+        /// Frames drawn since caps were set, paces the duration queries
+        frames: u64,
+        duration: Option<gst::ClockTime>,
+    }
+
+    pub struct Overlay {
+        settings: Mutex<Settings>,
+        state: Mutex<Option<State>>,
+        /// Laid out glyphs live in the font atlas, created once in `start` because it is slow
+        fonts: Mutex<Option<Fonts>>,
+        pub(super) callback: Mutex<Option<DrawCallback>>,
+    }
+
+    impl Default for Overlay {
+        fn default() -> Self {
+            Overlay {
+                settings: Mutex::new(Settings::default()),
+                state: Mutex::new(None),
+                fonts: Mutex::new(None),
+                callback: Mutex::new(None),
+            }
+        }
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for Overlay {
+        const NAME: &'static str = "GstRsOverlay";
+        type Type = super::Overlay;
+        type ParentType = gst_base::BaseTransform;
+    }
+
+    impl ObjectImpl for Overlay {
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: LazyLock<Vec<glib::ParamSpec>> = LazyLock::new(|| {
+                vec![
+                    glib::ParamSpecString::builder("text")
+                        .nick("Text")
+                        .blurb("Custom text drawn below the timing lines")
+                        .default_value(Some(DEFAULT_TEXT))
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecBoolean::builder("show-timecode")
+                        .nick("Show timecode")
+                        .blurb("Draw the SMPTE timecode and frame number")
+                        .default_value(DEFAULT_SHOW_TIMECODE)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecBoolean::builder("show-position")
+                        .nick("Show position")
+                        .blurb("Draw the stream position and duration")
+                        .default_value(DEFAULT_SHOW_POSITION)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecFloat::builder("font-size")
+                        .nick("Font size")
+                        .blurb("Size of the built-in text in pixels")
+                        .minimum(6.0)
+                        .maximum(128.0)
+                        .default_value(DEFAULT_FONT_SIZE)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecUInt::builder("color")
+                        .nick("Color")
+                        .blurb("Colour of the built-in text, big-endian ARGB")
+                        .default_value(DEFAULT_COLOR)
+                        .mutable_playing()
+                        .build(),
+                ]
+            });
+
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+            let mut settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "text" => {
+                    let text: Option<String> = value.get().expect("type checked upstream");
+                    settings.text = text.unwrap_or_default();
+                }
+                "show-timecode" => settings.show_timecode = value.get().expect("type checked upstream"),
+                "show-position" => settings.show_position = value.get().expect("type checked upstream"),
+                "font-size" => settings.font_size = value.get().expect("type checked upstream"),
+                "color" => settings.color = value.get().expect("type checked upstream"),
+                _ => unreachable!(),
+            }
+        }
+
+        fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            let settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "text" => settings.text.to_value(),
+                "show-timecode" => settings.show_timecode.to_value(),
+                "show-position" => settings.show_position.to_value(),
+                "font-size" => settings.font_size.to_value(),
+                "color" => settings.color.to_value(),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    impl GstObjectImpl for Overlay {}
+
+    impl ElementImpl for Overlay {
+        fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+            static ELEMENT_METADATA: LazyLock<gst::subclass::ElementMetadata> =
+                LazyLock::new(|| {
+                    gst::subclass::ElementMetadata::new(
+                        "Overlay",
+                        "Filter/Editor/Video",
+                        "Draws timing information, text and shapes on RGB video with epaint",
+                        "gstream_prac",
+                    )
+                });
+
+            Some(&*ELEMENT_METADATA)
+        }
+
+        fn pad_templates() -> &'static [gst::PadTemplate] {
+            static PAD_TEMPLATES: LazyLock<Vec<gst::PadTemplate>> = LazyLock::new(|| {
+                let caps = gst_video::VideoCapsBuilder::new()
+                    .format_list(FORMATS.iter().map(|(format, _)| *format))
+                    .build();
+                let src_pad_template = gst::PadTemplate::new(
+                    "src",
+                    gst::PadDirection::Src,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+                let sink_pad_template = gst::PadTemplate::new(
+                    "sink",
+                    gst::PadDirection::Sink,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                vec![src_pad_template, sink_pad_template]
+            });
+
+            PAD_TEMPLATES.as_ref()
+        }
+    }
+
+    impl BaseTransformImpl for Overlay {
+        const MODE: gst_base::subclass::BaseTransformMode =
+            gst_base::subclass::BaseTransformMode::AlwaysInPlace;
+        const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+        const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+
+        fn start(&self) -> Result<(), gst::ErrorMessage> {
+            *self.fonts.lock().unwrap() = Some(Fonts::new(1.0, MAX_TEXTURE_SIDE, FontDefinitions::default()));
+            gst::info!(CAT, imp: self, "Started");
+
+            Ok(())
+        }
+
+        fn set_caps(&self, incaps: &gst::Caps, outcaps: &gst::Caps) -> Result<(), gst::LoggableError> {
+            let info = gst_video::VideoInfo::from_caps(incaps)
+                .map_err(|_| gst::loggable_error!(CAT, "Failed to parse input caps {}", incaps))?;
+            let rgb = FORMATS
+                .iter()
+                .find(|(format, _)| *format == info.format())
+                .map(|(_, rgb)| *rgb)
+                .ok_or_else(|| gst::loggable_error!(CAT, "Unsupported format {:?}", info.format()))?;
+
+            gst::debug!(CAT, imp: self, "Configured for caps {} to {}", incaps, outcaps);
+            *self.state.lock().unwrap() = Some(State {
+                info,
+                rgb,
+                frames: 0,
+                duration: None,
+            });
+
+            Ok(())
+        }
+
+        fn stop(&self) -> Result<(), gst::ErrorMessage> {
+            *self.state.lock().unwrap() = None;
+            *self.fonts.lock().unwrap() = None;
+            gst::info!(CAT, imp: self, "Stopped");
+
+            Ok(())
+        }
+
+        fn transform_ip(&self, buf: &mut gst::BufferRef) -> Result<gst::FlowSuccess, gst::FlowError> {
+            let settings = self.settings.lock().unwrap().clone();
+            let mut state_guard = self.state.lock().unwrap();
+            let state = state_guard.as_mut().ok_or_else(|| {
+                gst::element_imp_error!(self, gst::CoreError::Negotiation, ["Have no state yet"]);
+                gst::FlowError::NotNegotiated
+            })?;
+            let fonts_guard = self.fonts.lock().unwrap();
+            let fonts = fonts_guard.as_ref().ok_or(gst::FlowError::Flushing)?;
+
+            if state.frames % DURATION_QUERY_INTERVAL == 0 {
+                state.duration = self.obj().sink_pad().peer_query_duration::<gst::ClockTime>();
+            }
+            state.frames += 1;
+
+            let pts = buf.pts();
+            let segment = self.obj().segment();
+            let position = segment
+                .downcast_ref::<gst::ClockTime>()
+                .zip(pts)
+                .and_then(|(segment, pts)| segment.to_stream_time(pts));
+            let fps = state.info.fps();
+            let frame = position.and_then(|position| timecode::frame_at(position, fps));
+
+            fonts.begin_frame(1.0, MAX_TEXTURE_SIDE);
+            let overlay_frame = OverlayFrame {
+                pts,
+                position,
+                duration: state.duration,
+                frame,
+                width: state.info.width() as f32,
+                height: state.info.height() as f32,
+                fonts,
+            };
+
+            // Built-in lines stack down from the top left corner
+            let [a, r, g, b] = settings.color.to_be_bytes();
+            let color = Color32::from_rgba_unmultiplied(r, g, b, a);
+            let mut lines = Vec::new();
+            if settings.show_timecode {
+                let timecode = frame.and_then(|frame| Timecode::from_frame(frame, fps));
+                match timecode.zip(frame) {
+                    Some((timecode, frame)) => lines.push(format!("{timecode} #{frame}")),
+                    None => lines.push(format!("{:.3}", pts.display())),
+                }
+            }
+            if settings.show_position {
+                lines.push(format!("{:.3} / {:.3}", position.display(), state.duration.display()));
+            }
+            lines.extend(settings.text.lines().map(str::to_string));
+
+            let line_height = settings.font_size + 2.0 * TEXT_PADDING + 2.0;
+            let mut shapes: Vec<Shape> = lines
+                .into_iter()
+                .enumerate()
+                .flat_map(|(i, line)| {
+                    let pos = pos2(2.0 * TEXT_PADDING, 2.0 * TEXT_PADDING + i as f32 * line_height);
+                    text_shapes(fonts, pos, line, settings.font_size, color)
+                })
+                .collect();
+
+            if let Some(callback) = self.callback.lock().unwrap().as_mut() {
+                shapes.extend(callback(&overlay_frame));
+            }
+            if shapes.is_empty() {
+                return Ok(gst::FlowSuccess::Ok);
+            }
+
+            let mut video_frame = gst_video::VideoFrameRef::from_buffer_ref_writable(buf, &state.info)
+                .map_err(|_| {
+                    gst::element_imp_error!(self, gst::CoreError::Failed, ["Failed to map buffer writable"]);
+                    gst::FlowError::Error
+                })?;
+            let width = video_frame.width() as usize;
+            let height = video_frame.height() as usize;
+            let stride = video_frame.plane_stride()[0] as usize;
+            let data = video_frame.plane_data_mut(0).map_err(|_| gst::FlowError::Error)?;
+
+            Canvas::new(data, width, height, stride, state.rgb).draw_shapes(shapes, Some(fonts));
+
+            Ok(gst::FlowSuccess::Ok)
+        }
+    }
+}
+
+glib::wrapper! {
+    pub struct Overlay(ObjectSubclass<imp::Overlay>) @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+impl Overlay {
+    /// Calls `callback` on the streaming thread for every frame, the shapes it returns are drawn
+    /// on top of the built-in text. Positions are in pixels from the top left corner.
+    pub fn set_draw_callback<F>(&self, callback: F)
+    where
+        F: FnMut(&OverlayFrame) -> Vec<Shape> + Send + 'static,
+    {
+        *self.imp().callback.lock().unwrap() = Some(Box::new(callback));
+    }
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "rsoverlay",
+        gst::Rank::None,
+        Overlay::static_type(),
+    )
+}
@@ -0,0 +1,523 @@
+use gstreamer as gst;
+use gstreamer_base as gst_base;
+
+use gst::prelude::*;
+
+mod imp {
+    use std::sync::{LazyLock, Mutex};
+
+    use gstreamer as gst;
+    use gstreamer_audio as gst_audio;
+    use gstreamer_base as gst_base;
+
+    use byte_slice_cast::*;
+    use gst::prelude::*;
+    use gst::subclass::prelude::*;
+    use gst_base::prelude::*;
+    use gst_base::subclass::base_src::CreateSuccess;
+    use gst_base::subclass::prelude::*;
+
+    static CAT: LazyLock<gst::DebugCategory> = LazyLock::new(|| {
+        gst::DebugCategory::new(
+            "psychedelicsrc",
+            gst::DebugColorFlags::empty(),
+            Some("Psychedelic waveform source"),
+        )
+    });
+
+    const DEFAULT_FREQ_BASE: f64 = 1100.0;
+    const DEFAULT_AMPLITUDE: f64 = 500.0;
+    const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+    const DEFAULT_CHANNELS: u32 = 1;
+    const DEFAULT_SAMPLES_PER_BUFFER: u32 = 512;
+    const DEFAULT_IS_LIVE: bool = false;
+
+    #[derive(Debug, Clone, Copy)]
+    struct Settings {
+        freq_base: f64,
+        amplitude: f64,
+        sample_rate: u32,
+        channels: u32,
+        samples_per_buffer: u32,
+        is_live: bool,
+    }
+
+    impl Default for Settings {
+        fn default() -> Self {
+            Settings {
+                freq_base: DEFAULT_FREQ_BASE,
+                amplitude: DEFAULT_AMPLITUDE,
+                sample_rate: DEFAULT_SAMPLE_RATE,
+                channels: DEFAULT_CHANNELS,
+                samples_per_buffer: DEFAULT_SAMPLES_PER_BUFFER,
+                is_live: DEFAULT_IS_LIVE,
+            }
+        }
+    }
+
+    /// The waveform from the `need-data` callback of `basic_tutorial_8`. The slow oscillator
+    /// (`c`, `d`) steps once every `samples_per_buffer` frames and bends the frequency of the
+    /// fast one (`a`, `b`).
+    #[derive(Debug, Clone, Copy)]
+    struct Waveform {
+        a: f64,
+        b: f64,
+        c: f64,
+        d: f64,
+        freq: f64,
+        /// Frames generated so far
+        frame: u64,
+    }
+
+    impl Default for Waveform {
+        fn default() -> Self {
+            Waveform {
+                a: 0.0,
+                b: 1.0,
+                c: 0.0,
+                d: 1.0,
+                freq: DEFAULT_FREQ_BASE,
+                frame: 0,
+            }
+        }
+    }
+
+    impl Waveform {
+        fn step(&mut self, settings: &Settings) -> f64 {
+            if self.frame % u64::from(settings.samples_per_buffer) == 0 {
+                self.c += self.d;
+                self.d -= self.c / 1000.0;
+                self.freq = settings.freq_base + 1000.0 * self.d;
+            }
+            self.a += self.b;
+            self.b -= self.a / self.freq;
+            self.frame += 1;
+            self.a
+        }
+
+        fn fill(&mut self, settings: &Settings, samples: &mut [i16]) {
+            for frame in samples.chunks_exact_mut(settings.channels as usize) {
+                let value = (settings.amplitude * self.step(settings))
+                    .clamp(i16::MIN as f64, i16::MAX as f64) as i16;
+                frame.fill(value);
+            }
+        }
+
+        /// Runs the oscillators forward without output, used for seeking
+        fn skip(&mut self, settings: &Settings, frames: u64) {
+            for _ in 0..frames {
+                self.step(settings);
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct State {
+        info: Option<gst_audio::AudioInfo>,
+        wave: Waveform,
+        /// Offset of the next buffer, in frames
+        sample_offset: u64,
+        /// Frame at which to send EOS, from the seek segment
+        sample_stop: Option<u64>,
+    }
+
+    #[derive(Default)]
+    struct ClockWait {
+        clock_id: Option<gst::SingleShotClockId>,
+        flushing: bool,
+    }
+
+    fn frames_to_time(frames: u64, rate: u32) -> gst::ClockTime {
+        gst::ClockTime::SECOND
+            .mul_div_floor(frames, u64::from(rate))
+            .expect("u64 overflow")
+    }
+
+    fn time_to_frames(time: gst::ClockTime, rate: u32) -> u64 {
+        time.mul_div_floor(u64::from(rate), gst::ClockTime::SECOND.nseconds())
+            .expect("u64 overflow")
+            .nseconds()
+    }
+
+    #[derive(Default)]
+    pub struct PsychedelicSrc {
+        settings: Mutex<Settings>,
+        state: Mutex<State>,
+        clock_wait: Mutex<ClockWait>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for PsychedelicSrc {
+        const NAME: &'static str = "GstPsychedelicSrc";
+        type Type = super::PsychedelicSrc;
+        type ParentType = gst_base::PushSrc;
+    }
+
+    impl ObjectImpl for PsychedelicSrc {
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: LazyLock<Vec<glib::ParamSpec>> = LazyLock::new(|| {
+                vec![
+                    glib::ParamSpecDouble::builder("freq-base")
+                        .nick("Base frequency")
+                        .blurb("Centre of the oscillator's frequency parameter, which sweeps ±1000 around it")
+                        .minimum(1001.0)
+                        .maximum(100_000.0)
+                        .default_value(DEFAULT_FREQ_BASE)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecDouble::builder("amplitude")
+                        .nick("Amplitude")
+                        .blurb("Scale applied to the waveform before it is converted to S16")
+                        .minimum(0.0)
+                        .maximum(i16::MAX as f64)
+                        .default_value(DEFAULT_AMPLITUDE)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecUInt::builder("sample-rate")
+                        .nick("Sample rate")
+                        .blurb("Samples per second")
+                        .minimum(1)
+                        .maximum(192_000)
+                        .default_value(DEFAULT_SAMPLE_RATE)
+                        .mutable_ready()
+                        .build(),
+                    glib::ParamSpecUInt::builder("channels")
+                        .nick("Channels")
+                        .blurb("Number of channels, all carrying the same waveform")
+                        .minimum(1)
+                        .maximum(8)
+                        .default_value(DEFAULT_CHANNELS)
+                        .mutable_ready()
+                        .build(),
+                    glib::ParamSpecUInt::builder("samples-per-buffer")
+                        .nick("Samples per buffer")
+                        .blurb("Frames in each output buffer, also the sweep step")
+                        .minimum(1)
+                        .maximum(u32::MAX / 8)
+                        .default_value(DEFAULT_SAMPLES_PER_BUFFER)
+                        .mutable_ready()
+                        .build(),
+                    glib::ParamSpecBoolean::builder("is-live")
+                        .nick("Is live")
+                        .blurb("Produce buffers in real time, following the pipeline clock")
+                        .default_value(DEFAULT_IS_LIVE)
+                        .mutable_ready()
+                        .build(),
+                ]
+            });
+
+            PROPERTIES.as_ref()
+        }
+
+        fn constructed(&self) {
+            self.parent_constructed();
+
+            let obj = self.obj();
+            obj.set_live(DEFAULT_IS_LIVE);
+            obj.set_format(gst::Format::Time);
+        }
+
+        fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+            let mut settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "freq-base" => settings.freq_base = value.get().expect("type checked upstream"),
+                "amplitude" => settings.amplitude = value.get().expect("type checked upstream"),
+                "sample-rate" => {
+                    settings.sample_rate = value.get().expect("type checked upstream");
+                    drop(settings);
+                    let _ = self
+                        .obj()
+                        .post_message(gst::message::Latency::builder().src(&*self.obj()).build());
+                }
+                "channels" => settings.channels = value.get().expect("type checked upstream"),
+                "samples-per-buffer" => {
+                    settings.samples_per_buffer = value.get().expect("type checked upstream");
+                    drop(settings);
+                    let _ = self
+                        .obj()
+                        .post_message(gst::message::Latency::builder().src(&*self.obj()).build());
+                }
+                "is-live" => {
+                    let is_live = value.get().expect("type checked upstream");
+                    settings.is_live = is_live;
+                    drop(settings);
+                    self.obj().set_live(is_live);
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            let settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "freq-base" => settings.freq_base.to_value(),
+                "amplitude" => settings.amplitude.to_value(),
+                "sample-rate" => settings.sample_rate.to_value(),
+                "channels" => settings.channels.to_value(),
+                "samples-per-buffer" => settings.samples_per_buffer.to_value(),
+                "is-live" => settings.is_live.to_value(),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    impl GstObjectImpl for PsychedelicSrc {}
+
+    impl ElementImpl for PsychedelicSrc {
+        fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+            static ELEMENT_METADATA: LazyLock<gst::subclass::ElementMetadata> =
+                LazyLock::new(|| {
+                    gst::subclass::ElementMetadata::new(
+                        "Psychedelic waveform source",
+                        "Source/Audio",
+                        "Generates the sweeping waveform of basic tutorial 8",
+                        "gstream_prac",
+                    )
+                });
+
+            Some(&*ELEMENT_METADATA)
+        }
+
+        fn pad_templates() -> &'static [gst::PadTemplate] {
+            static PAD_TEMPLATES: LazyLock<Vec<gst::PadTemplate>> = LazyLock::new(|| {
+                let caps = gst_audio::AudioCapsBuilder::new_interleaved()
+                    .format(gst_audio::AUDIO_FORMAT_S16)
+                    .build();
+                let src_pad_template = gst::PadTemplate::new(
+                    "src",
+                    gst::PadDirection::Src,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                vec![src_pad_template]
+            });
+
+            PAD_TEMPLATES.as_ref()
+        }
+    }
+
+    impl BaseSrcImpl for PsychedelicSrc {
+        fn start(&self) -> Result<(), gst::ErrorMessage> {
+            *self.state.lock().unwrap() = State::default();
+            self.unlock_stop()?;
+            gst::info!(CAT, imp: self, "Started");
+
+            Ok(())
+        }
+
+        fn stop(&self) -> Result<(), gst::ErrorMessage> {
+            *self.state.lock().unwrap() = State::default();
+            self.unlock()?;
+            gst::info!(CAT, imp: self, "Stopped");
+
+            Ok(())
+        }
+
+        fn caps(&self, filter: Option<&gst::Caps>) -> Option<gst::Caps> {
+            let settings = *self.settings.lock().unwrap();
+            let caps = gst_audio::AudioInfo::builder(
+                gst_audio::AUDIO_FORMAT_S16,
+                settings.sample_rate,
+                settings.channels,
+            )
+            .build()
+            .ok()?
+            .to_caps()
+            .ok()?;
+
+            Some(match filter {
+                Some(filter) => filter.intersect_with_mode(&caps, gst::CapsIntersectMode::First),
+                None => caps,
+            })
+        }
+
+        fn set_caps(&self, caps: &gst::Caps) -> Result<(), gst::LoggableError> {
+            let info = gst_audio::AudioInfo::from_caps(caps)
+                .map_err(|_| gst::loggable_error!(CAT, "Failed to build `AudioInfo` from caps {}", caps))?;
+
+            gst::debug!(CAT, imp: self, "Configuring for caps {}", caps);
+            self.state.lock().unwrap().info = Some(info);
+
+            let _ = self
+                .obj()
+                .post_message(gst::message::Latency::builder().src(&*self.obj()).build());
+
+            Ok(())
+        }
+
+        fn query(&self, query: &mut gst::QueryRef) -> bool {
+            if let gst::QueryViewMut::Latency(q) = query.view_mut() {
+                let settings = *self.settings.lock().unwrap();
+                // One buffer worth of latency, it has to be complete before it is pushed
+                let latency = frames_to_time(
+                    u64::from(settings.samples_per_buffer),
+                    settings.sample_rate,
+                );
+                gst::debug!(CAT, imp: self, "Returning latency {}", latency);
+                q.set(settings.is_live, latency, gst::ClockTime::NONE);
+                return true;
+            }
+
+            BaseSrcImplExt::parent_query(self, query)
+        }
+
+        fn is_seekable(&self) -> bool {
+            !self.settings.lock().unwrap().is_live
+        }
+
+        fn do_seek(&self, segment: &mut gst::Segment) -> bool {
+            let settings = *self.settings.lock().unwrap();
+            if settings.is_live {
+                gst::error!(CAT, imp: self, "Seeking is not supported in live mode");
+                return false;
+            }
+
+            let Some(segment) = segment.downcast_ref::<gst::ClockTime>() else {
+                gst::error!(CAT, imp: self, "Only seeking in TIME format is supported");
+                return false;
+            };
+            if segment.rate() < 0.0 {
+                gst::error!(CAT, imp: self, "Reverse playback is not supported");
+                return false;
+            }
+
+            let mut state = self.state.lock().unwrap();
+            let rate = state
+                .info
+                .as_ref()
+                .map(|info| info.rate())
+                .unwrap_or(settings.sample_rate);
+            let sample_offset = segment
+                .start()
+                .map(|start| time_to_frames(start, rate))
+                .unwrap_or(0);
+            let sample_stop = segment.stop().map(|stop| time_to_frames(stop, rate));
+
+            // The waveform depends on everything before it, so replay it up to the new offset
+            let mut wave = Waveform::default();
+            wave.skip(&settings, sample_offset);
+
+            gst::debug!(
+                CAT,
+                imp: self,
+                "Seeked to frame {} (stop {:?})",
+                sample_offset,
+                sample_stop
+            );
+            state.wave = wave;
+            state.sample_offset = sample_offset;
+            state.sample_stop = sample_stop;
+
+            true
+        }
+
+        fn unlock(&self) -> Result<(), gst::ErrorMessage> {
+            let mut clock_wait = self.clock_wait.lock().unwrap();
+            if let Some(clock_id) = clock_wait.clock_id.take() {
+                clock_id.unschedule();
+            }
+            clock_wait.flushing = true;
+
+            Ok(())
+        }
+
+        fn unlock_stop(&self) -> Result<(), gst::ErrorMessage> {
+            self.clock_wait.lock().unwrap().flushing = false;
+
+            Ok(())
+        }
+    }
+
+    impl PushSrcImpl for PsychedelicSrc {
+        fn create(
+            &self,
+            _buffer: Option<&mut gst::BufferRef>,
+        ) -> Result<CreateSuccess, gst::FlowError> {
+            let settings = *self.settings.lock().unwrap();
+            let mut state = self.state.lock().unwrap();
+            let Some(info) = state.info.clone() else {
+                gst::element_imp_error!(self, gst::CoreError::Negotiation, ["Have no caps yet"]);
+                return Err(gst::FlowError::NotNegotiated);
+            };
+            let rate = info.rate();
+
+            let spb = u64::from(settings.samples_per_buffer);
+            let n_frames = match state.sample_stop {
+                Some(stop) if state.sample_offset >= stop => {
+                    gst::debug!(CAT, imp: self, "At the end of the segment");
+                    return Err(gst::FlowError::Eos);
+                }
+                Some(stop) => spb.min(stop - state.sample_offset),
+                None => spb,
+            };
+
+            let mut buffer =
+                gst::Buffer::with_size(n_frames as usize * info.bpf() as usize).unwrap();
+            let pts = frames_to_time(state.sample_offset, rate);
+            let end_pts = frames_to_time(state.sample_offset + n_frames, rate);
+            {
+                let buffer = buffer.get_mut().unwrap();
+                buffer.set_pts(pts);
+                buffer.set_duration(end_pts - pts);
+                buffer.set_offset(state.sample_offset);
+                buffer.set_offset_end(state.sample_offset + n_frames);
+
+                let mut map = buffer.map_writable().unwrap();
+                let samples = map.as_mut_slice_of::<i16>().unwrap();
+                let settings = Settings {
+                    channels: info.channels(),
+                    ..settings
+                };
+                state.wave.fill(&settings, samples);
+            }
+            state.sample_offset += n_frames;
+            drop(state);
+
+            // In live mode a buffer is only ready once its last sample would have been captured
+            if settings.is_live {
+                let obj = self.obj();
+                let (Some(clock), Some(base_time)) = (obj.clock(), obj.base_time()) else {
+                    return Ok(CreateSuccess::NewBuffer(buffer));
+                };
+
+                let clock_id = clock.new_single_shot_id(base_time + end_pts);
+                {
+                    let mut clock_wait = self.clock_wait.lock().unwrap();
+                    if clock_wait.flushing {
+                        gst::debug!(CAT, imp: self, "Flushing");
+                        return Err(gst::FlowError::Flushing);
+                    }
+                    clock_wait.clock_id = Some(clock_id.clone());
+                }
+
+                gst::log!(CAT, imp: self, "Waiting until {}", end_pts);
+                let (res, _jitter) = clock_id.wait();
+                self.clock_wait.lock().unwrap().clock_id = None;
+
+                if res == Err(gst::ClockError::Unscheduled) {
+                    gst::debug!(CAT, imp: self, "Flushing");
+                    return Err(gst::FlowError::Flushing);
+                }
+            }
+
+            gst::log!(CAT, imp: self, "Produced buffer {:?}", buffer);
+
+            Ok(CreateSuccess::NewBuffer(buffer))
+        }
+    }
+}
+
+glib::wrapper! {
+    pub struct PsychedelicSrc(ObjectSubclass<imp::PsychedelicSrc>) @extends gst_base::PushSrc, gst_base::BaseSrc, gst::Element, gst::Object;
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "psychedelicsrc",
+        gst::Rank::None,
+        PsychedelicSrc::static_type(),
+    )
+}
@@ -0,0 +1,700 @@
+use gstreamer as gst;
+
+use gst::prelude::*;
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRsScopeMode")]
+pub enum ScopeMode {
+    #[enum_value(name = "Waveform, one band per channel", nick = "waveform")]
+    Waveform = 0,
+    #[enum_value(name = "Lissajous (left on x, right on y)", nick = "lissajous")]
+    Lissajous = 1,
+    #[enum_value(name = "Scrolling spectrogram", nick = "spectrogram")]
+    Spectrogram = 2,
+}
+
+mod imp {
+    use std::collections::VecDeque;
+    use std::sync::{LazyLock, Mutex};
+
+    use gstreamer as gst;
+    use gstreamer_audio as gst_audio;
+    use gstreamer_video as gst_video;
+
+    use byte_slice_cast::*;
+    use epaint::{pos2, Color32, Shape, Stroke};
+    use gst::prelude::*;
+    use gst::subclass::prelude::*;
+
+    use super::super::canvas::Canvas;
+    use super::ScopeMode;
+
+    static CAT: LazyLock<gst::DebugCategory> = LazyLock::new(|| {
+        gst::DebugCategory::new(
+            "rsscope",
+            gst::DebugColorFlags::empty(),
+            Some("Rust audio visualiser"),
+        )
+    });
+
+    const DEFAULT_MODE: ScopeMode = ScopeMode::Waveform;
+    const DEFAULT_WIDTH: u32 = 640;
+    const DEFAULT_HEIGHT: u32 = 480;
+    const DEFAULT_FPS: i32 = 30;
+    const DEFAULT_FOREGROUND: u32 = 0xff00ff00;
+    const DEFAULT_BACKGROUND: u32 = 0xff000000;
+    const DEFAULT_LINE_WIDTH: f32 = 1.5;
+
+    /// Samples per spectrogram column, the column shows FFT_SIZE / 2 bins
+    const FFT_SIZE: usize = 1024;
+    /// Bins below this level are drawn in the background colour
+    const SPECTROGRAM_FLOOR_DB: f32 = -90.0;
+
+    #[derive(Debug, Clone, Copy)]
+    struct Settings {
+        mode: ScopeMode,
+        width: u32,
+        height: u32,
+        framerate: gst::Fraction,
+        /// ARGB, like the `color` property of `textoverlay`
+        foreground: u32,
+        background: u32,
+        line_width: f32,
+    }
+
+    impl Default for Settings {
+        fn default() -> Self {
+            Settings {
+                mode: DEFAULT_MODE,
+                width: DEFAULT_WIDTH,
+                height: DEFAULT_HEIGHT,
+                framerate: gst::Fraction::new(DEFAULT_FPS, 1),
+                foreground: DEFAULT_FOREGROUND,
+                background: DEFAULT_BACKGROUND,
+                line_width: DEFAULT_LINE_WIDTH,
+            }
+        }
+    }
+
+    struct State {
+        audio_info: gst_audio::AudioInfo,
+        video_info: gst_video::VideoInfo,
+        /// Interleaved samples not drawn yet
+        pending: Vec<f32>,
+        /// Timestamp of the first sample after the last discontinuity
+        base_pts: gst::ClockTime,
+        /// Video frames produced since `base_pts`
+        frame: u64,
+        /// Audio frames drawn since `base_pts`
+        consumed: u64,
+        /// Last FFT_SIZE mono samples, for the spectrogram
+        history: VecDeque<f32>,
+        /// One column of bin levels (0..=1, top row first) per output pixel column
+        columns: VecDeque<Vec<f32>>,
+    }
+
+    impl State {
+        fn reset(&mut self, base_pts: gst::ClockTime) {
+            self.pending.clear();
+            self.base_pts = base_pts;
+            self.frame = 0;
+            self.consumed = 0;
+        }
+
+        /// Audio frames that belong to the next video frame
+        fn frames_needed(&self) -> u64 {
+            let fps = self.video_info.fps();
+            let end = (self.frame + 1) * u64::from(self.audio_info.rate()) * fps.denom() as u64
+                / fps.numer() as u64;
+            end - self.consumed
+        }
+
+        fn frame_pts(&self, frame: u64) -> gst::ClockTime {
+            let fps = self.video_info.fps();
+            self.base_pts
+                + gst::ClockTime::SECOND
+                    .mul_div_floor(frame * fps.denom() as u64, fps.numer() as u64)
+                    .unwrap()
+        }
+    }
+
+    pub struct Scope {
+        srcpad: gst::Pad,
+        sinkpad: gst::Pad,
+        settings: Mutex<Settings>,
+        state: Mutex<Option<State>>,
+    }
+
+    fn argb(color: u32) -> Color32 {
+        let [a, r, g, b] = color.to_be_bytes();
+        Color32::from_rgba_unmultiplied(r, g, b, a)
+    }
+
+    fn lerp(from: Color32, to: Color32, t: f32) -> Color32 {
+        let mix = |a: u8, b: u8| (f32::from(a) + (f32::from(b) - f32::from(a)) * t).round() as u8;
+        Color32::from_rgb(
+            mix(from.r(), to.r()),
+            mix(from.g(), to.g()),
+            mix(from.b(), to.b()),
+        )
+    }
+
+    /// In place iterative radix-2 FFT, `re.len()` must be a power of two
+    fn fft(re: &mut [f32], im: &mut [f32]) {
+        let n = re.len();
+        let bits = n.trailing_zeros();
+        for i in 0..n {
+            let j = i.reverse_bits() >> (usize::BITS - bits);
+            if j > i {
+                re.swap(i, j);
+                im.swap(i, j);
+            }
+        }
+
+        let mut len = 2;
+        while len <= n {
+            let angle = -2.0 * std::f32::consts::PI / len as f32;
+            for start in (0..n).step_by(len) {
+                for k in 0..len / 2 {
+                    let (sin, cos) = (angle * k as f32).sin_cos();
+                    let (a, b) = (start + k, start + k + len / 2);
+                    let t_re = re[b] * cos - im[b] * sin;
+                    let t_im = re[b] * sin + im[b] * cos;
+                    re[b] = re[a] - t_re;
+                    im[b] = im[a] - t_im;
+                    re[a] += t_re;
+                    im[a] += t_im;
+                }
+            }
+            len *= 2;
+        }
+    }
+
+    /// One trace per channel, each in its own horizontal band, at most one point per column
+    fn waveform(
+        window: &[f32],
+        channels: usize,
+        width: usize,
+        height: usize,
+        stroke: Stroke,
+    ) -> Vec<Shape> {
+        let frames = window.len() / channels;
+        // Nothing arrived for this video frame, it stays blank
+        if frames == 0 {
+            return Vec::new();
+        }
+
+        let band = height as f32 / channels as f32;
+        let points = frames.min(width);
+        (0..channels)
+            .map(|channel| {
+                let centre = band * (channel as f32 + 0.5);
+                let line = (0..points)
+                    .map(|i| {
+                        let sample = window[i * frames / points * channels + channel];
+                        pos2(
+                            i as f32 * width as f32 / points as f32,
+                            centre - sample.clamp(-1.0, 1.0) * band / 2.0,
+                        )
+                    })
+                    .collect();
+                Shape::line(line, stroke)
+            })
+            .collect()
+    }
+
+    /// Hann windowed spectrum of `samples` resampled to `rows` levels in 0..=1, highest
+    /// frequency first
+    fn spectrum_column(samples: &VecDeque<f32>, rows: usize) -> Vec<f32> {
+        let mut re = vec![0.0; FFT_SIZE];
+        let mut im = vec![0.0; FFT_SIZE];
+        let offset = FFT_SIZE - samples.len();
+        for (i, sample) in samples.iter().enumerate() {
+            let n = offset + i;
+            let window =
+                0.5 - 0.5 * (2.0 * std::f32::consts::PI * n as f32 / FFT_SIZE as f32).cos();
+            re[n] = sample * window;
+        }
+        fft(&mut re, &mut im);
+
+        // A full scale sine peaks at FFT_SIZE / 4 with the Hann window
+        let bins = FFT_SIZE / 2;
+        (0..rows)
+            .map(|row| {
+                let bin = (rows - 1 - row) * bins / rows;
+                let magnitude =
+                    (re[bin] * re[bin] + im[bin] * im[bin]).sqrt() / (FFT_SIZE as f32 / 4.0);
+                let db = 20.0 * magnitude.max(1e-9).log10();
+                (1.0 - db / SPECTROGRAM_FLOOR_DB).clamp(0.0, 1.0)
+            })
+            .collect()
+    }
+
+    impl Scope {
+        fn sink_chain(
+            &self,
+            _pad: &gst::Pad,
+            buffer: gst::Buffer,
+        ) -> Result<gst::FlowSuccess, gst::FlowError> {
+            let settings = *self.settings.lock().unwrap();
+            let mut frames = Vec::new();
+            {
+                let mut state_guard = self.state.lock().unwrap();
+                let state = state_guard.as_mut().ok_or_else(|| {
+                    gst::element_imp_error!(
+                        self,
+                        gst::CoreError::Negotiation,
+                        ["Have no state yet"]
+                    );
+                    gst::FlowError::NotNegotiated
+                })?;
+
+                if buffer.flags().contains(gst::BufferFlags::DISCONT)
+                    || (state.frame == 0 && state.pending.is_empty())
+                {
+                    state.reset(buffer.pts().unwrap_or(gst::ClockTime::ZERO));
+                }
+
+                let map = buffer.map_readable().map_err(|_| {
+                    gst::element_imp_error!(
+                        self,
+                        gst::CoreError::Failed,
+                        ["Failed to map buffer readable"]
+                    );
+                    gst::FlowError::Error
+                })?;
+                let samples = map
+                    .as_slice_of::<f32>()
+                    .map_err(|_| gst::FlowError::Error)?;
+                state.pending.extend_from_slice(samples);
+
+                let channels = state.audio_info.channels() as usize;
+                loop {
+                    let needed = state.frames_needed() as usize;
+                    if state.pending.len() < needed * channels {
+                        break;
+                    }
+                    let window: Vec<f32> = state.pending.drain(..needed * channels).collect();
+                    frames.push(self.render(&settings, state, &window)?);
+                    state.consumed += needed as u64;
+                    state.frame += 1;
+                }
+            }
+
+            for frame in frames {
+                self.srcpad.push(frame)?;
+            }
+
+            Ok(gst::FlowSuccess::Ok)
+        }
+
+        fn render(
+            &self,
+            settings: &Settings,
+            state: &mut State,
+            window: &[f32],
+        ) -> Result<gst::Buffer, gst::FlowError> {
+            let channels = state.audio_info.channels() as usize;
+            for frame in window.chunks_exact(channels) {
+                if state.history.len() == FFT_SIZE {
+                    state.history.pop_front();
+                }
+                state
+                    .history
+                    .push_back(frame.iter().sum::<f32>() / channels as f32);
+            }
+
+            let mut buffer = gst::Buffer::with_size(state.video_info.size())
+                .map_err(|_| gst::FlowError::Error)?;
+            {
+                let buffer = buffer.get_mut().unwrap();
+                let pts = state.frame_pts(state.frame);
+                buffer.set_pts(pts);
+                buffer.set_duration(state.frame_pts(state.frame + 1) - pts);
+
+                let mut frame =
+                    gst_video::VideoFrameRef::from_buffer_ref_writable(buffer, &state.video_info)
+                        .map_err(|_| {
+                        gst::element_imp_error!(
+                            self,
+                            gst::CoreError::Failed,
+                            ["Failed to map buffer writable"]
+                        );
+                        gst::FlowError::Error
+                    })?;
+                let width = frame.width() as usize;
+                let height = frame.height() as usize;
+                let stride = frame.plane_stride()[0] as usize;
+                let data = frame.plane_data_mut(0).map_err(|_| gst::FlowError::Error)?;
+                let mut canvas = Canvas::new(data, width, height, stride, [0, 1, 2]);
+
+                let foreground = argb(settings.foreground);
+                let background = argb(settings.background);
+                canvas.fill(background);
+                let stroke = Stroke::new(settings.line_width, foreground);
+
+                match settings.mode {
+                    ScopeMode::Waveform => {
+                        let shapes = waveform(window, channels, width, height, stroke);
+                        canvas.draw_shapes(shapes, None);
+                    }
+                    ScopeMode::Lissajous => {
+                        let half = width.min(height) as f32 / 2.0;
+                        let (cx, cy) = (width as f32 / 2.0, height as f32 / 2.0);
+                        let line = window
+                            .chunks_exact(channels)
+                            .map(|frame| {
+                                let left = frame[0].clamp(-1.0, 1.0);
+                                let right =
+                                    frame.get(1).copied().unwrap_or(frame[0]).clamp(-1.0, 1.0);
+                                pos2(cx + left * half, cy - right * half)
+                            })
+                            .collect();
+                        canvas.draw_shapes([Shape::line(line, stroke)], None);
+                    }
+                    ScopeMode::Spectrogram => {
+                        if state.columns.len() == width {
+                            state.columns.pop_front();
+                        }
+                        state
+                            .columns
+                            .push_back(spectrum_column(&state.history, height));
+
+                        // Newest column on the right
+                        let offset = width - state.columns.len();
+                        for (i, column) in state.columns.iter().enumerate() {
+                            for (y, level) in column.iter().enumerate() {
+                                canvas.set_pixel(
+                                    offset + i,
+                                    y,
+                                    lerp(background, foreground, *level),
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+
+            Ok(buffer)
+        }
+
+        fn sink_event(&self, pad: &gst::Pad, event: gst::Event) -> bool {
+            use gst::EventView;
+
+            match event.view() {
+                EventView::Caps(caps) => {
+                    let caps = caps.caps_owned();
+                    let Ok(audio_info) = gst_audio::AudioInfo::from_caps(&caps) else {
+                        gst::error!(CAT, imp: self, "Failed to parse input caps {}", caps);
+                        return false;
+                    };
+
+                    let settings = *self.settings.lock().unwrap();
+                    let Ok(video_info) = gst_video::VideoInfo::builder(
+                        gst_video::VideoFormat::Rgbx,
+                        settings.width,
+                        settings.height,
+                    )
+                    .fps(settings.framerate)
+                    .build() else {
+                        gst::error!(CAT, imp: self, "Invalid output size or framerate");
+                        return false;
+                    };
+                    let video_caps = video_info.to_caps().unwrap();
+
+                    gst::debug!(CAT, imp: self, "Configured for caps {} to {}", caps, video_caps);
+                    *self.state.lock().unwrap() = Some(State {
+                        audio_info,
+                        video_info,
+                        pending: Vec::new(),
+                        base_pts: gst::ClockTime::ZERO,
+                        frame: 0,
+                        consumed: 0,
+                        history: VecDeque::with_capacity(FFT_SIZE),
+                        columns: VecDeque::new(),
+                    });
+
+                    self.srcpad.push_event(gst::event::Caps::new(&video_caps))
+                }
+                EventView::FlushStop(..) => {
+                    if let Some(state) = self.state.lock().unwrap().as_mut() {
+                        state.reset(gst::ClockTime::ZERO);
+                    }
+                    gst::Pad::event_default(pad, Some(&*self.obj()), event)
+                }
+                _ => gst::Pad::event_default(pad, Some(&*self.obj()), event),
+            }
+        }
+
+        fn sink_query(&self, pad: &gst::Pad, query: &mut gst::QueryRef) -> bool {
+            use gst::QueryViewMut;
+
+            match query.view_mut() {
+                // Any audio we can read, the output caps only depend on the properties
+                QueryViewMut::Caps(q) => {
+                    let caps = pad.pad_template_caps();
+                    let result = match q.filter() {
+                        Some(filter) => {
+                            filter.intersect_with_mode(&caps, gst::CapsIntersectMode::First)
+                        }
+                        None => caps,
+                    };
+                    q.set_result(&result);
+                    true
+                }
+                _ => gst::Pad::query_default(pad, Some(&*self.obj()), query),
+            }
+        }
+
+        fn src_query(&self, pad: &gst::Pad, query: &mut gst::QueryRef) -> bool {
+            use gst::QueryViewMut;
+
+            match query.view_mut() {
+                QueryViewMut::Caps(q) => {
+                    let settings = *self.settings.lock().unwrap();
+                    let caps = gst_video::VideoCapsBuilder::new()
+                        .format(gst_video::VideoFormat::Rgbx)
+                        .width(settings.width as i32)
+                        .height(settings.height as i32)
+                        .framerate(settings.framerate)
+                        .build();
+                    let result = match q.filter() {
+                        Some(filter) => {
+                            filter.intersect_with_mode(&caps, gst::CapsIntersectMode::First)
+                        }
+                        None => caps,
+                    };
+                    q.set_result(&result);
+                    true
+                }
+                _ => gst::Pad::query_default(pad, Some(&*self.obj()), query),
+            }
+        }
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for Scope {
+        const NAME: &'static str = "GstRsScope";
+        type Type = super::Scope;
+        type ParentType = gst::Element;
+
+        fn with_class(klass: &Self::Class) -> Self {
+            let templ = klass.pad_template("sink").unwrap();
+            let sinkpad = gst::Pad::builder_from_template(&templ)
+                .chain_function(|pad, parent, buffer| {
+                    Scope::catch_panic_pad_function(
+                        parent,
+                        || Err(gst::FlowError::Error),
+                        |scope| scope.sink_chain(pad, buffer),
+                    )
+                })
+                .event_function(|pad, parent, event| {
+                    Scope::catch_panic_pad_function(
+                        parent,
+                        || false,
+                        |scope| scope.sink_event(pad, event),
+                    )
+                })
+                .query_function(|pad, parent, query| {
+                    Scope::catch_panic_pad_function(
+                        parent,
+                        || false,
+                        |scope| scope.sink_query(pad, query),
+                    )
+                })
+                .build();
+
+            let templ = klass.pad_template("src").unwrap();
+            let srcpad = gst::Pad::builder_from_template(&templ)
+                .query_function(|pad, parent, query| {
+                    Scope::catch_panic_pad_function(
+                        parent,
+                        || false,
+                        |scope| scope.src_query(pad, query),
+                    )
+                })
+                .build();
+
+            Scope {
+                srcpad,
+                sinkpad,
+                settings: Mutex::new(Settings::default()),
+                state: Mutex::new(None),
+            }
+        }
+    }
+
+    impl ObjectImpl for Scope {
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: LazyLock<Vec<glib::ParamSpec>> = LazyLock::new(|| {
+                vec![
+                    glib::ParamSpecEnum::builder_with_default("mode", DEFAULT_MODE)
+                        .nick("Mode")
+                        .blurb("What is drawn")
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecUInt::builder("width")
+                        .nick("Width")
+                        .blurb("Output width in pixels")
+                        .minimum(16)
+                        .maximum(4096)
+                        .default_value(DEFAULT_WIDTH)
+                        .mutable_ready()
+                        .build(),
+                    glib::ParamSpecUInt::builder("height")
+                        .nick("Height")
+                        .blurb("Output height in pixels")
+                        .minimum(16)
+                        .maximum(4096)
+                        .default_value(DEFAULT_HEIGHT)
+                        .mutable_ready()
+                        .build(),
+                    gst::ParamSpecFraction::builder("framerate")
+                        .nick("Framerate")
+                        .blurb("Output framerate")
+                        .minimum(gst::Fraction::new(1, 1))
+                        .maximum(gst::Fraction::new(120, 1))
+                        .default_value(gst::Fraction::new(DEFAULT_FPS, 1))
+                        .mutable_ready()
+                        .build(),
+                    glib::ParamSpecUInt::builder("foreground")
+                        .nick("Foreground")
+                        .blurb("Colour of the trace, big-endian ARGB")
+                        .default_value(DEFAULT_FOREGROUND)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecUInt::builder("background")
+                        .nick("Background")
+                        .blurb("Background colour, big-endian ARGB")
+                        .default_value(DEFAULT_BACKGROUND)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecFloat::builder("line-width")
+                        .nick("Line width")
+                        .blurb("Width of the waveform and Lissajous lines in pixels")
+                        .minimum(0.5)
+                        .maximum(16.0)
+                        .default_value(DEFAULT_LINE_WIDTH)
+                        .mutable_playing()
+                        .build(),
+                ]
+            });
+
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+            let mut settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "mode" => {
+                    let mode = value.get().expect("type checked upstream");
+                    gst::info!(CAT, imp: self, "Changing mode to {:?}", mode);
+                    settings.mode = mode;
+                }
+                "width" => settings.width = value.get().expect("type checked upstream"),
+                "height" => settings.height = value.get().expect("type checked upstream"),
+                "framerate" => settings.framerate = value.get().expect("type checked upstream"),
+                "foreground" => settings.foreground = value.get().expect("type checked upstream"),
+                "background" => settings.background = value.get().expect("type checked upstream"),
+                "line-width" => settings.line_width = value.get().expect("type checked upstream"),
+                _ => unreachable!(),
+            }
+        }
+
+        fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            let settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "mode" => settings.mode.to_value(),
+                "width" => settings.width.to_value(),
+                "height" => settings.height.to_value(),
+                "framerate" => settings.framerate.to_value(),
+                "foreground" => settings.foreground.to_value(),
+                "background" => settings.background.to_value(),
+                "line-width" => settings.line_width.to_value(),
+                _ => unreachable!(),
+            }
+        }
+
+        fn constructed(&self) {
+            self.parent_constructed();
+
+            let obj = self.obj();
+            obj.add_pad(&self.sinkpad).unwrap();
+            obj.add_pad(&self.srcpad).unwrap();
+        }
+    }
+
+    impl GstObjectImpl for Scope {}
+
+    impl ElementImpl for Scope {
+        fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+            static ELEMENT_METADATA: LazyLock<gst::subclass::ElementMetadata> =
+                LazyLock::new(|| {
+                    gst::subclass::ElementMetadata::new(
+                        "Scope",
+                        "Visualization",
+                        "Draws audio as a waveform, Lissajous figure or spectrogram",
+                        "gstream_prac",
+                    )
+                });
+
+            Some(&*ELEMENT_METADATA)
+        }
+
+        fn pad_templates() -> &'static [gst::PadTemplate] {
+            static PAD_TEMPLATES: LazyLock<Vec<gst::PadTemplate>> = LazyLock::new(|| {
+                let src_caps = gst_video::VideoCapsBuilder::new()
+                    .format(gst_video::VideoFormat::Rgbx)
+                    .build();
+                let src_pad_template = gst::PadTemplate::new(
+                    "src",
+                    gst::PadDirection::Src,
+                    gst::PadPresence::Always,
+                    &src_caps,
+                )
+                .unwrap();
+
+                let sink_caps = gst_audio::AudioCapsBuilder::new_interleaved()
+                    .format(gst_audio::AUDIO_FORMAT_F32)
+                    .build();
+                let sink_pad_template = gst::PadTemplate::new(
+                    "sink",
+                    gst::PadDirection::Sink,
+                    gst::PadPresence::Always,
+                    &sink_caps,
+                )
+                .unwrap();
+
+                vec![src_pad_template, sink_pad_template]
+            });
+
+            PAD_TEMPLATES.as_ref()
+        }
+
+        fn change_state(
+            &self,
+            transition: gst::StateChange,
+        ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+            let ret = self.parent_change_state(transition)?;
+            if transition == gst::StateChange::PausedToReady {
+                *self.state.lock().unwrap() = None;
+            }
+
+            Ok(ret)
+        }
+    }
+}
+
+glib::wrapper! {
+    pub struct Scope(ObjectSubclass<imp::Scope>) @extends gst::Element, gst::Object;
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "rsscope",
+        gst::Rank::None,
+        Scope::static_type(),
+    )
+}
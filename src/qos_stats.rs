@@ -0,0 +1,181 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use gstreamer as gst;
+use gstreamer_base as gst_base;
+
+use gst::prelude::*;
+
+/// Aggregated QoS messages of one element
+#[derive(Debug, Default, Clone)]
+pub struct ElementQos {
+    pub messages: u64,
+    /// Latest processed/dropped counters the element reported, they are running totals
+    pub processed: u64,
+    pub dropped: u64,
+    /// Sum and maximum of the reported jitter in nanoseconds, positive is late
+    pub jitter_sum: i64,
+    pub jitter_max: i64,
+    /// Latest long term proportion, below 1.0 means upstream should speed up
+    pub proportion: f64,
+}
+
+impl ElementQos {
+    pub fn jitter_avg(&self) -> i64 {
+        if self.messages == 0 {
+            0
+        } else {
+            self.jitter_sum / self.messages as i64
+        }
+    }
+}
+
+/// Buffers that went through one pad
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PadFlow {
+    pub buffers: u64,
+    pub bytes: u64,
+}
+
+#[derive(Default)]
+struct Stats {
+    qos: BTreeMap<String, ElementQos>,
+    flow: BTreeMap<String, PadFlow>,
+    /// Pads that already have our probe
+    probed: HashSet<String>,
+}
+
+/// Collects QoS messages, sink `stats` and per pad buffer counts of a pipeline, to find the
+/// branch that falls behind. Clones share the same numbers.
+#[derive(Clone)]
+pub struct QosCollector {
+    pipeline: gst::Bin,
+    stats: Arc<Mutex<Stats>>,
+}
+
+impl QosCollector {
+    pub fn new(pipeline: &gst::Bin) -> QosCollector {
+        QosCollector {
+            pipeline: pipeline.clone(),
+            stats: Arc::new(Mutex::new(Stats::default())),
+        }
+    }
+
+    /// Counts buffers on every source pad in the pipeline. Safe to call again, e.g. after
+    /// `uridecodebin` added its pads: pads that are already counted are skipped.
+    pub fn install_probes(&self) {
+        for element in self.pipeline.iterate_recurse().into_iter().flatten() {
+            for pad in element.src_pads() {
+                // Names repeat in different bins, e.g. a queue0 in each decodebin
+                let key = pad.path_string().to_string();
+                if !self.stats.lock().unwrap().probed.insert(key.clone()) {
+                    continue;
+                }
+
+                let stats = self.stats.clone();
+                pad.add_probe(
+                    gst::PadProbeType::BUFFER | gst::PadProbeType::BUFFER_LIST,
+                    move |_pad, info| {
+                        let (buffers, bytes) = match &info.data {
+                            Some(gst::PadProbeData::Buffer(buffer)) => (1, buffer.size()),
+                            Some(gst::PadProbeData::BufferList(list)) => {
+                                (list.len(), list.calculate_size())
+                            }
+                            _ => return gst::PadProbeReturn::Ok,
+                        };
+                        let mut stats = stats.lock().unwrap();
+                        let flow = stats.flow.entry(key.clone()).or_default();
+                        flow.buffers += buffers as u64;
+                        flow.bytes += bytes as u64;
+                        gst::PadProbeReturn::Ok
+                    },
+                );
+            }
+        }
+    }
+
+    /// Records `Qos` messages, returns false for every other message
+    pub fn handle_message(&self, message: &gst::Message) -> bool {
+        let gst::MessageView::Qos(qos) = message.view() else {
+            return false;
+        };
+        let Some(src) = message.src() else {
+            return true;
+        };
+
+        let (jitter, proportion, _quality) = qos.values();
+        let (processed, dropped) = qos.stats();
+
+        let mut stats = self.stats.lock().unwrap();
+        let entry = stats.qos.entry(src.path_string().to_string()).or_default();
+        entry.messages += 1;
+        // -1 when the element does not count
+        entry.processed = u64::try_from(processed.value()).unwrap_or(entry.processed);
+        entry.dropped = u64::try_from(dropped.value()).unwrap_or(entry.dropped);
+        entry.jitter_sum += jitter;
+        entry.jitter_max = entry.jitter_max.max(jitter);
+        entry.proportion = proportion;
+        true
+    }
+
+    pub fn qos(&self) -> BTreeMap<String, ElementQos> {
+        self.stats.lock().unwrap().qos.clone()
+    }
+
+    pub fn flow(&self) -> BTreeMap<String, PadFlow> {
+        self.stats.lock().unwrap().flow.clone()
+    }
+
+    /// The `stats` property of every sink, by element path: rendered, dropped and average rate
+    pub fn sink_stats(&self) -> Vec<(String, u64, u64, f64)> {
+        self.pipeline
+            .iterate_recurse()
+            .into_iter()
+            .flatten()
+            .filter(|element| element.is::<gst_base::BaseSink>())
+            .map(|sink| {
+                let stats = sink.property::<gst::Structure>("stats");
+                (
+                    sink.path_string().to_string(),
+                    stats.get::<u64>("rendered").unwrap_or_default(),
+                    stats.get::<u64>("dropped").unwrap_or_default(),
+                    stats.get::<f64>("average-rate").unwrap_or_default(),
+                )
+            })
+            .collect()
+    }
+
+    pub fn print_table(&self) {
+        println!(
+            "\n{:<48} {:>6} {:>10} {:>8} {:>12} {:>12} {:>6}",
+            "QoS", "msgs", "processed", "dropped", "avg jitter", "max jitter", "prop"
+        );
+        for (element, qos) in self.qos() {
+            println!(
+                "{:<48} {:>6} {:>10} {:>8} {:>10}us {:>10}us {:>6.3}",
+                element,
+                qos.messages,
+                qos.processed,
+                qos.dropped,
+                qos.jitter_avg() / 1000,
+                qos.jitter_max / 1000,
+                qos.proportion
+            );
+        }
+
+        println!(
+            "\n{:<48} {:>10} {:>8} {:>12}",
+            "Sink", "rendered", "dropped", "avg rate"
+        );
+        for (sink, rendered, dropped, rate) in self.sink_stats() {
+            println!("{sink:<48} {rendered:>10} {dropped:>8} {rate:>12.3}");
+        }
+
+        println!("\n{:<48} {:>10} {:>14}", "Pad", "buffers", "bytes");
+        for (pad, flow) in self.flow() {
+            println!("{pad:<48} {:>10} {:>14}", flow.buffers, flow.bytes);
+        }
+    }
+}
@@ -0,0 +1,295 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use gstreamer as gst;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubtitleFormat {
+    Srt,
+    WebVtt,
+    Ssa,
+}
+
+impl SubtitleFormat {
+    pub fn from_extension(ext: &str) -> Option<SubtitleFormat> {
+        match ext.to_ascii_lowercase().as_str() {
+            "srt" => Some(SubtitleFormat::Srt),
+            "vtt" | "webvtt" => Some(SubtitleFormat::WebVtt),
+            "ssa" | "ass" => Some(SubtitleFormat::Ssa),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            SubtitleFormat::Srt => "srt",
+            SubtitleFormat::WebVtt => "vtt",
+            SubtitleFormat::Ssa => "ass",
+        }
+    }
+}
+
+/// A subtitle file found next to a media file, e.g. `movie.en.srt` for `movie.mkv`
+#[derive(Debug, Clone)]
+pub struct Sidecar {
+    pub path: PathBuf,
+    pub format: SubtitleFormat,
+    /// Language suffix taken from the file name, if any
+    pub language: Option<String>,
+}
+
+impl Sidecar {
+    pub fn uri(&self) -> Result<glib::GString, glib::Error> {
+        let path = fs::canonicalize(&self.path).unwrap_or_else(|_| self.path.clone());
+        glib::filename_to_uri(path, None)
+    }
+}
+
+fn is_language_code(s: &str) -> bool {
+    (s.len() == 2 || s.len() == 3) && s.chars().all(|c| c.is_ascii_alphabetic())
+}
+
+/// Looks for subtitle files sharing the stem of `media`. Files without a language suffix
+/// come first, the rest are sorted by language.
+pub fn find_sidecars(media: &Path) -> Vec<Sidecar> {
+    let Some(stem) = media.file_stem().and_then(|s| s.to_str()) else {
+        return Vec::new();
+    };
+    let dir = match media.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut sidecars = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Some(name) = path.file_name().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        let Some(rest) = name.strip_prefix(stem) else {
+            continue;
+        };
+        // "movie.srt" -> [srt], "movie.en.forced.srt" -> [en, forced, srt]
+        let Some(rest) = rest.strip_prefix('.') else {
+            continue;
+        };
+        let parts: Vec<&str> = rest.split('.').collect();
+        let Some(format) = parts
+            .last()
+            .and_then(|ext| SubtitleFormat::from_extension(ext))
+        else {
+            continue;
+        };
+        let language = parts[..parts.len() - 1]
+            .iter()
+            .find(|part| is_language_code(part))
+            .map(|part| part.to_ascii_lowercase());
+
+        sidecars.push(Sidecar {
+            path,
+            format,
+            language,
+        });
+    }
+
+    sidecars.sort_by(|a, b| {
+        a.language
+            .is_some()
+            .cmp(&b.language.is_some())
+            .then_with(|| a.language.cmp(&b.language))
+            .then_with(|| a.path.cmp(&b.path))
+    });
+    sidecars
+}
+
+/// Formats a timestamp as `HH:MM:SS,mmm` (SRT) or `HH:MM:SS.mmm` (WebVTT)
+pub fn format_timestamp(time: gst::ClockTime, format: SubtitleFormat) -> String {
+    let ms = time.mseconds();
+    let (h, m, s, ms) = (ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000);
+    match format {
+        SubtitleFormat::Srt => format!("{h:02}:{m:02}:{s:02},{ms:03}"),
+        SubtitleFormat::WebVtt => format!("{h:02}:{m:02}:{s:02}.{ms:03}"),
+        // SSA uses centiseconds and a single digit hour
+        SubtitleFormat::Ssa => format!("{h}:{m:02}:{s:02}.{:02}", ms / 10),
+    }
+}
+
+/// Removes pango/HTML style markup from a cue, keeping the plain text
+pub fn strip_markup(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut in_tag = false;
+    for c in text.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => out.push(c),
+            _ => (),
+        }
+    }
+    out.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// Writes cues to an SRT or WebVTT file
+pub struct SubtitleWriter {
+    out: BufWriter<File>,
+    format: SubtitleFormat,
+    /// Number of cues written so far
+    count: u32,
+}
+
+impl SubtitleWriter {
+    pub fn create(path: &Path, format: SubtitleFormat) -> io::Result<SubtitleWriter> {
+        if format == SubtitleFormat::Ssa {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "writing SSA/ASS subtitles is not supported",
+            ));
+        }
+
+        let mut out = BufWriter::new(File::create(path)?);
+        if format == SubtitleFormat::WebVtt {
+            writeln!(out, "WEBVTT")?;
+            writeln!(out)?;
+        }
+
+        Ok(SubtitleWriter {
+            out,
+            format,
+            count: 0,
+        })
+    }
+
+    pub fn write_cue(
+        &mut self,
+        start: gst::ClockTime,
+        end: gst::ClockTime,
+        text: &str,
+    ) -> io::Result<()> {
+        let text = text.trim_end_matches(['\n', '\r', '\0']);
+        if text.trim().is_empty() {
+            return Ok(());
+        }
+
+        self.count += 1;
+        if self.format == SubtitleFormat::Srt {
+            writeln!(self.out, "{}", self.count)?;
+        }
+        writeln!(
+            self.out,
+            "{} --> {}",
+            format_timestamp(start, self.format),
+            format_timestamp(end, self.format)
+        )?;
+        writeln!(self.out, "{text}")?;
+        writeln!(self.out)?;
+        self.out.flush()
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    #[test]
+    fn timestamps() {
+        let time = gst::ClockTime::from_mseconds(3_723_004);
+        assert_eq!(format_timestamp(time, SubtitleFormat::Srt), "01:02:03,004");
+        assert_eq!(
+            format_timestamp(time, SubtitleFormat::WebVtt),
+            "01:02:03.004"
+        );
+        assert_eq!(format_timestamp(time, SubtitleFormat::Ssa), "1:02:03.00");
+        assert_eq!(
+            format_timestamp(gst::ClockTime::ZERO, SubtitleFormat::Srt),
+            "00:00:00,000"
+        );
+        // Below a millisecond is cut off, not rounded
+        assert_eq!(
+            format_timestamp(gst::ClockTime::from_nseconds(999_999), SubtitleFormat::Srt),
+            "00:00:00,000"
+        );
+        assert_eq!(
+            format_timestamp(
+                gst::ClockTime::from_mseconds(59_999),
+                SubtitleFormat::WebVtt
+            ),
+            "00:00:59.999"
+        );
+        // Hours keep counting past two digits
+        assert_eq!(
+            format_timestamp(
+                gst::ClockTime::from_seconds(100 * 3600 + 1),
+                SubtitleFormat::Srt
+            ),
+            "100:00:01,000"
+        );
+    }
+
+    #[test]
+    fn markup() {
+        assert_eq!(strip_markup("<i>Hello</i> <b>world</b>"), "Hello world");
+        assert_eq!(
+            strip_markup("<span foreground=\"red\">a &lt;b&gt; &amp; c</span>"),
+            "a <b> & c"
+        );
+        assert_eq!(
+            strip_markup("no markup\nsecond line"),
+            "no markup\nsecond line"
+        );
+        assert_eq!(strip_markup("2 > 1"), "2 > 1");
+    }
+
+    #[test]
+    fn sidecars() {
+        let dir = env::temp_dir().join(format!("gstream_prac_sidecars_{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for name in [
+            "movie.mkv",
+            "movie.srt",
+            "movie.fr.srt",
+            "movie.EN.VTT",
+            "movie.de.forced.ass",
+            "movie.director.srt",
+            "movie.nfo",
+            "movie2.srt",
+            "other.en.srt",
+        ] {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+
+        let found: Vec<(String, SubtitleFormat, Option<String>)> =
+            find_sidecars(&dir.join("movie.mkv"))
+                .into_iter()
+                .map(|sidecar| {
+                    let name = sidecar.path.file_name().unwrap().to_string_lossy().into();
+                    (name, sidecar.format, sidecar.language)
+                })
+                .collect();
+        let expected = [
+            ("movie.director.srt", SubtitleFormat::Srt, None),
+            ("movie.srt", SubtitleFormat::Srt, None),
+            ("movie.de.forced.ass", SubtitleFormat::Ssa, Some("de")),
+            ("movie.EN.VTT", SubtitleFormat::WebVtt, Some("en")),
+            ("movie.fr.srt", SubtitleFormat::Srt, Some("fr")),
+        ]
+        .map(|(name, format, language)| (name.to_string(), format, language.map(String::from)));
+        assert_eq!(found, expected);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
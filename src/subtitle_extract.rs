@@ -0,0 +1,309 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+};
+
+extern crate gstreamer as gst;
+extern crate gstreamer_app as gst_app;
+
+use anyhow::Error;
+use gst::prelude::*;
+
+use crate::subtitle::{self, SubtitleFormat, SubtitleWriter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CueKind {
+    /// `text/x-raw`, plain utf8 or pango markup
+    Text,
+    /// `application/x-ssa` / `application/x-ass` dialogue lines
+    Ssa,
+}
+
+impl CueKind {
+    fn from_caps(caps: &gst::CapsRef) -> Option<CueKind> {
+        match caps.structure(0)?.name().as_str() {
+            "text/x-raw" => Some(CueKind::Text),
+            "application/x-ssa" | "application/x-ass" => Some(CueKind::Ssa),
+            _ => None,
+        }
+    }
+}
+
+/// Turns a Matroska SSA/ASS block ("ReadOrder, Layer, Style, Name, MarginL, MarginR,
+/// MarginV, Effect, Text") into plain text
+fn ssa_dialogue_text(data: &str) -> String {
+    let text = data.splitn(9, ',').nth(8).unwrap_or(data);
+
+    let mut out = String::with_capacity(text.len());
+    let mut in_override = false;
+    for c in text.chars() {
+        match c {
+            '{' => in_override = true,
+            '}' if in_override => in_override = false,
+            _ if !in_override => out.push(c),
+            _ => (),
+        }
+    }
+    out.replace("\\N", "\n").replace("\\n", "\n").replace("\\h", " ")
+}
+
+/// Writes the cues of one embedded subtitle stream
+struct TrackExtractor {
+    index: u32,
+    kind: CueKind,
+    /// Output file name without the language and extension
+    base: PathBuf,
+    format: SubtitleFormat,
+    writer: Option<SubtitleWriter>,
+    /// Cue without a duration, closed when the next one starts
+    pending: Option<(gst::ClockTime, String)>,
+}
+
+impl TrackExtractor {
+    fn open(&mut self, appsink: &gst_app::AppSink) -> Option<&mut SubtitleWriter> {
+        if self.writer.is_none() {
+            let language = appsink
+                .static_pad("sink")
+                .and_then(|pad| pad.sticky_event::<gst::event::Tag>(0))
+                .and_then(|event| {
+                    event
+                        .tag()
+                        .get::<gst::tags::LanguageCode>()
+                        .map(|l| l.get().to_string())
+                })
+                .unwrap_or_else(|| "und".to_string());
+
+            let mut path = self.base.clone().into_os_string();
+            path.push(format!(".{}.{language}.{}", self.index, self.format.extension()));
+            let path = PathBuf::from(path);
+
+            match SubtitleWriter::create(&path, self.format) {
+                Ok(writer) => {
+                    println!("Writing subtitle track {} to {}", self.index, path.display());
+                    self.writer = Some(writer);
+                }
+                Err(err) => eprintln!("Failed to create {}: {err}", path.display()),
+            }
+        }
+
+        self.writer.as_mut()
+    }
+
+    fn push_sample(&mut self, appsink: &gst_app::AppSink, sample: &gst::Sample) {
+        let Some(buffer) = sample.buffer() else {
+            return;
+        };
+        let Some(pts) = buffer.pts() else {
+            return;
+        };
+
+        // Convert the buffer timestamp to stream time so the cues start at the media start
+        let start = sample
+            .segment()
+            .and_then(|segment| segment.downcast_ref::<gst::ClockTime>())
+            .and_then(|segment| segment.to_stream_time(pts))
+            .unwrap_or(pts);
+
+        let Ok(map) = buffer.map_readable() else {
+            return;
+        };
+        let data = String::from_utf8_lossy(&map);
+        let text = match self.kind {
+            CueKind::Text => subtitle::strip_markup(&data),
+            CueKind::Ssa => ssa_dialogue_text(&data),
+        };
+
+        self.flush_pending(appsink, Some(start));
+        match buffer.duration() {
+            Some(duration) => self.write_cue(appsink, start, start + duration, &text),
+            None => self.pending = Some((start, text)),
+        }
+    }
+
+    fn flush_pending(&mut self, appsink: &gst_app::AppSink, next: Option<gst::ClockTime>) {
+        if let Some((start, text)) = self.pending.take() {
+            let end = next.unwrap_or(start + 2 * gst::ClockTime::SECOND);
+            self.write_cue(appsink, start, end, &text);
+        }
+    }
+
+    fn write_cue(
+        &mut self,
+        appsink: &gst_app::AppSink,
+        start: gst::ClockTime,
+        end: gst::ClockTime,
+        text: &str,
+    ) {
+        let index = self.index;
+        if let Some(writer) = self.open(appsink) {
+            if let Err(err) = writer.write_cue(start, end, text) {
+                eprintln!("Failed to write cue of subtitle track {index}: {err}");
+            }
+        }
+    }
+}
+
+fn make_subtitle_branch(
+    pipeline: &gst::Pipeline,
+    extractor: TrackExtractor,
+) -> Result<gst::Element, Error> {
+    let queue = gst::ElementFactory::make("queue").build()?;
+    let appsink = gst_app::AppSink::builder().sync(false).build();
+
+    pipeline.add_many([&queue, appsink.upcast_ref()])?;
+    queue.link(&appsink)?;
+
+    let extractor = Arc::new(Mutex::new(extractor));
+    let extractor_clone = extractor.clone();
+    appsink.set_callbacks(
+        gst_app::AppSinkCallbacks::builder()
+            .new_sample(move |appsink| {
+                let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+                extractor.lock().unwrap().push_sample(appsink, &sample);
+                Ok(gst::FlowSuccess::Ok)
+            })
+            .eos(move |appsink| {
+                let mut extractor = extractor_clone.lock().unwrap();
+                extractor.flush_pending(appsink, None);
+                if let Some(writer) = &extractor.writer {
+                    println!(
+                        "Subtitle track {} done, {} cues written",
+                        extractor.index,
+                        writer.count()
+                    );
+                }
+            })
+            .build(),
+    );
+
+    queue.sync_state_with_parent()?;
+    appsink.sync_state_with_parent()?;
+
+    Ok(queue)
+}
+
+fn make_discard_branch(pipeline: &gst::Pipeline) -> Result<gst::Element, Error> {
+    let fakesink = gst::ElementFactory::make("fakesink")
+        .property("sync", false)
+        .property("async", false)
+        .build()?;
+
+    pipeline.add(&fakesink)?;
+    fakesink.sync_state_with_parent()?;
+
+    Ok(fakesink)
+}
+
+/// Demuxes every text subtitle stream of `input` without decoding anything and writes each
+/// one next to the input as `<name>.<track>.<language>.<srt|vtt>`
+pub fn extract_subtitles(input: &Path, format: SubtitleFormat) -> Result<(), Error> {
+    gst::init()?;
+
+    let base = input.with_extension("");
+
+    // Going through a URI keeps paths that aren't valid UTF-8 working
+    let uri = glib::filename_to_uri(input.canonicalize()?, None)?;
+    let source = gst::Element::make_from_uri(gst::URIType::Src, &uri, Some("source"))?;
+    // parsebin only demuxes and parses, no decoders are plugged
+    let parsebin = gst::ElementFactory::make("parsebin")
+        .name("parsebin")
+        .build()?;
+
+    let pipeline = gst::Pipeline::with_name("subtitle-extract-pipeline");
+    pipeline.add_many([&source, &parsebin])?;
+    source.link(&parsebin)?;
+
+    let pipeline_weak = pipeline.downgrade();
+    let next_index = AtomicU32::new(0);
+    parsebin.connect_pad_added(move |_, src_pad| {
+        let Some(pipeline) = pipeline_weak.upgrade() else {
+            return;
+        };
+
+        let caps = src_pad
+            .current_caps()
+            .unwrap_or_else(|| src_pad.query_caps(None));
+
+        let branch = match CueKind::from_caps(&caps) {
+            Some(kind) => {
+                let extractor = TrackExtractor {
+                    index: next_index.fetch_add(1, Ordering::SeqCst),
+                    kind,
+                    base: base.clone(),
+                    format,
+                    writer: None,
+                    pending: None,
+                };
+                make_subtitle_branch(&pipeline, extractor)
+            }
+            None => {
+                // Audio, video and bitmap subtitles are thrown away
+                println!("Ignoring stream with caps {caps}");
+                make_discard_branch(&pipeline)
+            }
+        };
+
+        match branch {
+            Ok(branch) => {
+                let sink_pad = branch.static_pad("sink").unwrap();
+                if let Err(err) = src_pad.link(&sink_pad) {
+                    eprintln!("Failed to link {}: {err:?}", src_pad.name());
+                }
+            }
+            Err(err) => eprintln!("Failed to create branch for {}: {err}", src_pad.name()),
+        }
+    });
+
+    pipeline.set_state(gst::State::Playing)?;
+
+    let bus = pipeline.bus().unwrap();
+    for msg in bus.iter_timed(gst::ClockTime::NONE) {
+        use gst::MessageView;
+
+        match msg.view() {
+            MessageView::Error(err) => {
+                eprintln!(
+                    "Error received from element {:?}: {}",
+                    err.src().map(|s| s.path_string()),
+                    err.error()
+                );
+                eprintln!("Debugging information: {:?}", err.debug());
+                break;
+            }
+            MessageView::Eos(..) => {
+                println!("Finished extracting subtitles");
+                break;
+            }
+            _ => (),
+        }
+    }
+
+    pipeline.set_state(gst::State::Null)?;
+
+    Ok(())
+}
+
+pub fn tutorial_main() {
+    let args: Vec<_> = env::args().collect();
+    if args.len() < 2 {
+        eprintln!("Usage: {} <file.mkv|file.mp4> [srt|vtt]", args[0]);
+        return;
+    }
+
+    let format = match args.get(2).map(String::as_str) {
+        None | Some("srt") => SubtitleFormat::Srt,
+        Some("vtt") => SubtitleFormat::WebVtt,
+        Some(other) => {
+            eprintln!("Unsupported output format {other}");
+            return;
+        }
+    };
+
+    if let Err(err) = extract_subtitles(Path::new(&args[1]), format) {
+        eprintln!("Failed to extract subtitles: {err}");
+    }
+}
@@ -0,0 +1,211 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use gstreamer as gst;
+
+use anyhow::Error;
+use gst::prelude::*;
+
+/// A branch hanging off the tee
+struct Branch {
+    element: gst::Element,
+    tee_pad: gst::Pad,
+}
+
+/// Attaches and detaches branches of a `tee` while the pipeline is running.
+///
+/// New branches are brought to the state of the pipeline before they are linked. Removed
+/// branches are unlinked from an idle probe, so the tee is never blocked in the middle of a
+/// buffer and the other branches keep flowing. They then get an EOS to finish what they are
+/// doing (a muxer writes its index, for example) and are only shut down and taken out of the
+/// pipeline once every sink in them received it. Clones share the same branches.
+#[derive(Clone)]
+pub struct TeeBranches {
+    pipeline: gst::Bin,
+    tee: gst::Element,
+    branches: Arc<Mutex<BTreeMap<String, Branch>>>,
+}
+
+impl TeeBranches {
+    pub fn new(pipeline: &gst::Bin, tee: &gst::Element) -> TeeBranches {
+        // Without this the tee fails with not-linked while its last branch is removed
+        tee.set_property("allow-not-linked", true);
+
+        TeeBranches {
+            pipeline: pipeline.clone(),
+            tee: tee.clone(),
+            branches: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.branches.lock().unwrap().contains_key(name)
+    }
+
+    /// Adds a branch from a launch description, e.g.
+    /// `queue ! audioconvert ! wavenc ! filesink location=out.wav`
+    pub fn add_from_description(&self, name: &str, description: &str) -> Result<(), Error> {
+        let bin = gst::parse_bin_from_description(description, true)?;
+        self.add(name, bin.upcast_ref())
+    }
+
+    /// Adds `element`, which must have a `sink` pad, and links it to a new pad of the tee
+    pub fn add(&self, name: &str, element: &gst::Element) -> Result<(), Error> {
+        if self.contains(name) {
+            return Err(glib::bool_error!("Branch {name} already exists").into());
+        }
+        let sink_pad = element
+            .static_pad("sink")
+            .ok_or_else(|| glib::bool_error!("Branch {name} has no sink pad"))?;
+
+        self.pipeline.add(element)?;
+        let tee_pad = match self.tee.request_pad_simple("src_%u") {
+            Some(tee_pad) => tee_pad,
+            None => {
+                let _ = self.pipeline.remove(element);
+                return Err(glib::bool_error!("Failed to request a pad from the tee").into());
+            }
+        };
+
+        // Data may arrive as soon as the pads are linked, the branch has to be ready for it
+        let linked = element
+            .sync_state_with_parent()
+            .map_err(Error::from)
+            .and_then(|_| tee_pad.link(&sink_pad).map_err(Error::from));
+        if let Err(err) = linked {
+            let _ = element.set_state(gst::State::Null);
+            let _ = self.pipeline.remove(element);
+            self.tee.release_request_pad(&tee_pad);
+            return Err(err);
+        }
+
+        println!("Added branch {name} on {}", tee_pad.name());
+        self.branches.lock().unwrap().insert(
+            name.to_string(),
+            Branch {
+                element: element.clone(),
+                tee_pad,
+            },
+        );
+        Ok(())
+    }
+
+    /// Detaches a branch. Returns right away, the branch is drained and removed in the
+    /// background.
+    pub fn remove(&self, name: &str) -> Result<(), Error> {
+        self.detach(name, None)
+    }
+
+    /// Detaches a branch and waits until it is drained and removed, e.g. to finish a
+    /// recording before the pipeline is stopped. Only call it while the pipeline is playing.
+    pub fn remove_and_wait(&self, name: &str, timeout: Duration) -> Result<(), Error> {
+        let (done, removed) = mpsc::channel();
+        self.detach(name, Some(done))?;
+        removed
+            .recv_timeout(timeout)
+            .map_err(|_| glib::bool_error!("Branch {name} was not drained in time").into())
+    }
+
+    fn detach(&self, name: &str, done: Option<mpsc::Sender<()>>) -> Result<(), Error> {
+        let Branch { element, tee_pad } = self
+            .branches
+            .lock()
+            .unwrap()
+            .remove(name)
+            .ok_or_else(|| glib::bool_error!("No branch named {name}"))?;
+
+        let teardown = Teardown {
+            name: name.to_string(),
+            pipeline: self.pipeline.clone(),
+            tee: self.tee.clone(),
+            tee_pad: tee_pad.clone(),
+            element: element.clone(),
+            done,
+        };
+
+        // Tear down once the EOS went through the whole branch
+        let sink_pads = sink_pads(&element);
+        let has_sinks = !sink_pads.is_empty();
+        let pending = Arc::new(AtomicUsize::new(sink_pads.len()));
+        for pad in sink_pads {
+            let pending = pending.clone();
+            let teardown = teardown.clone();
+            pad.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |_pad, info| {
+                let Some(gst::PadProbeData::Event(ref event)) = info.data else {
+                    return gst::PadProbeReturn::Ok;
+                };
+                if event.type_() != gst::EventType::Eos {
+                    return gst::PadProbeReturn::Ok;
+                }
+                if pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+                    teardown.run();
+                }
+                // The sink must not post EOS, the rest of the pipeline is still playing
+                gst::PadProbeReturn::Drop
+            });
+        }
+
+        // Runs from the streaming thread of the tee between two buffers, or right away when
+        // nothing flows
+        tee_pad.add_probe(gst::PadProbeType::IDLE, move |tee_pad, _info| {
+            let mut drained = !has_sinks;
+            if let Some(peer) = tee_pad.peer() {
+                let _ = tee_pad.unlink(&peer);
+                // Refused when the branch is not running, then no EOS will reach the sinks
+                drained |= !peer.send_event(gst::event::Eos::new());
+            }
+            if drained {
+                teardown.run();
+            }
+            gst::PadProbeReturn::Remove
+        });
+
+        Ok(())
+    }
+}
+
+/// Sink pads of the sinks in `element`, or of `element` itself if it is a sink
+fn sink_pads(element: &gst::Element) -> Vec<gst::Pad> {
+    let sinks = match element.downcast_ref::<gst::Bin>() {
+        Some(bin) => bin.iterate_sinks().into_iter().flatten().collect(),
+        None if element.element_flags().contains(gst::ElementFlags::SINK) => {
+            vec![element.clone()]
+        }
+        None => Vec::new(),
+    };
+    sinks.iter().flat_map(|sink| sink.sink_pads()).collect()
+}
+
+#[derive(Clone)]
+struct Teardown {
+    name: String,
+    pipeline: gst::Bin,
+    tee: gst::Element,
+    tee_pad: gst::Pad,
+    element: gst::Element,
+    /// Told once the branch is out of the pipeline
+    done: Option<mpsc::Sender<()>>,
+}
+
+impl Teardown {
+    /// Changing states from a streaming thread of the branch would deadlock, do it from
+    /// another thread
+    fn run(&self) {
+        let teardown = self.clone();
+        self.element.call_async(move |element| {
+            let _ = element.set_state(gst::State::Null);
+            let _ = teardown.pipeline.remove(element);
+            teardown.tee.release_request_pad(&teardown.tee_pad);
+            println!("Removed branch {}", teardown.name);
+            if let Some(done) = &teardown.done {
+                let _ = done.send(());
+            }
+        });
+    }
+}
@@ -0,0 +1,259 @@
+use std::{env, path::PathBuf};
+
+use gstreamer as gst;
+
+use anyhow::Error;
+use gst::prelude::*;
+
+use crate::plugin_prac;
+
+/// Everything that decides what the generated clip looks like. The same options always give
+/// the same frames: the pattern is animated by frame count, not by the clock.
+#[derive(Debug, Clone)]
+pub struct PatternOptions {
+    /// `videotestsrc` pattern nick: smpte, ball, snow, zone-plate, bar, ...
+    pub pattern: String,
+    pub width: i32,
+    pub height: i32,
+    pub framerate: gst::Fraction,
+    /// Raw video format of the output, e.g. I420, NV12, RGBA
+    pub format: String,
+    /// Number of frames, None for endless
+    pub frames: Option<u64>,
+    /// Moving ball: `wavy`, `sweep` or `hsweep`
+    pub motion: Option<String>,
+    /// Moving ball: invert colors every second
+    pub flip: bool,
+    /// Zone plate and bar: pixels per frame
+    pub horizontal_speed: Option<i32>,
+    /// Colors of ball, solid-color and bar patterns as 0xAARRGGBB
+    pub foreground: Option<u32>,
+    pub background: Option<u32>,
+    /// Burns timecode and frame number into the picture
+    pub counter: bool,
+}
+
+impl Default for PatternOptions {
+    fn default() -> Self {
+        PatternOptions {
+            pattern: "smpte".to_string(),
+            width: 1280,
+            height: 720,
+            framerate: gst::Fraction::new(30, 1),
+            format: "I420".to_string(),
+            frames: None,
+            motion: None,
+            flip: false,
+            horizontal_speed: None,
+            foreground: None,
+            background: None,
+            counter: false,
+        }
+    }
+}
+
+impl PatternOptions {
+    /// Number of frames in `seconds` at the configured framerate
+    pub fn frames_for(&self, seconds: f64) -> u64 {
+        let fps = self.framerate.numer() as f64 / self.framerate.denom() as f64;
+        (seconds * fps).round() as u64
+    }
+
+    /// Launch description of the source part, up to and including the caps
+    pub fn source_description(&self) -> String {
+        let mut source = format!(
+            "videotestsrc pattern={} animation-mode=frames is-live=false",
+            self.pattern
+        );
+        if let Some(frames) = self.frames {
+            source += &format!(" num-buffers={frames}");
+        }
+        if let Some(motion) = &self.motion {
+            source += &format!(" motion={motion}");
+        }
+        if self.flip {
+            source += " flip=true";
+        }
+        if let Some(speed) = self.horizontal_speed {
+            source += &format!(" horizontal-speed={speed}");
+        }
+        if let Some(color) = self.foreground {
+            source += &format!(" foreground-color={color}");
+        }
+        if let Some(color) = self.background {
+            source += &format!(" background-color={color}");
+        }
+
+        let caps = format!(
+            "video/x-raw,format={},width={},height={},framerate={}/{}",
+            self.format,
+            self.width,
+            self.height,
+            self.framerate.numer(),
+            self.framerate.denom()
+        );
+        if self.counter {
+            // rsoverlay draws on packed RGB, convert there and back to the requested format
+            format!(
+                "{source} ! video/x-raw,width={},height={},framerate={}/{} ! videoconvert \
+                 ! rsoverlay show-timecode=true show-position=true ! videoconvert ! {caps}",
+                self.width,
+                self.height,
+                self.framerate.numer(),
+                self.framerate.denom()
+            )
+        } else {
+            format!("{source} ! {caps}")
+        }
+    }
+}
+
+/// Where the frames go, picked by file extension
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatternOutput {
+    Display,
+    /// Plain frames one after the other, no header (`.raw`, `.yuv`, `.rgb`)
+    Raw(PathBuf),
+    /// YUV4MPEG2, raw frames with a header players understand (`.y4m`)
+    Y4m(PathBuf),
+    /// H.264 in Matroska or MP4, VP8 in WebM
+    Encoded(PathBuf),
+}
+
+impl PatternOutput {
+    pub fn from_path(path: PathBuf) -> PatternOutput {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("raw" | "yuv" | "rgb") => PatternOutput::Raw(path),
+            Some("y4m") => PatternOutput::Y4m(path),
+            _ => PatternOutput::Encoded(path),
+        }
+    }
+
+    fn sink_description(&self) -> Result<String, Error> {
+        let location = |path: &PathBuf| format!("filesink location=\"{}\"", path.display());
+        Ok(match self {
+            PatternOutput::Display => "videoconvert ! autovideosink".to_string(),
+            PatternOutput::Raw(path) => location(path),
+            PatternOutput::Y4m(path) => format!("y4menc ! {}", location(path)),
+            PatternOutput::Encoded(path) => {
+                let encoder = match path.extension().and_then(|e| e.to_str()) {
+                    Some("mkv") => "x264enc ! h264parse ! matroskamux",
+                    Some("mp4") => "x264enc ! h264parse ! mp4mux",
+                    Some("webm") => "vp8enc deadline=1 ! webmmux",
+                    _ => {
+                        return Err(glib::bool_error!(
+                            "Unknown output type {}, use raw, yuv, rgb, y4m, mkv, mp4 or webm",
+                            path.display()
+                        )
+                        .into())
+                    }
+                };
+                format!("videoconvert ! {encoder} ! {}", location(path))
+            }
+        })
+    }
+}
+
+/// Builds the generator pipeline for `options` writing to `output`
+pub fn build_pipeline(
+    options: &PatternOptions,
+    output: &PatternOutput,
+) -> Result<gst::Pipeline, Error> {
+    let description = format!(
+        "{} ! {}",
+        options.source_description(),
+        output.sink_description()?
+    );
+    Ok(gst::parse_launch(&description)?
+        .downcast::<gst::Pipeline>()
+        .unwrap())
+}
+
+fn parse_color(value: &str) -> Result<u32, Error> {
+    let hex = value.trim_start_matches("0x").trim_start_matches('#');
+    Ok(u32::from_str_radix(hex, 16)?)
+}
+
+/// Generates a test clip. Options are given as `--name=value`:
+/// `--pattern`, `--size=WxH`, `--framerate=N[/D]`, `--format`, `--frames`, `--duration`
+/// (seconds), `--motion`, `--flip`, `--speed`, `--foreground`, `--background`, `--counter`
+/// and `--output=FILE` (.raw/.yuv/.rgb, .y4m, .mkv, .mp4, .webm). Without output it is shown
+/// in a window.
+pub fn tutorial_main() -> Result<(), Error> {
+    // Initialize GStreamer
+    gst::init()?;
+    plugin_prac::register()?;
+
+    let mut options = PatternOptions::default();
+    let mut output = PatternOutput::Display;
+    let mut duration = None;
+    for arg in env::args().skip(1) {
+        let (name, value) = arg.split_once('=').unwrap_or((arg.as_str(), ""));
+        match name {
+            "--pattern" => options.pattern = value.to_string(),
+            "--size" => {
+                let (width, height) = value
+                    .split_once('x')
+                    .ok_or_else(|| glib::bool_error!("Size must be WIDTHxHEIGHT"))?;
+                options.width = width.parse()?;
+                options.height = height.parse()?;
+            }
+            "--framerate" => {
+                let (numer, denom) = value.split_once('/').unwrap_or((value, "1"));
+                options.framerate = gst::Fraction::new(numer.parse()?, denom.parse()?);
+            }
+            "--format" => options.format = value.to_string(),
+            "--frames" => options.frames = Some(value.parse()?),
+            "--duration" => duration = Some(value.parse::<f64>()?),
+            "--motion" => options.motion = Some(value.to_string()),
+            "--flip" => options.flip = true,
+            "--speed" => options.horizontal_speed = Some(value.parse()?),
+            "--foreground" => options.foreground = Some(parse_color(value)?),
+            "--background" => options.background = Some(parse_color(value)?),
+            "--counter" => options.counter = true,
+            "--output" => output = PatternOutput::from_path(PathBuf::from(value)),
+            _ => return Err(glib::bool_error!("Unknown option {arg}").into()),
+        }
+    }
+    // The duration is turned into frames so the clip has exactly that many
+    if let Some(duration) = duration {
+        options.frames = Some(options.frames_for(duration));
+    }
+    if options.frames.is_none() && output != PatternOutput::Display {
+        eprintln!("No --frames or --duration given, writing until interrupted");
+    }
+
+    let pipeline = build_pipeline(&options, &output)?;
+    println!("Generating {options:?}");
+
+    pipeline.set_state(gst::State::Playing)?;
+
+    // Wait until error or EOS
+    let bus = pipeline.bus().unwrap();
+    for msg in bus.iter_timed(gst::ClockTime::NONE) {
+        use gst::MessageView;
+
+        match msg.view() {
+            MessageView::Error(err) => {
+                eprintln!(
+                    "Error received from element {:?}: {}",
+                    err.src().map(|s| s.path_string()),
+                    err.error()
+                );
+                eprintln!("Debugging information: {:?}", err.debug());
+                break;
+            }
+            MessageView::Eos(..) => {
+                if let Some(position) = pipeline.query_position::<gst::ClockTime>() {
+                    println!("Done, {position} of video");
+                }
+                break;
+            }
+            _ => (),
+        }
+    }
+
+    pipeline.set_state(gst::State::Null)?;
+
+    Ok(())
+}
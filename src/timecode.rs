@@ -0,0 +1,272 @@
+use std::fmt;
+
+use gstreamer as gst;
+use gstreamer_audio as gst_audio;
+use gstreamer_video as gst_video;
+
+use gst::prelude::*;
+
+/// SMPTE timecode. Drop-frame timecode (29.97 and 59.94 fps) skips frame numbers 0 and 1
+/// (0 to 3 at 59.94) at the start of every minute except each tenth, so it stays in step
+/// with the wall clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timecode {
+    pub hours: u64,
+    pub minutes: u64,
+    pub seconds: u64,
+    pub frames: u64,
+    pub drop_frame: bool,
+}
+
+impl Timecode {
+    /// Timecode of frame `frame` (counted from 0) at `fps`
+    pub fn from_frame(frame: u64, fps: gst::Fraction) -> Option<Timecode> {
+        if fps.numer() <= 0 || fps.denom() <= 0 {
+            return None;
+        }
+        let nominal = (f64::from(fps.numer()) / f64::from(fps.denom())).round() as u64;
+        if nominal == 0 {
+            return None;
+        }
+
+        let drop_frame = fps.denom() == 1001 && nominal % 30 == 0;
+        let mut frame = frame;
+        if drop_frame {
+            let dropped = nominal / 15;
+            let per_minute = nominal * 60 - dropped;
+            let per_ten_minutes = per_minute * 10 + dropped;
+
+            let tens = frame / per_ten_minutes;
+            let rest = frame % per_ten_minutes;
+            // The first minute of every ten keeps all its frame numbers
+            frame += dropped * 9 * tens;
+            if rest > dropped {
+                frame += dropped * ((rest - dropped) / per_minute);
+            }
+        }
+
+        Some(Timecode {
+            hours: frame / (nominal * 3600),
+            minutes: frame / (nominal * 60) % 60,
+            seconds: frame / nominal % 60,
+            frames: frame % nominal,
+            drop_frame,
+        })
+    }
+}
+
+impl fmt::Display for Timecode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // A semicolon before the frames marks drop-frame timecode
+        let separator = if self.drop_frame { ';' } else { ':' };
+        write!(
+            f,
+            "{:02}:{:02}:{:02}{separator}{:02}",
+            self.hours, self.minutes, self.seconds, self.frames
+        )
+    }
+}
+
+/// Frame shown at `position`. Rounds to the nearest frame because timestamps of fractional
+/// framerates are truncated to whole nanoseconds.
+pub fn frame_at(position: gst::ClockTime, fps: gst::Fraction) -> Option<u64> {
+    if fps.numer() <= 0 || fps.denom() <= 0 {
+        return None;
+    }
+    let scale = u128::from(gst::ClockTime::SECOND.nseconds()) * fps.denom() as u128;
+    let frame = (u128::from(position.nseconds()) * fps.numer() as u128 + scale / 2) / scale;
+    Some(frame as u64)
+}
+
+/// Audio frames (samples per channel) played by `position`
+pub fn sample_at(position: gst::ClockTime, rate: u32) -> u64 {
+    position
+        .nseconds()
+        .mul_div_floor(u64::from(rate), gst::ClockTime::SECOND.nseconds())
+        .unwrap()
+}
+
+/// Framerate and sample rate negotiated on the current video and audio streams of a playbin
+pub fn playbin_rates(playbin: &gst::Element) -> (Option<gst::Fraction>, Option<u32>) {
+    let current_caps = |signal: &str, property: &str| {
+        let current = playbin.property::<i32>(property);
+        playbin
+            .emit_by_name::<Option<gst::Pad>>(signal, &[&current])
+            .and_then(|pad| pad.current_caps())
+    };
+
+    let fps = current_caps("get-video-pad", "current-video")
+        .and_then(|caps| gst_video::VideoInfo::from_caps(&caps).ok())
+        .map(|info| info.fps())
+        .filter(|fps| fps.numer() > 0);
+    let rate = current_caps("get-audio-pad", "current-audio")
+        .and_then(|caps| gst_audio::AudioInfo::from_caps(&caps).ok())
+        .map(|info| info.rate());
+
+    (fps, rate)
+}
+
+/// Position of a pipeline in every format we can work out
+#[derive(Debug, Clone)]
+pub struct PositionReport {
+    pub position: gst::ClockTime,
+    pub duration: Option<gst::ClockTime>,
+    pub timecode: Option<Timecode>,
+    /// Frame number derived from the negotiated framerate
+    pub frame: Option<u64>,
+    /// Sample count derived from the negotiated audio rate
+    pub samples: Option<u64>,
+    /// Answer to a position query in `gst::Format::Default`, frames or samples depending on
+    /// which element answers, when the pipeline supports it
+    pub default: Option<i64>,
+    /// Answer to a position query in `gst::Format::Buffers`, when the pipeline supports it
+    pub buffers: Option<i64>,
+}
+
+impl PositionReport {
+    /// Queries `element` for its position. `fps` and `rate` come from the negotiated caps,
+    /// see `playbin_rates`.
+    pub fn query(
+        element: &gst::Element,
+        fps: Option<gst::Fraction>,
+        rate: Option<u32>,
+    ) -> Option<PositionReport> {
+        let position = element.query_position::<gst::ClockTime>()?;
+        let frame = fps.and_then(|fps| frame_at(position, fps));
+
+        Some(PositionReport {
+            position,
+            duration: element.query_duration::<gst::ClockTime>(),
+            timecode: fps
+                .zip(frame)
+                .and_then(|(fps, frame)| Timecode::from_frame(frame, fps)),
+            frame,
+            samples: rate.map(|rate| sample_at(position, rate)),
+            default: element
+                .query_position_generic(gst::Format::Default)
+                .map(|value| value.value()),
+            buffers: element
+                .query_position_generic(gst::Format::Buffers)
+                .map(|value| value.value()),
+        })
+    }
+}
+
+impl fmt::Display for PositionReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Position {} / {}",
+            self.position,
+            self.duration.display()
+        )?;
+        if let Some(timecode) = self.timecode {
+            write!(f, " | TC {timecode}")?;
+        }
+        if let Some(frame) = self.frame {
+            write!(f, " | frame {frame}")?;
+        }
+        if let Some(samples) = self.samples {
+            write!(f, " | sample {samples}")?;
+        }
+        if let Some(default) = self.default {
+            write!(f, " | default {default}")?;
+        }
+        if let Some(buffers) = self.buffers {
+            write!(f, " | buffers {buffers}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timecode(frame: u64, numer: i32, denom: i32) -> String {
+        Timecode::from_frame(frame, gst::Fraction::new(numer, denom))
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn drop_frame_29_97() {
+        assert_eq!(timecode(0, 30000, 1001), "00:00:00;00");
+        assert_eq!(timecode(1799, 30000, 1001), "00:00:59;29");
+        // Frame numbers 0 and 1 of the second minute don't exist
+        assert_eq!(timecode(1800, 30000, 1001), "00:01:00;02");
+        assert_eq!(timecode(17981, 30000, 1001), "00:09:59;29");
+        // Every tenth minute keeps them
+        assert_eq!(timecode(17982, 30000, 1001), "00:10:00;00");
+        assert_eq!(timecode(17982 * 6, 30000, 1001), "01:00:00;00");
+    }
+
+    #[test]
+    fn drop_frame_59_94() {
+        assert_eq!(timecode(3599, 60000, 1001), "00:00:59;59");
+        assert_eq!(timecode(3600, 60000, 1001), "00:01:00;04");
+        assert_eq!(timecode(35963, 60000, 1001), "00:09:59;59");
+        assert_eq!(timecode(35964, 60000, 1001), "00:10:00;00");
+    }
+
+    #[test]
+    fn non_drop_frame() {
+        assert_eq!(timecode(90_000, 25, 1), "01:00:00:00");
+        assert_eq!(timecode(1799, 30, 1), "00:00:59:29");
+        assert_eq!(timecode(1800, 30, 1), "00:01:00:00");
+        // 23.976 counts 24 frame numbers per second without dropping any
+        assert_eq!(timecode(1440, 24000, 1001), "00:01:00:00");
+        assert_eq!(
+            Timecode::from_frame(1, gst::Fraction::new(50, 1)),
+            Some(Timecode {
+                hours: 0,
+                minutes: 0,
+                seconds: 0,
+                frames: 1,
+                drop_frame: false,
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_framerates() {
+        assert_eq!(Timecode::from_frame(1, gst::Fraction::new(0, 1)), None);
+        assert_eq!(Timecode::from_frame(1, gst::Fraction::new(-25, 1)), None);
+        assert_eq!(
+            frame_at(gst::ClockTime::SECOND, gst::Fraction::new(0, 1)),
+            None
+        );
+    }
+
+    #[test]
+    fn frames_at_positions() {
+        let ntsc = gst::Fraction::new(30000, 1001);
+        assert_eq!(frame_at(gst::ClockTime::ZERO, ntsc), Some(0));
+        // Frame 1 starts at 33366666.67 ns, the timestamp is truncated
+        assert_eq!(
+            frame_at(gst::ClockTime::from_nseconds(33_366_666), ntsc),
+            Some(1)
+        );
+        assert_eq!(
+            frame_at(gst::ClockTime::from_nseconds(60_060_000_000), ntsc),
+            Some(1800)
+        );
+        assert_eq!(
+            frame_at(gst::ClockTime::from_seconds(1), gst::Fraction::new(25, 1)),
+            Some(25)
+        );
+    }
+
+    #[test]
+    fn samples_at_positions() {
+        assert_eq!(sample_at(gst::ClockTime::ZERO, 48_000), 0);
+        assert_eq!(sample_at(gst::ClockTime::from_seconds(1), 48_000), 48_000);
+        assert_eq!(
+            sample_at(gst::ClockTime::from_mseconds(500), 44_100),
+            22_050
+        );
+        // Partial samples are not played yet
+        assert_eq!(sample_at(gst::ClockTime::from_nseconds(22_675), 44_100), 0);
+        assert_eq!(sample_at(gst::ClockTime::from_nseconds(22_676), 44_100), 1);
+    }
+}
@@ -0,0 +1,287 @@
+use std::{env, time::Instant};
+
+use gstreamer as gst;
+use gstreamer_app as gst_app;
+use gstreamer_check as gst_check;
+use gstreamer_video as gst_video;
+
+use anyhow::Error;
+use gst::prelude::*;
+
+use crate::basic_tutorial_6::print_caps;
+
+/// Formats tried when none are given: planar and semi-planar YUV, packed YUV, RGB, gray and
+/// a few high bit depth ones
+const DEFAULT_FORMATS: [gst_video::VideoFormat; 16] = [
+    gst_video::VideoFormat::I420,
+    gst_video::VideoFormat::Yv12,
+    gst_video::VideoFormat::Nv12,
+    gst_video::VideoFormat::Nv21,
+    gst_video::VideoFormat::Y42b,
+    gst_video::VideoFormat::Y444,
+    gst_video::VideoFormat::Yuy2,
+    gst_video::VideoFormat::Uyvy,
+    gst_video::VideoFormat::Ayuv,
+    gst_video::VideoFormat::Rgb,
+    gst_video::VideoFormat::Bgr,
+    gst_video::VideoFormat::Rgba,
+    gst_video::VideoFormat::Bgrx,
+    gst_video::VideoFormat::Gray8,
+    gst_video::VideoFormat::I42010le,
+    gst_video::VideoFormat::P01010le,
+];
+const DEFAULT_FRAMES: usize = 30;
+
+/// Outcome of one input format, output format and size combination
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Conversion {
+    /// Converted and every output frame looked right, in frames per second
+    Supported { fps: f64 },
+    /// Caps could not be negotiated or the conversion failed
+    Unsupported,
+    /// Frames came out but their planes or strides were wrong
+    Invalid,
+}
+
+/// A frame in `info`'s format and size, rendered by `videotestsrc`
+fn test_frame(info: &gst_video::VideoInfo) -> Result<gst::Buffer, Error> {
+    let pipeline =
+        gst::parse_launch("videotestsrc pattern=smpte num-buffers=1 ! appsink name=sink")?
+            .downcast::<gst::Pipeline>()
+            .unwrap();
+    let appsink = pipeline
+        .by_name("sink")
+        .unwrap()
+        .downcast::<gst_app::AppSink>()
+        .unwrap();
+    appsink.set_caps(Some(&info.to_caps()?));
+
+    pipeline.set_state(gst::State::Playing)?;
+    let sample = appsink.pull_sample();
+    pipeline.set_state(gst::State::Null)?;
+
+    let sample = sample.map_err(|_| glib::bool_error!("videotestsrc can't produce {info:?}"))?;
+    Ok(sample.buffer_owned().unwrap())
+}
+
+/// Checks that `buffer` holds a frame laid out as `info` says: every plane is there, strides
+/// fit a row and the planes hold all rows. Also rejects frames of a single byte value, the
+/// SMPTE bars never convert to that.
+pub fn validate_frame(buffer: &gst::BufferRef, info: &gst_video::VideoInfo) -> Result<(), String> {
+    let frame = gst_video::VideoFrameRef::from_buffer_ref_readable(buffer, info)
+        .map_err(|_| "buffer does not fit the video info".to_string())?;
+    let format_info = info.format_info();
+
+    if frame.n_planes() != info.n_planes() {
+        return Err(format!(
+            "{} planes instead of {}",
+            frame.n_planes(),
+            info.n_planes()
+        ));
+    }
+    let mut distinct = false;
+    for plane in 0..frame.n_planes() {
+        let stride = frame.plane_stride()[plane as usize];
+        if stride <= 0 {
+            return Err(format!("plane {plane} has stride {stride}"));
+        }
+        // The first component stored in this plane decides how many rows it has
+        let Some(component) =
+            (0..format_info.n_components()).find(|&c| format_info.plane()[c as usize] == plane)
+        else {
+            return Err(format!("no component in plane {plane}"));
+        };
+        let rows = format_info.scale_height(component as u8, info.height());
+        let data = frame
+            .plane_data(plane)
+            .map_err(|_| format!("plane {plane} can't be mapped"))?;
+        let needed = stride as usize * rows as usize;
+        if data.len() < needed {
+            return Err(format!(
+                "plane {plane} has {} bytes, {rows} rows of {stride} need {needed}",
+                data.len()
+            ));
+        }
+        distinct |= data.iter().any(|&b| b != data[0]);
+    }
+    if !distinct {
+        return Err("every byte of the frame is the same".to_string());
+    }
+    Ok(())
+}
+
+/// Pushes `frames` copies of `frame`, laid out as `input`, through
+/// `videoconvert ! videoscale` into `output`. Only the conversion is timed, the output is
+/// validated afterwards.
+pub fn convert(
+    frame: &gst::Buffer,
+    input: &gst_video::VideoInfo,
+    output: &gst_video::VideoInfo,
+    frames: usize,
+    verbose: bool,
+) -> Conversion {
+    let (Ok(input_caps), Ok(output_caps)) = (input.to_caps(), output.to_caps()) else {
+        return Conversion::Unsupported;
+    };
+
+    let mut h = gst_check::Harness::new_parse("videoconvert ! videoscale");
+    h.set_caps(input_caps, output_caps);
+    let frame_duration = gst::ClockTime::SECOND
+        .mul_div_floor(input.fps().denom() as u64, input.fps().numer() as u64);
+
+    let mut converted = Vec::with_capacity(frames);
+    let start = Instant::now();
+    for i in 0..frames {
+        let mut buffer = frame.copy();
+        {
+            let buffer = buffer.make_mut();
+            if let Some(duration) = frame_duration {
+                buffer.set_pts(duration * i as u64);
+                buffer.set_duration(duration);
+            }
+        }
+        if h.push(buffer).is_err() {
+            return Conversion::Unsupported;
+        }
+        // Both elements work in the pushing thread, the output is there already
+        let Some(buffer) = h.try_pull() else {
+            return Conversion::Unsupported;
+        };
+        converted.push(buffer);
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+
+    for buffer in &converted {
+        if let Err(err) = validate_frame(buffer, output) {
+            if verbose {
+                println!("  {:?} -> {:?}: {err}", input.format(), output.format());
+            }
+            return Conversion::Invalid;
+        }
+    }
+
+    if verbose {
+        println!("  {:?} -> {:?}", input.format(), output.format());
+        if let Some(caps) = h.srcpad().and_then(|pad| pad.current_caps()) {
+            print_caps(&caps, "    in  ");
+        }
+        if let Some(caps) = h.sinkpad().and_then(|pad| pad.current_caps()) {
+            print_caps(&caps, "    out ");
+        }
+    }
+
+    Conversion::Supported {
+        fps: frames as f64 / elapsed.max(f64::EPSILON),
+    }
+}
+
+fn parse_size(value: &str) -> Result<(u32, u32), Error> {
+    let (width, height) = value
+        .split_once('x')
+        .ok_or_else(|| glib::bool_error!("Size must be WIDTHxHEIGHT"))?;
+    Ok((width.parse()?, height.parse()?))
+}
+
+fn print_matrix(
+    formats: &[gst_video::VideoFormat],
+    results: &[Vec<Conversion>],
+    (in_width, in_height): (u32, u32),
+    (out_width, out_height): (u32, u32),
+) {
+    println!("\n{in_width}x{in_height} -> {out_width}x{out_height}, frames per second (- unsupported, ! invalid output)");
+    print!("{:<12}", "in \\ out");
+    for format in formats {
+        print!("{:>11}", format.to_str());
+    }
+    println!();
+
+    for (input, row) in formats.iter().zip(results) {
+        print!("{:<12}", input.to_str());
+        for result in row {
+            match result {
+                Conversion::Supported { fps } => print!("{fps:>11.0}"),
+                Conversion::Unsupported => print!("{:>11}", "-"),
+                Conversion::Invalid => print!("{:>11}", "!"),
+            }
+        }
+        println!();
+    }
+}
+
+/// Converts between every pair of formats for every size pair and prints a matrix per size
+/// pair. `--formats=I420,NV12,...`, `--sizes=640x360:1280x720,...` (input:output),
+/// `--frames=N` per conversion and `--verbose` for the negotiated caps and validation errors.
+pub fn tutorial_main() -> Result<(), Error> {
+    // Initialize GStreamer
+    gst::init()?;
+
+    let mut formats = DEFAULT_FORMATS.to_vec();
+    let mut sizes = vec![((640, 360), (640, 360)), ((640, 360), (1280, 720))];
+    let mut frames = DEFAULT_FRAMES;
+    let mut verbose = false;
+    for arg in env::args().skip(1) {
+        let (name, value) = arg.split_once('=').unwrap_or((arg.as_str(), ""));
+        match name {
+            "--formats" => {
+                formats = value
+                    .split(',')
+                    .map(|name| match gst_video::VideoFormat::from_string(name) {
+                        gst_video::VideoFormat::Unknown => {
+                            Err(glib::bool_error!("Unknown video format {name}"))
+                        }
+                        format => Ok(format),
+                    })
+                    .collect::<Result<_, _>>()?;
+            }
+            "--sizes" => {
+                sizes = value
+                    .split(',')
+                    .map(|pair| {
+                        let (input, output) = pair.split_once(':').unwrap_or((pair, pair));
+                        Ok((parse_size(input)?, parse_size(output)?))
+                    })
+                    .collect::<Result<_, Error>>()?;
+            }
+            "--frames" => frames = value.parse()?,
+            "--verbose" => verbose = true,
+            _ => return Err(glib::bool_error!("Unknown option {arg}").into()),
+        }
+    }
+
+    let mut supported = 0;
+    let mut total = 0;
+    for (input_size, output_size) in sizes {
+        let mut results = Vec::new();
+        for &input_format in &formats {
+            let input = gst_video::VideoInfo::builder(input_format, input_size.0, input_size.1)
+                .fps(gst::Fraction::new(30, 1))
+                .build()?;
+            // Rendered once, every output format converts the same frame
+            let frame = test_frame(&input).ok();
+            let row: Vec<Conversion> = formats
+                .iter()
+                .map(|&output_format| {
+                    let Some(frame) = &frame else {
+                        return Conversion::Unsupported;
+                    };
+                    gst_video::VideoInfo::builder(output_format, output_size.0, output_size.1)
+                        .fps(gst::Fraction::new(30, 1))
+                        .build()
+                        .map_or(Conversion::Unsupported, |output| {
+                            convert(frame, &input, &output, frames, verbose)
+                        })
+                })
+                .collect();
+            supported += row
+                .iter()
+                .filter(|result| matches!(result, Conversion::Supported { .. }))
+                .count();
+            total += row.len();
+            results.push(row);
+        }
+        print_matrix(&formats, &results, input_size, output_size);
+    }
+    println!("\n{supported} of {total} conversions supported");
+
+    Ok(())
+}